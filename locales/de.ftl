@@ -0,0 +1,98 @@
+# Messages shown to subscribers, in German.
+
+## Confirmation email
+
+confirmation-email-subject = Willkommen!
+confirmation-email-greeting = Willkommen bei unserem Newsletter!
+confirmation-email-link = Klicken Sie hier, um Ihr Abonnement zu bestätigen.
+confirmation-email-text-link = Besuchen Sie { $link }, um Ihr Abonnement zu bestätigen.
+
+## Footer of every email sent to a subscriber
+
+email-footer-text = Name, Listen oder E-Mail-Adresse ändern oder den Versand pausieren: { $link }
+email-footer-link = Abonnement verwalten
+
+## Email change email
+
+email-change-subject = Bestätigen Sie Ihre neue E-Mail-Adresse
+email-change-link = Klicken Sie hier, um Ihre neue E-Mail-Adresse zu bestätigen.
+email-change-text-link = Besuchen Sie { $link }, um Ihre neue E-Mail-Adresse zu bestätigen.
+
+## Weekly digest
+
+digest-subject = Ihre wöchentliche Zusammenfassung
+
+## Signup form
+
+signup-title = Abonnieren Sie unseren Newsletter
+signup-intro = Eine E-Mail, sobald wir etwas Neues veröffentlichen. Kein Spam, jederzeit abbestellbar.
+signup-name = Name
+signup-email = E-Mail
+signup-submit = Abonnieren
+
+## Pages shown after subscribing and confirming
+
+check-inbox-title = Sehen Sie in Ihrem Posteingang nach
+check-inbox-body = Wir haben einen Bestätigungslink an { $email } gesendet. Klicken Sie darauf, um den Newsletter zu erhalten.
+check-inbox-spam = Nichts angekommen? Sehen Sie in Ihrem Spam-Ordner nach.
+confirmed-title = Abonnement bestätigt
+confirmed-heading = Willkommen an Bord, { $name }!
+confirmed-body = Ihr Abonnement ist bestätigt. Die nächste Ausgabe landet in Ihrem Posteingang.
+already-confirmed-title = Bereits bestätigt
+already-confirmed-body = Ihr Abonnement wurde bereits bestätigt, { $name }. Es gibt nichts weiter zu tun.
+expired-link-title = Dieser Link ist abgelaufen
+expired-link-body = Bestätigungslinks sind nur wenige Tage gültig. Abonnieren Sie erneut, um einen neuen zu erhalten.
+invalid-link-title = Wir kennen diesen Link nicht
+invalid-link-body = Prüfen Sie, ob Sie den ganzen Link aus der E-Mail kopiert haben, oder abonnieren Sie erneut.
+subscribe-again = Erneut abonnieren
+
+## Preference center
+
+preferences-title = Ihr Abonnement
+preferences-saved = Ihre Änderungen wurden gespeichert.
+preferences-email-sent = Wir haben einen Bestätigungslink an Ihre neue E-Mail-Adresse geschickt. Sie wird verwendet, sobald Sie ihn anklicken.
+preferences-name-heading = Name
+preferences-name-label = Wie sollen wir Sie nennen?
+preferences-save = Speichern
+preferences-lists-heading = Listen
+preferences-pause-heading = Versand
+preferences-active = Sie erhalten jede Ausgabe.
+preferences-paused-until = Der Versand ist bis { $date } pausiert.
+preferences-pause-label = Versand pausieren für
+preferences-pause-none = Nicht pausieren
+preferences-pause-1 = 1 Woche
+preferences-pause-2 = 2 Wochen
+preferences-pause-4 = 4 Wochen
+preferences-pause-12 = 12 Wochen
+preferences-frequency-heading = Häufigkeit
+preferences-frequency-label = Ausgaben senden
+preferences-frequency-immediate = Sobald sie erscheinen
+preferences-frequency-weekly = Gesammelt in einer wöchentlichen Zusammenfassung
+preferences-email-heading = E-Mail-Adresse
+preferences-email-current = Ausgaben werden an { $email } geschickt.
+preferences-email-label = Neue E-Mail-Adresse
+preferences-email-submit = E-Mail-Adresse ändern
+email-changed-title = E-Mail-Adresse geändert
+email-changed-body = Ab jetzt werden Ausgaben an { $email } geschickt.
+preferences-data-heading = Ihre Daten
+preferences-data-download = Alles herunterladen, was wir über Sie gespeichert haben
+preferences-erase-warning = Wenn Sie Ihre Daten löschen, endet Ihr Abonnement sofort. Das kann nicht rückgängig gemacht werden.
+preferences-erase-submit = Meine Daten löschen
+erased-title = Ihre Daten wurden gelöscht
+erased-body = Wir haben Ihr Abonnement und alles, was wir über Sie gespeichert hatten, gelöscht. Sie hören nicht mehr von uns.
+
+## Error pages, by error code
+
+error-request-id = Anfrage-ID: { $request_id }
+error-generic = Bei uns ist etwas schiefgelaufen. Bitte versuchen Sie es später erneut.
+error-not_found = Seite nicht gefunden
+error-invalid_request = Ungültige Anfrage
+error-validation_failed = Bitte prüfen Sie Ihre Eingaben
+error-challenge_failed = Wir konnten nicht bestätigen, dass Sie ein Mensch sind
+error-too_many_requests = Zu viele Versuche
+error-overloaded = Wir sind gerade sehr ausgelastet
+error-timed_out = Das dauert zu lange
+error-payload_too_large = Ihre Eingabe ist zu groß
+error-cross_site_request = Anfrage blockiert
+error-internal_error = Etwas ist schiefgelaufen
+error-email_taken = Diese E-Mail-Adresse ist bereits angemeldet
@@ -0,0 +1,99 @@
+# Messages shown to subscribers, in English. Every other catalog must provide the same
+# messages, with the same variables.
+
+## Confirmation email
+
+confirmation-email-subject = Welcome!
+confirmation-email-greeting = Welcome to our newsletter!
+confirmation-email-link = Click here to confirm your subscription.
+confirmation-email-text-link = Visit { $link } to confirm your subscription.
+
+## Footer of every email sent to a subscriber
+
+email-footer-text = Change your name, lists or email address, or pause delivery: { $link }
+email-footer-link = Manage your subscription
+
+## Email change email
+
+email-change-subject = Confirm your new email address
+email-change-link = Click here to confirm your new email address.
+email-change-text-link = Visit { $link } to confirm your new email address.
+
+## Weekly digest
+
+digest-subject = Your weekly digest
+
+## Signup form
+
+signup-title = Subscribe to our newsletter
+signup-intro = One email whenever we publish something new. No spam, unsubscribe at any time.
+signup-name = Name
+signup-email = Email
+signup-submit = Subscribe
+
+## Pages shown after subscribing and confirming
+
+check-inbox-title = Check your inbox
+check-inbox-body = We sent a confirmation link to { $email }. Click it to start receiving the newsletter.
+check-inbox-spam = Nothing there? Have a look in your spam folder.
+confirmed-title = Subscription confirmed
+confirmed-heading = You're in, { $name }!
+confirmed-body = Your subscription is confirmed. The next issue will land in your inbox.
+already-confirmed-title = Already confirmed
+already-confirmed-body = Your subscription was confirmed earlier, { $name }. There is nothing more to do.
+expired-link-title = This link has expired
+expired-link-body = Confirmation links are only valid for a few days. Subscribe again to get a new one.
+invalid-link-title = We don't recognise this link
+invalid-link-body = Make sure you copied the whole link from the email, or subscribe again.
+subscribe-again = Subscribe again
+
+## Preference center
+
+preferences-title = Your subscription
+preferences-saved = Your changes are saved.
+preferences-email-sent = We sent a confirmation link to your new email address. It will be used once you click it.
+preferences-name-heading = Name
+preferences-name-label = How should we call you?
+preferences-save = Save
+preferences-lists-heading = Lists
+preferences-pause-heading = Delivery
+preferences-active = You are receiving every issue.
+preferences-paused-until = Delivery is paused until { $date }.
+preferences-pause-label = Pause delivery for
+preferences-pause-none = Do not pause
+preferences-pause-1 = 1 week
+preferences-pause-2 = 2 weeks
+preferences-pause-4 = 4 weeks
+preferences-pause-12 = 12 weeks
+preferences-frequency-heading = Frequency
+preferences-frequency-label = Send me issues
+preferences-frequency-immediate = As soon as they are published
+preferences-frequency-weekly = Bundled in a weekly digest
+preferences-email-heading = Email address
+preferences-email-current = Issues are sent to { $email }.
+preferences-email-label = New email address
+preferences-email-submit = Change email address
+email-changed-title = Email address changed
+email-changed-body = From now on, issues will be sent to { $email }.
+preferences-data-heading = Your data
+preferences-data-download = Download everything we hold about you
+preferences-erase-warning = Deleting your data ends your subscription at once. It cannot be undone.
+preferences-erase-submit = Delete my data
+erased-title = Your data is deleted
+erased-body = We deleted your subscription and everything we held about you. You will not hear from us again.
+
+## Error pages, by error code
+
+error-request-id = Request id: { $request_id }
+error-generic = Something went wrong on our side. Please try again later.
+error-not_found = Page not found
+error-invalid_request = Invalid request
+error-validation_failed = Please check what you entered
+error-challenge_failed = We could not verify that you are human
+error-too_many_requests = Too many attempts
+error-overloaded = We are very busy right now
+error-timed_out = This is taking too long
+error-payload_too_large = What you sent is too large
+error-cross_site_request = Request blocked
+error-internal_error = Something went wrong
+error-email_taken = This email address is already subscribed
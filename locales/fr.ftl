@@ -0,0 +1,98 @@
+# Messages shown to subscribers, in French.
+
+## Confirmation email
+
+confirmation-email-subject = Bienvenue !
+confirmation-email-greeting = Bienvenue dans notre newsletter !
+confirmation-email-link = Cliquez ici pour confirmer votre inscription.
+confirmation-email-text-link = Rendez-vous sur { $link } pour confirmer votre inscription.
+
+## Footer of every email sent to a subscriber
+
+email-footer-text = Changez votre nom, vos listes ou votre adresse e-mail, ou suspendez l'envoi : { $link }
+email-footer-link = Gérer votre abonnement
+
+## Email change email
+
+email-change-subject = Confirmez votre nouvelle adresse e-mail
+email-change-link = Cliquez ici pour confirmer votre nouvelle adresse e-mail.
+email-change-text-link = Rendez-vous sur { $link } pour confirmer votre nouvelle adresse e-mail.
+
+## Weekly digest
+
+digest-subject = Votre récapitulatif de la semaine
+
+## Signup form
+
+signup-title = Abonnez-vous à notre newsletter
+signup-intro = Un email à chaque nouvelle publication. Pas de spam, désinscription à tout moment.
+signup-name = Nom
+signup-email = Email
+signup-submit = S'abonner
+
+## Pages shown after subscribing and confirming
+
+check-inbox-title = Consultez votre boîte de réception
+check-inbox-body = Nous avons envoyé un lien de confirmation à { $email }. Cliquez dessus pour commencer à recevoir la newsletter.
+check-inbox-spam = Rien reçu ? Jetez un œil à vos spams.
+confirmed-title = Inscription confirmée
+confirmed-heading = Bienvenue, { $name } !
+confirmed-body = Votre inscription est confirmée. Le prochain numéro arrivera dans votre boîte de réception.
+already-confirmed-title = Déjà confirmée
+already-confirmed-body = Votre inscription a déjà été confirmée, { $name }. Il n'y a rien d'autre à faire.
+expired-link-title = Ce lien a expiré
+expired-link-body = Les liens de confirmation ne sont valables que quelques jours. Abonnez-vous à nouveau pour en recevoir un nouveau.
+invalid-link-title = Nous ne reconnaissons pas ce lien
+invalid-link-body = Vérifiez que vous avez copié le lien en entier depuis l'email, ou abonnez-vous à nouveau.
+subscribe-again = S'abonner à nouveau
+
+## Preference center
+
+preferences-title = Votre abonnement
+preferences-saved = Vos modifications sont enregistrées.
+preferences-email-sent = Nous avons envoyé un lien de confirmation à votre nouvelle adresse e-mail. Elle sera utilisée dès que vous aurez cliqué dessus.
+preferences-name-heading = Nom
+preferences-name-label = Comment devons-nous vous appeler ?
+preferences-save = Enregistrer
+preferences-lists-heading = Listes
+preferences-pause-heading = Envoi
+preferences-active = Vous recevez chaque numéro.
+preferences-paused-until = L'envoi est suspendu jusqu'au { $date }.
+preferences-pause-label = Suspendre l'envoi pendant
+preferences-pause-none = Ne pas suspendre
+preferences-pause-1 = 1 semaine
+preferences-pause-2 = 2 semaines
+preferences-pause-4 = 4 semaines
+preferences-pause-12 = 12 semaines
+preferences-frequency-heading = Fréquence
+preferences-frequency-label = Recevoir les numéros
+preferences-frequency-immediate = Dès leur parution
+preferences-frequency-weekly = Regroupés dans un récapitulatif hebdomadaire
+preferences-email-heading = Adresse e-mail
+preferences-email-current = Les numéros sont envoyés à { $email }.
+preferences-email-label = Nouvelle adresse e-mail
+preferences-email-submit = Changer d'adresse e-mail
+email-changed-title = Adresse e-mail modifiée
+email-changed-body = Désormais, les numéros seront envoyés à { $email }.
+preferences-data-heading = Vos données
+preferences-data-download = Télécharger tout ce que nous conservons à votre sujet
+preferences-erase-warning = Supprimer vos données met fin immédiatement à votre abonnement. C'est irréversible.
+preferences-erase-submit = Supprimer mes données
+erased-title = Vos données sont supprimées
+erased-body = Nous avons supprimé votre abonnement et tout ce que nous conservions à votre sujet. Vous ne recevrez plus rien de notre part.
+
+## Error pages, by error code
+
+error-request-id = Identifiant de la requête : { $request_id }
+error-generic = Une erreur s'est produite de notre côté. Veuillez réessayer plus tard.
+error-not_found = Page introuvable
+error-invalid_request = Requête invalide
+error-validation_failed = Veuillez vérifier votre saisie
+error-challenge_failed = Nous n'avons pas pu vérifier que vous êtes humain
+error-too_many_requests = Trop de tentatives
+error-overloaded = Nous sommes très sollicités en ce moment
+error-timed_out = Cela prend trop de temps
+error-payload_too_large = Votre envoi est trop volumineux
+error-cross_site_request = Requête bloquée
+error-internal_error = Une erreur s'est produite
+error-email_taken = Cette adresse e-mail est déjà abonnée
@@ -0,0 +1,6 @@
+-- Token buckets shared by every replica when `rate_limit.store` is `postgres`.
+CREATE TABLE rate_limit_buckets(
+  key TEXT PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at timestamptz NOT NULL
+);
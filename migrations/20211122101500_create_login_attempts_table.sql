@@ -0,0 +1,7 @@
+-- Failed login attempts per username ("username:<name>") and per client IP ("ip:<addr>").
+CREATE TABLE login_attempts(
+  key TEXT PRIMARY KEY,
+  failed_attempts INTEGER NOT NULL,
+  last_failed_at timestamptz NOT NULL,
+  locked_until timestamptz NULL
+);
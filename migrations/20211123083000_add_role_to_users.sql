@@ -0,0 +1,17 @@
+-- Existing users could publish to the whole list, so they keep full access.
+BEGIN;
+  ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin'
+    CHECK (role IN ('admin', 'editor', 'viewer'));
+  -- New users get the least privileged role unless told otherwise.
+  ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+COMMIT;
+
+-- Issues written by editors, sent when someone with the publish permission publishes them.
+CREATE TABLE newsletter_drafts (
+    draft_id uuid PRIMARY KEY,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    created_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Optional TOTP second factor (RFC 6238) per user, plus single-use recovery codes.
+CREATE TABLE user_two_factor(
+    user_id uuid PRIMARY KEY REFERENCES users (user_id) ON DELETE CASCADE,
+    -- AES-256-GCM encrypted TOTP secret, base64(nonce || ciphertext || tag).
+    encrypted_secret TEXT NOT NULL,
+    -- NULL until the user confirms enrolment with a valid code.
+    enabled_at timestamptz NULL,
+    -- Last TOTP time step accepted, to reject replayed codes.
+    last_used_step BIGINT NULL
+);
+CREATE TABLE two_factor_recovery_codes(
+    user_id uuid NOT NULL REFERENCES user_two_factor (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,24 @@
+-- Append-only record of administrative actions.
+-- actor_user_id has no foreign key on purpose: events must outlive the users they mention.
+CREATE TABLE audit_events(
+    event_id uuid PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    actor_user_id uuid NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NULL,
+    request_id uuid NULL
+);
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+CREATE INDEX audit_events_actor_idx ON audit_events (actor);
+CREATE INDEX audit_events_action_idx ON audit_events (action);
+
+CREATE FUNCTION reject_audit_event_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_event_changes();
@@ -0,0 +1,2 @@
+-- Lets confirmation links expire. Tokens issued before this migration count as new.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
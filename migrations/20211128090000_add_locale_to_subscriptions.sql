@@ -0,0 +1,2 @@
+-- Language of the emails and pages a subscriber gets.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,39 @@
+-- Self-service preferences: mailing lists, digests, pausing delivery and changing email address.
+-- Tokens of existing subscribers are created when they are first needed.
+ALTER TABLE subscriptions ADD COLUMN preferences_token TEXT NULL UNIQUE;
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
+-- 'immediate', or 'weekly' to get the issues bundled in a weekly digest.
+ALTER TABLE subscriptions ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediate';
+
+CREATE TABLE lists (
+    list_id uuid PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+CREATE TABLE list_memberships (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id, list_id)
+);
+-- Every issue used to go to every subscriber: they all start on the default list.
+INSERT INTO lists (list_id, slug, name)
+VALUES ('6f1c5a0e-3b7d-4a4e-9d3f-2a8e4c1b9f10', 'newsletter', 'Newsletter');
+INSERT INTO list_memberships (subscriber_id, list_id)
+SELECT id, '6f1c5a0e-3b7d-4a4e-9d3f-2a8e4c1b9f10' FROM subscriptions;
+
+-- New addresses waiting for confirmation.
+CREATE TABLE email_changes (
+    token TEXT PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- Issues waiting for the next digest of a subscriber.
+CREATE TABLE digest_entries (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    queued_at timestamptz NOT NULL
+);
@@ -0,0 +1,40 @@
+-- Proof of consent for double opt-in: what each subscriber agreed to when signing up, and when
+-- and from where they confirmed it. Subscribers who signed up earlier have no records.
+-- Rows are never updated. They are only deleted along with their subscriber, on erasure: the
+-- triggers below enforce both.
+CREATE TABLE consent_events(
+    event_id uuid PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    ip TEXT NOT NULL,
+    user_agent TEXT NULL,
+    -- Only recorded on signup.
+    source TEXT NULL,
+    consent_text_version TEXT NULL
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+
+CREATE FUNCTION reject_consent_event_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'consent_events cannot be changed';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER consent_events_immutable
+    BEFORE UPDATE OR TRUNCATE ON consent_events
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_consent_event_changes();
+
+-- Deleting a subscriber cascades here, after their row is gone: only then may events go.
+CREATE FUNCTION reject_consent_event_deletion() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM subscriptions WHERE id = OLD.subscriber_id) THEN
+        RAISE EXCEPTION 'consent_events are only deleted along with their subscriber';
+    END IF;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER consent_events_deleted_with_subscriber
+    BEFORE DELETE ON consent_events
+    FOR EACH ROW EXECUTE FUNCTION reject_consent_event_deletion();
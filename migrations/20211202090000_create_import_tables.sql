@@ -0,0 +1,34 @@
+-- Bulk imports of subscribers from CSV. Uploaded rows are stored first, then imported one by one
+-- in the background, so that interrupted imports resume where they stopped.
+CREATE TABLE import_jobs(
+    job_id uuid PRIMARY KEY,
+    status TEXT NOT NULL,
+    -- Whether imported subscribers skip double opt-in.
+    confirmed BOOLEAN NOT NULL,
+    -- A list joined on top of the default one.
+    list_id uuid NULL REFERENCES lists (list_id) ON DELETE SET NULL,
+    created_by TEXT NOT NULL,
+    -- IP of the upload, or `cli`. Recorded as the origin of the imported consent.
+    created_from TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    finished_at timestamptz NULL
+);
+
+CREATE TABLE import_rows(
+    job_id uuid NOT NULL REFERENCES import_jobs (job_id) ON DELETE CASCADE,
+    -- Position of the row in the upload, counting the header as 1. This is its line number,
+    -- unless quoted fields span several lines.
+    line INT NOT NULL,
+    email TEXT NOT NULL,
+    name TEXT NOT NULL,
+    locale TEXT NOT NULL,
+    -- NULL until the row is processed.
+    outcome TEXT NULL,
+    error TEXT NULL,
+    PRIMARY KEY (job_id, line)
+);
+
+-- Imports look addresses up case-insensitively, once per row. Being unique, the index also
+-- keeps `Ursula@example.com` and `ursula@example.com` from being two subscribers. Creating it
+-- fails if such duplicates exist already: merge them first.
+CREATE UNIQUE INDEX subscriptions_lower_email_key ON subscriptions (lower(email));
@@ -0,0 +1,56 @@
+use crate::configuration::{ChallengeProvider, ChallengeSettings};
+use std::sync::Arc;
+
+/// Verifies the response to a CAPTCHA-style challenge submitted alongside a form.
+///
+/// Providers (hCaptcha, Turnstile, ...) usually require a round trip to their API, hence the
+/// async signature. Transport errors should be returned as `Err` rather than swallowed.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    async fn verify(
+        &self,
+        challenge_response: Option<&str>,
+        client_ip: &str,
+    ) -> Result<bool, anyhow::Error>;
+}
+
+/// Accepts every submission. Used when no provider is configured.
+pub struct NoChallenge;
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for NoChallenge {
+    async fn verify(&self, _: Option<&str>, _: &str) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+}
+
+/// Accepts only submissions carrying a fixed, pre-shared response. Meant for tests.
+pub struct StubChallengeVerifier {
+    expected_response: String,
+}
+
+impl StubChallengeVerifier {
+    pub fn new(expected_response: String) -> Self {
+        Self { expected_response }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for StubChallengeVerifier {
+    async fn verify(
+        &self,
+        challenge_response: Option<&str>,
+        _: &str,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(challenge_response == Some(self.expected_response.as_str()))
+    }
+}
+
+pub fn get_challenge_verifier(settings: &ChallengeSettings) -> Arc<dyn ChallengeVerifier> {
+    match settings.provider {
+        ChallengeProvider::Disabled => Arc::new(NoChallenge),
+        ChallengeProvider::Stub => Arc::new(StubChallengeVerifier::new(
+            settings.stub_response.clone().unwrap_or_default(),
+        )),
+    }
+}
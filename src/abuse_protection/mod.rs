@@ -0,0 +1,5 @@
+mod challenge;
+mod rate_limit;
+
+pub use challenge::*;
+pub use rate_limit::*;
@@ -0,0 +1,427 @@
+use crate::api_error::ApiError;
+use crate::configuration::{RateLimitSettings, RateLimitStoreKind, TokenBucketSettings};
+use crate::routes::error_chain_fmt;
+use crate::utils::{client_ip, too_many_requests};
+use actix_http::{Method, StatusCode};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+/// Once the in-memory store holds this many buckets, full buckets are evicted on the next write.
+const MAX_IN_MEMORY_BUCKETS: usize = 10_000;
+
+/// A classic token bucket: each request takes a token, tokens trickle back at a fixed rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    pub fn full(settings: &TokenBucketSettings, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: settings.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and takes a token if one is available.
+    ///
+    /// Returns how long the caller has to wait for the next token when the bucket is empty.
+    pub fn try_acquire(
+        &mut self,
+        settings: &TokenBucketSettings,
+        now: DateTime<Utc>,
+    ) -> Result<(), Duration> {
+        self.refill(settings, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing_seconds = (1.0 - self.tokens) / settings.refill_rate();
+            Err(Duration::from_secs_f64(missing_seconds))
+        }
+    }
+
+    fn refill(&mut self, settings: &TokenBucketSettings, now: DateTime<Utc>) {
+        let elapsed_seconds = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        self.tokens =
+            (self.tokens + elapsed_seconds * settings.refill_rate()).min(settings.capacity as f64);
+        self.updated_at = now;
+    }
+
+    fn is_full(&self, settings: &TokenBucketSettings, now: DateTime<Utc>) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(settings, now);
+        bucket.tokens >= settings.capacity as f64
+    }
+}
+
+enum RateLimitStore {
+    InMemory(Mutex<HashMap<String, TokenBucket>>),
+    // Shared between all replicas of the application.
+    Postgres(PgPool),
+}
+
+/// Throttles `POST /subscriptions` per client IP and per email address.
+pub struct RateLimiter {
+    store: RateLimitStore,
+    // Behind locks so that limits can be changed by a configuration reload.
+    per_ip: RwLock<TokenBucketSettings>,
+    per_email: RwLock<TokenBucketSettings>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings, pool: PgPool) -> Self {
+        let store = match settings.store {
+            RateLimitStoreKind::Memory => RateLimitStore::InMemory(Mutex::new(HashMap::new())),
+            RateLimitStoreKind::Postgres => RateLimitStore::Postgres(pool),
+        };
+        Self {
+            store,
+            per_ip: RwLock::new(settings.per_ip.clone()),
+            per_email: RwLock::new(settings.per_email.clone()),
+        }
+    }
+
+    /// Applies new limits. Existing buckets are kept and refill at the new rate.
+    ///
+    /// The store cannot be changed without a restart.
+    pub fn update_limits(&self, settings: &RateLimitSettings) {
+        *self.per_ip.write().unwrap() = settings.per_ip.clone();
+        *self.per_email.write().unwrap() = settings.per_email.clone();
+    }
+
+    #[tracing::instrument(name = "Check per-IP rate limit", skip(self))]
+    pub async fn check_ip(&self, ip: &str) -> Result<(), RateLimitError> {
+        let settings = self.per_ip.read().unwrap().clone();
+        self.acquire(&format!("ip:{}", ip), &settings).await
+    }
+
+    #[tracing::instrument(name = "Check per-email rate limit", skip(self, email))]
+    pub async fn check_email(&self, email: &str) -> Result<(), RateLimitError> {
+        let settings = self.per_email.read().unwrap().clone();
+        self.acquire(&format!("email:{}", email.to_lowercase()), &settings)
+            .await
+    }
+
+    /// Forgets the buckets that have refilled completely: a new one would be just the same.
+    #[tracing::instrument(name = "Prune rate limit buckets", skip(self))]
+    pub async fn prune(&self) -> Result<(), anyhow::Error> {
+        let now = Utc::now();
+        match &self.store {
+            RateLimitStore::InMemory(buckets) => {
+                self.evict_full_buckets(&mut buckets.lock().unwrap(), now);
+            }
+            RateLimitStore::Postgres(pool) => {
+                let per_ip = self.per_ip.read().unwrap().clone();
+                let per_email = self.per_email.read().unwrap().clone();
+                // Untouched for that long, a bucket is full whatever it held.
+                sqlx::query!(
+                    r#"
+                    DELETE FROM rate_limit_buckets
+                    WHERE (key LIKE 'ip:%' AND updated_at < $1)
+                       OR (key LIKE 'email:%' AND updated_at < $2)
+                    "#,
+                    now - time_to_refill(&per_ip),
+                    now - time_to_refill(&per_email)
+                )
+                .execute(pool)
+                .await
+                .context("Failed to delete full rate limit buckets.")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Each bucket is checked against the limits of its own kind, which may refill at a
+    // different rate than the bucket being acquired.
+    fn evict_full_buckets(&self, buckets: &mut HashMap<String, TokenBucket>, now: DateTime<Utc>) {
+        let per_ip = self.per_ip.read().unwrap().clone();
+        let per_email = self.per_email.read().unwrap().clone();
+        buckets.retain(|key, bucket| {
+            let settings = if key.starts_with("ip:") {
+                &per_ip
+            } else {
+                &per_email
+            };
+            !bucket.is_full(settings, now)
+        });
+    }
+
+    async fn acquire(
+        &self,
+        key: &str,
+        settings: &TokenBucketSettings,
+    ) -> Result<(), RateLimitError> {
+        let now = Utc::now();
+        let outcome = match &self.store {
+            RateLimitStore::InMemory(buckets) => {
+                let mut buckets = buckets.lock().unwrap();
+                if buckets.len() >= MAX_IN_MEMORY_BUCKETS {
+                    self.evict_full_buckets(&mut buckets, now);
+                }
+                buckets
+                    .entry(key.to_string())
+                    .or_insert_with(|| TokenBucket::full(settings, now))
+                    .try_acquire(settings, now)
+            }
+            RateLimitStore::Postgres(pool) => acquire_from_postgres(pool, key, settings, now)
+                .await
+                .map_err(RateLimitError::UnexpectedError)?,
+        };
+        outcome.map_err(|retry_after| {
+            tracing::warn!(rate_limit.key = %key, "Rate limit exceeded.");
+            RateLimitError::Exceeded { retry_after }
+        })
+    }
+}
+
+/// How long an empty bucket takes to fill up.
+fn time_to_refill(settings: &TokenBucketSettings) -> chrono::Duration {
+    chrono::Duration::seconds(settings.capacity as i64 * settings.refill_interval_seconds as i64)
+}
+
+#[tracing::instrument(name = "Acquire rate limit token from Postgres", skip(pool, settings))]
+async fn acquire_from_postgres(
+    pool: &PgPool,
+    key: &str,
+    settings: &TokenBucketSettings,
+    now: DateTime<Utc>,
+) -> Result<Result<(), Duration>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Lock the row so that concurrent requests for the same key are serialised.
+    let mut bucket = sqlx::query!(
+        r#"
+        SELECT tokens, updated_at
+        FROM rate_limit_buckets
+        WHERE key = $1
+        FOR UPDATE
+        "#,
+        key
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch rate limit bucket")?
+    .map(|row| TokenBucket {
+        tokens: row.tokens,
+        updated_at: row.updated_at,
+    })
+    .unwrap_or_else(|| TokenBucket::full(settings, now));
+
+    let outcome = bucket.try_acquire(settings, now);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE
+        SET tokens = EXCLUDED.tokens, updated_at = EXCLUDED.updated_at
+        "#,
+        key,
+        bucket.tokens,
+        bucket.updated_at
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store rate limit bucket")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a rate limit bucket")?;
+
+    Ok(outcome)
+}
+
+#[derive(thiserror::Error)]
+pub enum RateLimitError {
+    #[error("Too many requests. Try again later.")]
+    Exceeded { retry_after: Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<&RateLimitError> for ApiError {
+    fn from(e: &RateLimitError) -> Self {
+        match e {
+            RateLimitError::Exceeded { retry_after } => too_many_requests(*retry_after),
+            RateLimitError::UnexpectedError(_) => ApiError::internal(),
+        }
+    }
+}
+
+impl ResponseError for RateLimitError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
+
+/// Middleware that rejects requests with `429 Too Many Requests` once the client IP has
+/// exhausted its bucket. It relies on a `RateLimiter` being registered as application data.
+pub struct IpRateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for IpRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = IpRateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpRateLimitMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IpRateLimitMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for IpRateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            // Preflights are free, so that a cross-origin signup costs a single token.
+            let is_preflight = req.method() == Method::OPTIONS;
+            let rate_limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+            if let Some(rate_limiter) = rate_limiter.filter(|_| !is_preflight) {
+                let ip = client_ip(req.request());
+                rate_limiter.check_ip(&ip).await?;
+            }
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, TokenBucket};
+    use crate::configuration::{RateLimitSettings, RateLimitStoreKind, TokenBucketSettings};
+    use chrono::{Duration, TimeZone, Utc};
+    use claim::{assert_err, assert_ok};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::collections::HashMap;
+
+    fn settings() -> TokenBucketSettings {
+        TokenBucketSettings {
+            capacity: 2,
+            refill_interval_seconds: 10,
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_allows_up_to_capacity_requests() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let mut bucket = TokenBucket::full(&settings(), now);
+
+        assert_ok!(bucket.try_acquire(&settings(), now));
+        assert_ok!(bucket.try_acquire(&settings(), now));
+        assert_err!(bucket.try_acquire(&settings(), now));
+    }
+
+    #[test]
+    fn an_empty_bucket_reports_when_the_next_token_is_available() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let mut bucket = TokenBucket::full(&settings(), now);
+        let _ = bucket.try_acquire(&settings(), now);
+        let _ = bucket.try_acquire(&settings(), now);
+
+        let retry_after = bucket
+            .try_acquire(&settings(), now + Duration::seconds(4))
+            .unwrap_err();
+
+        assert_eq!(retry_after.as_secs(), 6);
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let mut bucket = TokenBucket::full(&settings(), now);
+        let _ = bucket.try_acquire(&settings(), now);
+        let _ = bucket.try_acquire(&settings(), now);
+
+        assert_ok!(bucket.try_acquire(&settings(), now + Duration::seconds(10)));
+    }
+
+    #[test]
+    fn tokens_never_exceed_capacity() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let mut bucket = TokenBucket::full(&settings(), now);
+        let later = now + Duration::hours(1);
+
+        assert_ok!(bucket.try_acquire(&settings(), later));
+        assert_ok!(bucket.try_acquire(&settings(), later));
+        assert_err!(bucket.try_acquire(&settings(), later));
+    }
+
+    #[tokio::test]
+    async fn buckets_are_evicted_once_full_by_their_own_limits() {
+        let limiter = RateLimiter::new(
+            &RateLimitSettings {
+                store: RateLimitStoreKind::Memory,
+                per_ip: TokenBucketSettings {
+                    capacity: 1,
+                    refill_interval_seconds: 1,
+                },
+                per_email: TokenBucketSettings {
+                    capacity: 1,
+                    refill_interval_seconds: 3600,
+                },
+            },
+            PgPoolOptions::new().connect_lazy_with(PgConnectOptions::new()),
+        );
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let empty = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+        let mut buckets: HashMap<_, _> = vec![
+            ("ip:203.0.113.7".to_string(), empty.clone()),
+            ("email:ursula@example.com".to_string(), empty),
+        ]
+        .into_iter()
+        .collect();
+
+        limiter.evict_full_buckets(&mut buckets, now + Duration::seconds(10));
+
+        assert_eq!(
+            buckets.keys().collect::<Vec<_>>(),
+            vec!["email:ursula@example.com"]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::audit::request_id;
+use crate::i18n::{lookup, translate, Locale};
+use crate::templates::escape_html;
+use actix_http::header::{self, HeaderMap};
+use actix_http::StatusCode;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// An error as reported to clients, whatever the route.
+///
+/// It is rendered as an RFC 7807 problem, `application/problem+json`, with a stable
+/// machine-readable `code`. Browsers get an HTML page instead. Either way, the response
+/// carries the id of the request, so that it can be matched with the logs.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    detail: Option<String>,
+    retry_after: Option<Duration>,
+    // Realm of the Basic authentication challenge.
+    realm: Option<&'static str>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str) -> Self {
+        Self {
+            status,
+            code,
+            detail: None,
+            retry_after: None,
+            realm: None,
+        }
+    }
+
+    /// `500 Internal Server Error`. The cause is logged, but never shown to clients.
+    pub fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+    }
+
+    /// Explanation specific to this occurrence, shown to clients.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Tells the client when it may try again, with `Retry-After`.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    /// Asks the client for Basic credentials, with `WWW-Authenticate`.
+    pub fn with_basic_challenge(mut self, realm: &'static str) -> Self {
+        self.realm = Some(realm);
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    fn title(&self) -> &'static str {
+        self.status.canonical_reason().unwrap_or("Error")
+    }
+
+    fn problem(&self, context: &ErrorContext) -> serde_json::Value {
+        let mut problem = serde_json::json!({
+            "type": "about:blank",
+            "title": self.title(),
+            "status": self.status.as_u16(),
+            "code": self.code,
+        });
+        if let Some(detail) = &self.detail {
+            problem["detail"] = detail.as_str().into();
+        }
+        if let Some(path) = &context.path {
+            problem["instance"] = path.as_str().into();
+        }
+        if let Some(request_id) = context.request_id {
+            problem["request_id"] = request_id.to_string().into();
+        }
+        problem
+    }
+
+    /// A page in the client's language. The heading is translated by code, but `detail` is
+    /// shown as is.
+    fn html(&self, context: &ErrorContext) -> String {
+        let locale = context.locale;
+        let title = lookup(locale, &format!("error-{}", self.code))
+            .unwrap_or_else(|| self.title())
+            .to_string();
+        let detail = self
+            .detail
+            .clone()
+            .unwrap_or_else(|| translate(locale, "error-generic", &[]));
+        let request_id = context
+            .request_id
+            .map(|id| {
+                let id = id.to_string();
+                let line = translate(locale, "error-request-id", &[("request_id", &id)]);
+                format!("<p><small>{}</small></p>", escape_html(&line))
+            })
+            .unwrap_or_default();
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"{lang}\">\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+            <body>\n<h1>{title}</h1>\n<p>{detail}</p>\n{request_id}\n</body>\n</html>\n",
+            lang = locale.as_str(),
+            title = escape_html(&title),
+            detail = escape_html(&detail),
+            request_id = request_id,
+        )
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} ({})", detail, self.code),
+            None => write!(f, "{} ({})", self.title(), self.code),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let context = ERROR_CONTEXT.try_with(Clone::clone).unwrap_or_default();
+        let mut response = HttpResponse::build(self.status);
+        if let Some(retry_after) = self.retry_after {
+            // Retry-After is expressed in whole seconds, so round up.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        if let Some(realm) = self.realm {
+            response.insert_header((
+                header::WWW_AUTHENTICATE,
+                format!(r#"Basic realm="{}""#, realm),
+            ));
+        }
+        if context.prefers_html {
+            response
+                .content_type("text/html; charset=utf-8")
+                .body(self.html(&context))
+        } else {
+            response
+                .content_type("application/problem+json")
+                .body(self.problem(&context).to_string())
+        }
+    }
+}
+
+/// Wraps errors raised while extracting the request, e.g. a malformed body or query string,
+/// so that they are reported like any other. The original error is kept for the logs.
+pub fn invalid_request(e: impl ResponseError + 'static) -> actix_web::Error {
+    let response = ApiError::new(StatusCode::BAD_REQUEST, "invalid_request")
+        .with_detail(e.to_string())
+        .error_response();
+    InternalError::from_response(e, response).into()
+}
+
+/// Reports malformed query strings with `invalid_request`.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _: &HttpRequest| invalid_request(e))
+}
+
+/// Reports malformed path segments, e.g. ids that are not UUIDs, with `invalid_request`.
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|e, _: &HttpRequest| invalid_request(e))
+}
+
+/// Answers requests that match no route.
+pub async fn not_found() -> HttpResponse {
+    ApiError::new(StatusCode::NOT_FOUND, "not_found")
+        .with_detail("There is nothing at this address.")
+        .error_response()
+}
+
+/// What an error response needs to know about the request it answers.
+#[derive(Debug, Clone, Default)]
+struct ErrorContext {
+    request_id: Option<Uuid>,
+    path: Option<String>,
+    prefers_html: bool,
+    locale: Locale,
+}
+
+tokio::task_local! {
+    static ERROR_CONTEXT: ErrorContext;
+}
+
+/// Whether the client would rather get HTML than JSON, as browsers navigating to a page do.
+pub(crate) fn prefers_html(headers: &HeaderMap) -> bool {
+    let accept = match headers.get(header::ACCEPT).and_then(|h| h.to_str().ok()) {
+        Some(accept) => accept,
+        None => return false,
+    };
+    // Browsers list the types they prefer first, so the first match wins.
+    accept
+        .split(',')
+        .map(|range| range.split(';').next().unwrap_or("").trim())
+        .find_map(|media_type| match media_type {
+            "text/html" => Some(true),
+            "application/json" | "application/problem+json" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+/// Middleware that gives `ApiError` responses access to the request they answer. It must be
+/// wrapped inside `TracingLogger`, which assigns request ids.
+pub struct ErrorResponses;
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorResponses
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ErrorResponsesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorResponsesMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ErrorResponsesMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorResponsesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let context = ErrorContext {
+            request_id: request_id(req.request()),
+            path: Some(req.path().to_string()),
+            prefers_html: prefers_html(req.headers()),
+            locale: Locale::of_request(req.headers()),
+        };
+        Box::pin(async move {
+            // Handlers turn their errors into responses while they run, within the scope.
+            match ERROR_CONTEXT
+                .scope(context.clone(), service.call(req))
+                .await
+            {
+                Ok(response) => Ok(response),
+                // Errors returned by middlewares would only be turned into responses later, so
+                // render them now.
+                Err(e) => {
+                    let response = ERROR_CONTEXT
+                        .scope(context, async { e.as_response_error().error_response() })
+                        .await;
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefers_html;
+    use actix_http::header::{HeaderMap, HeaderValue, ACCEPT};
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn browsers_navigating_to_a_page_prefer_html() {
+        let headers = accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8");
+
+        assert!(prefers_html(&headers));
+    }
+
+    #[test]
+    fn api_clients_prefer_json() {
+        assert!(!prefers_html(&accept("application/json, text/html")));
+        assert!(!prefers_html(&accept("*/*")));
+        assert!(!prefers_html(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::authentication::AuthenticatedUser;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres};
+use std::convert::{TryFrom, TryInto};
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+/// Administrative actions recorded in the `audit_events` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    LoginLockout,
+    RecoveryCodeUsed,
+    PublishNewsletter,
+    CreateUser,
+    ChangeUserRole,
+    DeleteUser,
+    CreateApiToken,
+    RevokeApiToken,
+    EnableTwoFactor,
+    DisableTwoFactor,
+    ExportSubscribers,
+    DeleteSubscriber,
+    ImportSubscribers,
+    ChangeLogFilter,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::LoginLockout => "login_lockout",
+            AuditAction::RecoveryCodeUsed => "recovery_code_used",
+            AuditAction::PublishNewsletter => "publish_newsletter",
+            AuditAction::CreateUser => "create_user",
+            AuditAction::ChangeUserRole => "change_user_role",
+            AuditAction::DeleteUser => "delete_user",
+            AuditAction::CreateApiToken => "create_api_token",
+            AuditAction::RevokeApiToken => "revoke_api_token",
+            AuditAction::EnableTwoFactor => "enable_two_factor",
+            AuditAction::DisableTwoFactor => "disable_two_factor",
+            AuditAction::ExportSubscribers => "export_subscribers",
+            AuditAction::DeleteSubscriber => "delete_subscriber",
+            AuditAction::ImportSubscribers => "import_subscribers",
+            AuditAction::ChangeLogFilter => "change_log_filter",
+        }
+    }
+}
+
+impl TryFrom<String> for AuditAction {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "login" => Ok(Self::Login),
+            "login_failed" => Ok(Self::LoginFailed),
+            "login_lockout" => Ok(Self::LoginLockout),
+            "recovery_code_used" => Ok(Self::RecoveryCodeUsed),
+            "publish_newsletter" => Ok(Self::PublishNewsletter),
+            "create_user" => Ok(Self::CreateUser),
+            "change_user_role" => Ok(Self::ChangeUserRole),
+            "delete_user" => Ok(Self::DeleteUser),
+            "create_api_token" => Ok(Self::CreateApiToken),
+            "revoke_api_token" => Ok(Self::RevokeApiToken),
+            "enable_two_factor" => Ok(Self::EnableTwoFactor),
+            "disable_two_factor" => Ok(Self::DisableTwoFactor),
+            "export_subscribers" => Ok(Self::ExportSubscribers),
+            "delete_subscriber" => Ok(Self::DeleteSubscriber),
+            "import_subscribers" => Ok(Self::ImportSubscribers),
+            "change_log_filter" => Ok(Self::ChangeLogFilter),
+            other => Err(format!("{} is not a supported audit action.", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Who performed an audited action.
+///
+/// `user_id` is unknown for failed logins, where `name` is the username that was attempted.
+pub struct Actor<'a> {
+    pub user_id: Option<Uuid>,
+    pub name: &'a str,
+}
+
+impl<'a> From<&'a AuthenticatedUser> for Actor<'a> {
+    fn from(user: &'a AuthenticatedUser) -> Self {
+        Self {
+            user_id: Some(user.user_id),
+            name: &user.username,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct AuditEvent {
+    pub event_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_user_id: Option<Uuid>,
+    pub actor: String,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub request_id: Option<Uuid>,
+}
+
+/// Filters for `list_audit_events`. Unset filters match everything.
+#[derive(Debug, Default)]
+pub struct AuditEventFilters {
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// The request id assigned by `TracingLogger`, so that events can be matched with the logs.
+pub fn request_id(request: &HttpRequest) -> Option<Uuid> {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .map(|request_id| (*request_id).into())
+}
+
+/// Appends an event to the audit log.
+///
+/// Pass the transaction that performs the action, if there is one, so that the event is only
+/// recorded if the action is committed.
+#[tracing::instrument(
+    name = "Record audit event",
+    skip(executor, request, actor, target),
+    fields(actor = %actor.name)
+)]
+pub async fn record_audit_event<'e, E>(
+    executor: E,
+    request: &HttpRequest,
+    actor: Actor<'_>,
+    action: AuditAction,
+    target: Option<&str>,
+) -> Result<(), anyhow::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let request_id = request_id(request);
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (event_id, occurred_at, actor_user_id, actor, action, target, request_id)
+        VALUES ($1, now(), $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        actor.user_id,
+        actor.name,
+        action.as_str(),
+        target,
+        request_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record an audit event.")?;
+    tracing::info!(
+        target: "audit",
+        action = %action,
+        actor = %actor.name,
+        audit.target = ?target,
+        "Recorded audit event."
+    );
+    Ok(())
+}
+
+/// Returns one page of events, most recent first, and the total number of matching events.
+#[tracing::instrument(name = "List audit events", skip(pool))]
+pub async fn list_audit_events(
+    pool: &PgPool,
+    filters: &AuditEventFilters,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<AuditEvent>, i64), anyhow::Error> {
+    let action = filters.action.map(|action| action.as_str());
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM audit_events
+        WHERE ($1::TEXT IS NULL OR actor = $1)
+          AND ($2::TEXT IS NULL OR action = $2)
+          AND ($3::TEXT IS NULL OR target = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR occurred_at >= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR occurred_at < $5)
+        "#,
+        filters.actor,
+        action,
+        filters.target,
+        filters.since,
+        filters.until
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count audit events.")?
+    .count;
+    let rows = sqlx::query!(
+        r#"
+        SELECT event_id, occurred_at, actor_user_id, actor, action, target, request_id
+        FROM audit_events
+        WHERE ($1::TEXT IS NULL OR actor = $1)
+          AND ($2::TEXT IS NULL OR action = $2)
+          AND ($3::TEXT IS NULL OR target = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR occurred_at >= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR occurred_at < $5)
+        ORDER BY occurred_at DESC, event_id
+        LIMIT $6 OFFSET $7
+        "#,
+        filters.actor,
+        action,
+        filters.target,
+        filters.since,
+        filters.until,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch audit events.")?;
+
+    let events = rows
+        .into_iter()
+        .map(|row| {
+            Ok(AuditEvent {
+                event_id: row.event_id,
+                occurred_at: row.occurred_at,
+                actor_user_id: row.actor_user_id,
+                actor: row.actor,
+                action: row.action.try_into().map_err(anyhow::Error::msg)?,
+                target: row.target,
+                request_id: row.request_id,
+            })
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok((events, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn actions_round_trip_through_their_stored_name() {
+        for action in &[
+            AuditAction::LoginFailed,
+            AuditAction::PublishNewsletter,
+            AuditAction::DeleteSubscriber,
+        ] {
+            assert_eq!(
+                AuditAction::try_from(action.as_str().to_string()),
+                Ok(*action)
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        assert!(AuditAction::try_from("drop_tables".to_string()).is_err());
+    }
+}
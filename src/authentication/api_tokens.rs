@@ -0,0 +1,191 @@
+use crate::authentication::{AuthError, AuthenticatedUser, Permission};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+/// Makes tokens easy to recognise, e.g. by secret scanners.
+const API_TOKEN_PREFIX: &str = "z2p_";
+
+/// Metadata of an API token. The token itself is only ever shown once, at creation.
+#[derive(serde::Serialize)]
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+pub fn generate_api_token() -> String {
+    let secret: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(40)
+        .collect();
+    format!("{}{}", API_TOKEN_PREFIX, secret)
+}
+
+/// Tokens carry enough entropy that a fast, unsalted hash is sufficient (unlike passwords).
+pub fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        FROM users
+        WHERE api_tokens.user_id = users.user_id
+          AND api_tokens.token_hash = $1
+          AND api_tokens.revoked_at IS NULL
+          AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > now())
+        RETURNING users.user_id, users.username, users.role, api_tokens.scopes, api_tokens.token_id
+        "#,
+        hash_api_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, expired or revoked API token."))
+    })?;
+
+    Ok(AuthenticatedUser {
+        user_id: row.user_id,
+        username: row.username,
+        role: row.role.try_into().map_err(anyhow::Error::msg)?,
+        scopes: Some(parse_scopes(row.scopes)?),
+        token_id: Some(row.token_id),
+    })
+}
+
+#[tracing::instrument(name = "Store a new API token", skip(token, pool))]
+pub async fn store_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    token: &str,
+    scopes: &[Permission],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<ApiToken, anyhow::Error> {
+    let api_token = ApiToken {
+        token_id: Uuid::new_v4(),
+        name: name.to_string(),
+        scopes: scopes.to_vec(),
+        created_at: Utc::now(),
+        expires_at,
+        last_used_at: None,
+        revoked_at: None,
+    };
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        api_token.token_id,
+        user_id,
+        api_token.name,
+        hash_api_token(token),
+        &scopes,
+        api_token.created_at,
+        api_token.expires_at
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the API token.")?;
+    Ok(api_token)
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch API tokens.")?;
+    rows.into_iter()
+        .map(|row| {
+            Ok(ApiToken {
+                token_id: row.token_id,
+                name: row.name,
+                scopes: parse_scopes(row.scopes)?,
+                created_at: row.created_at,
+                expires_at: row.expires_at,
+                last_used_at: row.last_used_at,
+                revoked_at: row.revoked_at,
+            })
+        })
+        .collect()
+}
+
+/// Revokes a token owned by `user_id`. Returns `false` if there is no such active token.
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the API token.")?
+    .rows_affected();
+    Ok(revoked > 0)
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Result<Vec<Permission>, anyhow::Error> {
+    scopes
+        .into_iter()
+        .map(|scope| scope.try_into().map_err(anyhow::Error::msg))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, hash_api_token};
+
+    #[test]
+    fn generated_tokens_are_prefixed_and_unique() {
+        let first = generate_api_token();
+        let second = generate_api_token();
+
+        assert!(first.starts_with("z2p_"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn hashes_are_deterministic_and_do_not_contain_the_token() {
+        let token = generate_api_token();
+
+        assert_eq!(hash_api_token(&token), hash_api_token(&token));
+        assert!(!hash_api_token(&token).contains(&token));
+        assert_eq!(hash_api_token(&token).len(), 64);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::audit::{record_audit_event, Actor, AuditAction};
+use crate::authentication::{
+    basic_authentication, bearer_token, get_two_factor_enrolment, record_used_step,
+    use_recovery_code, validate_api_token, validate_credentials, AuthError, AuthenticatedUser,
+    LoginThrottle, SecretCipher,
+};
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+/// Header carrying the current TOTP code, or a recovery code, for users with two-factor
+/// authentication enabled.
+pub const TWO_FACTOR_CODE_HEADER: &str = "X-Two-Factor-Code";
+
+/// Everything needed to authenticate a request, shared by all handlers.
+pub struct Authenticator {
+    pool: PgPool,
+    throttle: LoginThrottle,
+    cipher: SecretCipher,
+    two_factor_issuer: String,
+}
+
+impl Authenticator {
+    pub fn new(
+        pool: PgPool,
+        throttle: LoginThrottle,
+        cipher: SecretCipher,
+        two_factor_issuer: String,
+    ) -> Self {
+        Self {
+            pool,
+            throttle,
+            cipher,
+            two_factor_issuer,
+        }
+    }
+
+    pub fn cipher(&self) -> &SecretCipher {
+        &self.cipher
+    }
+
+    pub fn two_factor_issuer(&self) -> &str {
+        &self.two_factor_issuer
+    }
+
+    /// Authenticates the request with either an API token or its 'Basic' credentials,
+    /// followed by a second factor if the user enabled one.
+    ///
+    /// Successful logins are audited with the id of the API token, or else the client IP.
+    ///
+    /// Records `username` and `user_id` on the current span, so the calling handler should
+    /// declare them as empty fields.
+    pub async fn authenticate(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<AuthenticatedUser, AuthError> {
+        let user =
+            match bearer_token(request.headers()) {
+                // API tokens are meant for machines, which cannot answer a second factor challenge.
+                // They are scoped and revocable instead.
+                Some(token) => {
+                    let user = validate_api_token(token, &self.pool).await?;
+                    tracing::Span::current()
+                        .record("username", &tracing::field::display(&user.username));
+                    user
+                }
+                None => {
+                    let credentials = basic_authentication(request.headers())
+                        .map_err(AuthError::InvalidCredentials)?;
+                    tracing::Span::current()
+                        .record("username", &tracing::field::display(&credentials.username));
+                    let ip = client_ip(request);
+                    let username = credentials.username.clone();
+                    let outcome =
+                        match validate_credentials(credentials, &self.pool, &self.throttle, &ip)
+                            .await
+                        {
+                            Ok(user) => self
+                                .verify_second_factor(&user, request)
+                                .await
+                                .map(|_| user),
+                            Err(e) => Err(e),
+                        };
+                    match outcome {
+                        Ok(user) => {
+                            self.throttle.register_success(&username).await?;
+                            user
+                        }
+                        Err(AuthError::InvalidCredentials(e)) => {
+                            self.register_failure(request, &username, &ip).await?;
+                            return Err(AuthError::InvalidCredentials(e));
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+        tracing::Span::current().record("user_id", &tracing::field::display(&user.user_id));
+        let target = match user.token_id {
+            Some(token_id) => token_id.to_string(),
+            None => client_ip(request),
+        };
+        record_audit_event(
+            &self.pool,
+            request,
+            (&user).into(),
+            AuditAction::Login,
+            Some(&target),
+        )
+        .await?;
+        Ok(user)
+    }
+
+    async fn register_failure(
+        &self,
+        request: &HttpRequest,
+        username: &str,
+        client_ip: &str,
+    ) -> Result<(), AuthError> {
+        let lockout = self.throttle.register_failure(username, client_ip).await?;
+        let actor = Actor {
+            user_id: None,
+            name: username,
+        };
+        record_audit_event(
+            &self.pool,
+            request,
+            actor,
+            AuditAction::LoginFailed,
+            Some(client_ip),
+        )
+        .await?;
+        if lockout.is_some() {
+            let actor = Actor {
+                user_id: None,
+                name: username,
+            };
+            record_audit_event(
+                &self.pool,
+                request,
+                actor,
+                AuditAction::LoginLockout,
+                Some(client_ip),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Verify second factor", skip(self, user, request))]
+    async fn verify_second_factor(
+        &self,
+        user: &AuthenticatedUser,
+        request: &HttpRequest,
+    ) -> Result<(), AuthError> {
+        let enrolment =
+            match get_two_factor_enrolment(&self.pool, &self.cipher, user.user_id).await? {
+                Some(enrolment) if enrolment.enabled => enrolment,
+                _ => return Ok(()),
+            };
+        let code = request
+            .headers()
+            .get(TWO_FACTOR_CODE_HEADER)
+            .context("A two-factor code is required.")
+            .and_then(|code| {
+                code.to_str()
+                    .context("The two-factor code is not a valid string.")
+            })
+            .map_err(AuthError::InvalidCredentials)?
+            .trim();
+
+        if let Some(step) = enrolment
+            .secret
+            .verify(code, Utc::now(), enrolment.last_used_step)
+        {
+            return if record_used_step(&self.pool, user.user_id, step).await? {
+                Ok(())
+            } else {
+                Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "The two-factor code was already used."
+                )))
+            };
+        }
+        if use_recovery_code(&self.pool, user.user_id, code).await? {
+            record_audit_event(
+                &self.pool,
+                request,
+                user.into(),
+                AuditAction::RecoveryCodeUsed,
+                None,
+            )
+            .await?;
+            return Ok(());
+        }
+        Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Invalid two-factor code."
+        )))
+    }
+}
@@ -0,0 +1,301 @@
+use crate::authentication::AuthError;
+use crate::configuration::LoginProtectionSettings;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Failed login attempts recorded against a single key (a username or a client IP).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedAttempts {
+    pub count: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl FailedAttempts {
+    /// Records one more failure at `now`, locking the key once `threshold` is reached.
+    ///
+    /// Every failure past the threshold doubles the lockout, up to the configured maximum.
+    /// Failures older than the attempt window are forgotten.
+    pub fn register_failure(
+        previous: Option<Self>,
+        threshold: u32,
+        settings: &LoginProtectionSettings,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let window = chrono::Duration::seconds(settings.attempt_window_seconds as i64);
+        let count = match previous {
+            Some(previous) if now - previous.last_failed_at <= window => previous.count + 1,
+            _ => 1,
+        };
+        let locked_until = if count as u32 >= threshold {
+            let exponent = (count as u32 - threshold).min(31);
+            let lockout_seconds = settings
+                .base_lockout_seconds
+                .saturating_mul(2u64.saturating_pow(exponent))
+                .min(settings.max_lockout_seconds);
+            Some(now + chrono::Duration::seconds(lockout_seconds as i64))
+        } else {
+            None
+        };
+        Self {
+            count,
+            last_failed_at: now,
+            locked_until,
+        }
+    }
+}
+
+/// Tracks failed logins per username and per client IP, and enforces progressive lockouts.
+///
+/// State lives in Postgres so that every replica sees the same counters.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    pool: PgPool,
+    settings: LoginProtectionSettings,
+}
+
+impl LoginThrottle {
+    pub fn new(settings: LoginProtectionSettings, pool: PgPool) -> Self {
+        Self { pool, settings }
+    }
+
+    #[tracing::instrument(name = "Check login lockout", skip(self, username))]
+    pub async fn ensure_not_locked_out(
+        &self,
+        username: &str,
+        client_ip: &str,
+    ) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let locked_until = sqlx::query!(
+            r#"
+            SELECT MAX(locked_until) AS locked_until
+            FROM login_attempts
+            WHERE key = ANY($1) AND locked_until > $2
+            "#,
+            &[username_key(username), ip_key(client_ip)][..],
+            now
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to fetch login lockouts.")?
+        .locked_until;
+
+        match locked_until {
+            Some(locked_until) => Err(AuthError::LockedOut {
+                retry_after: (locked_until - now).to_std().unwrap_or_default(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns when the lockout ends if this failure triggered one.
+    #[tracing::instrument(name = "Register failed login", skip(self, username))]
+    pub async fn register_failure(
+        &self,
+        username: &str,
+        client_ip: &str,
+    ) -> Result<Option<DateTime<Utc>>, AuthError> {
+        let now = Utc::now();
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let keys = [
+            (
+                username_key(username),
+                self.settings.max_failed_attempts_per_user,
+            ),
+            (ip_key(client_ip), self.settings.max_failed_attempts_per_ip),
+        ];
+        let mut lockout = None;
+        for (key, threshold) in keys.iter() {
+            let previous = get_failed_attempts(&mut transaction, key).await?;
+            let attempts =
+                FailedAttempts::register_failure(previous, *threshold, &self.settings, now);
+            store_failed_attempts(&mut transaction, key, &attempts).await?;
+            if let Some(locked_until) = attempts.locked_until {
+                tracing::warn!(
+                    lockout.key = %key,
+                    failed_attempts = attempts.count,
+                    locked_until = %locked_until,
+                    "Locked out after too many failed login attempts."
+                );
+                lockout = lockout.max(Some(locked_until));
+            }
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store failed login attempts")?;
+        Ok(lockout)
+    }
+
+    /// Forgets failed attempts that no longer count: outside the attempt window and not
+    /// locked out.
+    #[tracing::instrument(name = "Prune failed logins", skip(self))]
+    pub async fn prune(&self) -> Result<(), anyhow::Error> {
+        let now = Utc::now();
+        let window = chrono::Duration::seconds(self.settings.attempt_window_seconds as i64);
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE last_failed_at < $1 AND (locked_until IS NULL OR locked_until <= $2)
+            "#,
+            now - window,
+            now
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete expired failed login attempts.")?;
+        Ok(())
+    }
+
+    /// Forgets failed attempts against a username once its owner logs in successfully.
+    ///
+    /// The per-IP counter is left alone: a single valid account must not let an attacker
+    /// reset the counter used to spot password spraying.
+    #[tracing::instrument(name = "Register successful login", skip(self, username))]
+    pub async fn register_success(&self, username: &str) -> Result<(), AuthError> {
+        sqlx::query!(
+            "DELETE FROM login_attempts WHERE key = $1",
+            username_key(username)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to clear failed login attempts.")?;
+        Ok(())
+    }
+}
+
+fn username_key(username: &str) -> String {
+    format!("username:{}", username)
+}
+
+fn ip_key(client_ip: &str) -> String {
+    format!("ip:{}", client_ip)
+}
+
+async fn get_failed_attempts(
+    transaction: &mut Transaction<'_, Postgres>,
+    key: &str,
+) -> Result<Option<FailedAttempts>, anyhow::Error> {
+    let attempts = sqlx::query!(
+        r#"
+        SELECT failed_attempts, last_failed_at, locked_until
+        FROM login_attempts
+        WHERE key = $1
+        FOR UPDATE
+        "#,
+        key
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to fetch failed login attempts.")?
+    .map(|row| FailedAttempts {
+        count: row.failed_attempts,
+        last_failed_at: row.last_failed_at,
+        locked_until: row.locked_until,
+    });
+    Ok(attempts)
+}
+
+async fn store_failed_attempts(
+    transaction: &mut Transaction<'_, Postgres>,
+    key: &str,
+    attempts: &FailedAttempts,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (key, failed_attempts, last_failed_at, locked_until)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (key) DO UPDATE
+        SET failed_attempts = EXCLUDED.failed_attempts,
+            last_failed_at = EXCLUDED.last_failed_at,
+            locked_until = EXCLUDED.locked_until
+        "#,
+        key,
+        attempts.count,
+        attempts.last_failed_at,
+        attempts.locked_until
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store failed login attempts.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailedAttempts;
+    use crate::configuration::LoginProtectionSettings;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn settings() -> LoginProtectionSettings {
+        LoginProtectionSettings {
+            max_failed_attempts_per_user: 3,
+            max_failed_attempts_per_ip: 10,
+            base_lockout_seconds: 30,
+            max_lockout_seconds: 100,
+            attempt_window_seconds: 600,
+        }
+    }
+
+    fn fail_n_times(n: u32, now: chrono::DateTime<Utc>) -> FailedAttempts {
+        (0..n)
+            .fold(None, |previous, _| {
+                Some(FailedAttempts::register_failure(
+                    previous,
+                    3,
+                    &settings(),
+                    now,
+                ))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn no_lockout_below_the_threshold() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        assert_eq!(fail_n_times(2, now).locked_until, None);
+    }
+
+    #[test]
+    fn reaching_the_threshold_locks_for_the_base_duration() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        assert_eq!(
+            fail_n_times(3, now).locked_until,
+            Some(now + Duration::seconds(30))
+        );
+    }
+
+    #[test]
+    fn lockouts_double_with_every_further_failure_up_to_the_maximum() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        assert_eq!(
+            fail_n_times(4, now).locked_until,
+            Some(now + Duration::seconds(60))
+        );
+        assert_eq!(
+            fail_n_times(5, now).locked_until,
+            Some(now + Duration::seconds(100))
+        );
+        assert_eq!(
+            fail_n_times(50, now).locked_until,
+            Some(now + Duration::seconds(100))
+        );
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let now = Utc.ymd(2021, 11, 20).and_hms(12, 0, 0);
+        let previous = fail_n_times(2, now);
+        let later = now + Duration::seconds(601);
+
+        let attempts = FailedAttempts::register_failure(Some(previous), 3, &settings(), later);
+
+        assert_eq!(attempts.count, 1);
+        assert_eq!(attempts.locked_until, None);
+    }
+}
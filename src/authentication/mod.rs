@@ -0,0 +1,13 @@
+mod api_tokens;
+mod authenticator;
+mod lockout;
+mod password;
+mod roles;
+mod two_factor;
+
+pub use api_tokens::*;
+pub use authenticator::*;
+pub use lockout::*;
+pub use password::*;
+pub use roles::*;
+pub use two_factor::*;
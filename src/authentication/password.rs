@@ -0,0 +1,218 @@
+use crate::authentication::{LoginThrottle, Permission, Role};
+use crate::routes::error_chain_fmt;
+use actix_http::header::HeaderMap;
+use anyhow::Context;
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use sqlx::PgPool;
+use std::convert::TryInto;
+use std::time::Duration;
+
+/// Argon2 hash of a throwaway password, using the same parameters as real user hashes.
+///
+/// We verify against it when the username is unknown so that the response time does not reveal
+/// whether a user exists.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=4096,t=3,p=1$XNfPezlwQL1PK+GC1G0yTQ$dTVsF4ODA3R5u1YYytdVElGxmc4n4acmQRWdthzCfo8";
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// A user whose credentials have been verified.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: uuid::Uuid,
+    pub username: String,
+    pub role: Role,
+    /// Set when the request was authenticated with an API token, which may only use a subset
+    /// of the permissions granted by the role.
+    pub scopes: Option<Vec<Permission>>,
+    /// The API token the request was authenticated with, if any.
+    pub token_id: Option<uuid::Uuid>,
+}
+
+impl AuthenticatedUser {
+    pub fn authorize(&self, permission: Permission) -> Result<(), AuthError> {
+        let in_scope = match &self.scopes {
+            Some(scopes) => scopes.contains(&permission),
+            None => true,
+        };
+        if self.role.can(permission) && in_scope {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden(permission))
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("Too many failed login attempts.")]
+    LockedOut { retry_after: Duration },
+    #[error("Not allowed to {0}.")]
+    Forbidden(Permission),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header is missing.")?
+        .to_str()
+        .context("The 'Authorization' header is not a valid UTF-8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authentication scheme is not 'Basic'.")?;
+    let decoded_bytes = base64::decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF-8.")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials { username, password })
+}
+
+/// Extracts the token from an `Authorization: Bearer <token>` header, if there is one.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, throttle))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+    throttle: &LoginThrottle,
+    client_ip: &str,
+) -> Result<AuthenticatedUser, AuthError> {
+    // Refuse early, before spending CPU on Argon2, if either the username or the IP is locked out.
+    throttle
+        .ensure_not_locked_out(&credentials.username, client_ip)
+        .await?;
+
+    let mut user = None;
+    // expected_password_hash is stored in PHC string format: "${algorithm}${algorithm version}${$-separated algorithm parameters}${hash}${salt}"
+    let mut expected_password_hash_phc = DUMMY_PASSWORD_HASH.to_string();
+    if let Some((stored_user, stored_password_hash_phc)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user = Some(stored_user);
+        expected_password_hash_phc = stored_password_hash_phc;
+    }
+
+    // let current_span = tracing::Span::current();
+    // actix_web::rt::task::spawn_blocking(move || {
+    //     // tracing::info_span!("Verify password hash")
+    //     //     .in_scope(|| verify_password_hash(expected_password_hash_phc, credentials.password))
+    //     current_span
+    //         .in_scope(|| verify_password_hash(expected_password_hash_phc, credentials.password))
+    // })
+
+    // Move CPU-intensive hashing to a separate thread
+    let password = credentials.password;
+    let verification = spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash_phc, password)
+    })
+    .await
+    .context("failed to spawn blocking task.")?;
+
+    // Using ok_or_else converts the Option to Result and makes it convenient to propagate any Err with `?`.
+    // Outcomes are registered with the throttle by the caller, once any second factor has been
+    // checked too.
+    verification.and_then(|_| {
+        user.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")))
+    })
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash_phc, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash_phc: String,
+    password_candidate: String,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(&expected_password_hash_phc)
+        .context("Failed to parse hash in PHC string format")?;
+
+    // Execute the function within the scope of this span.
+    Argon2::default()
+        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+// Copied function signature from `spawn_blocking`
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> actix_web::rt::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    actix_web::rt::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(AuthenticatedUser, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, username, password_hash, role
+        FROM users
+        WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?;
+
+    match row {
+        Some(row) => {
+            let user = AuthenticatedUser {
+                user_id: row.user_id,
+                username: row.username,
+                role: row.role.try_into().map_err(anyhow::Error::msg)?,
+                scopes: None,
+                token_id: None,
+            };
+            Ok(Some((user, row.password_hash)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Hashes a password with Argon2 and a random salt, returning it in PHC string format.
+pub fn compute_password_hash(password: String) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to hash password")?
+        .to_string();
+    Ok(password_hash)
+}
@@ -0,0 +1,170 @@
+use std::convert::TryFrom;
+
+/// What a user is allowed to do. Stored as TEXT in `users.role`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Editor,
+    Viewer,
+}
+
+/// Actions that are gated by a `Role`. API tokens are scoped to a subset of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ViewReports,
+    DraftNewsletters,
+    PublishNewsletters,
+    ManageUsers,
+    ExportSubscribers,
+    EraseSubscribers,
+    ImportSubscribers,
+    ViewAuditLog,
+    ManageTelemetry,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Editor => matches!(
+                permission,
+                Permission::ViewReports
+                    | Permission::DraftNewsletters
+                    | Permission::PublishNewsletters
+            ),
+            Role::Viewer => matches!(permission, Permission::ViewReports),
+        }
+    }
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ViewReports => "view_reports",
+            Permission::DraftNewsletters => "draft_newsletters",
+            Permission::PublishNewsletters => "publish_newsletters",
+            Permission::ManageUsers => "manage_users",
+            Permission::ExportSubscribers => "export_subscribers",
+            Permission::EraseSubscribers => "erase_subscribers",
+            Permission::ImportSubscribers => "import_subscribers",
+            Permission::ViewAuditLog => "view_audit_log",
+            Permission::ManageTelemetry => "manage_telemetry",
+        }
+    }
+}
+
+impl TryFrom<String> for Permission {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "view_reports" => Ok(Self::ViewReports),
+            "draft_newsletters" => Ok(Self::DraftNewsletters),
+            "publish_newsletters" => Ok(Self::PublishNewsletters),
+            "manage_users" => Ok(Self::ManageUsers),
+            "export_subscribers" => Ok(Self::ExportSubscribers),
+            "erase_subscribers" => Ok(Self::EraseSubscribers),
+            "import_subscribers" => Ok(Self::ImportSubscribers),
+            "view_audit_log" => Ok(Self::ViewAuditLog),
+            "manage_telemetry" => Ok(Self::ManageTelemetry),
+            other => Err(format!("{} is not a supported permission.", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "admin" => Ok(Self::Admin),
+            "editor" => Ok(Self::Editor),
+            "viewer" => Ok(Self::Viewer),
+            other => Err(format!(
+                "{} is not a supported role. Use either `admin`, `editor` or `viewer`.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            Permission::ViewReports => "view reports",
+            Permission::DraftNewsletters => "draft newsletters",
+            Permission::PublishNewsletters => "publish newsletters",
+            Permission::ManageUsers => "manage users",
+            Permission::ExportSubscribers => "export subscriber data",
+            Permission::EraseSubscribers => "erase subscriber data",
+            Permission::ImportSubscribers => "import subscribers",
+            Permission::ViewAuditLog => "view the audit log",
+            Permission::ManageTelemetry => "manage telemetry",
+        };
+        f.write_str(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn viewers_can_only_view_reports() {
+        assert!(Role::Viewer.can(Permission::ViewReports));
+        assert!(!Role::Viewer.can(Permission::DraftNewsletters));
+        assert!(!Role::Viewer.can(Permission::PublishNewsletters));
+        assert!(!Role::Viewer.can(Permission::ManageUsers));
+        assert!(!Role::Viewer.can(Permission::ExportSubscribers));
+        assert!(!Role::Viewer.can(Permission::EraseSubscribers));
+        assert!(!Role::Viewer.can(Permission::ImportSubscribers));
+        assert!(!Role::Viewer.can(Permission::ViewAuditLog));
+        assert!(!Role::Viewer.can(Permission::ManageTelemetry));
+    }
+
+    #[test]
+    fn editors_can_draft_and_publish_but_not_administer() {
+        assert!(Role::Editor.can(Permission::ViewReports));
+        assert!(Role::Editor.can(Permission::DraftNewsletters));
+        assert!(Role::Editor.can(Permission::PublishNewsletters));
+        assert!(!Role::Editor.can(Permission::ManageUsers));
+        assert!(!Role::Editor.can(Permission::ExportSubscribers));
+        assert!(!Role::Editor.can(Permission::EraseSubscribers));
+        assert!(!Role::Editor.can(Permission::ImportSubscribers));
+        assert!(!Role::Editor.can(Permission::ViewAuditLog));
+        assert!(!Role::Editor.can(Permission::ManageTelemetry));
+    }
+
+    #[test]
+    fn admins_can_do_everything() {
+        for permission in &[
+            Permission::ViewReports,
+            Permission::DraftNewsletters,
+            Permission::PublishNewsletters,
+            Permission::ManageUsers,
+            Permission::ExportSubscribers,
+            Permission::EraseSubscribers,
+            Permission::ImportSubscribers,
+            Permission::ViewAuditLog,
+            Permission::ManageTelemetry,
+        ] {
+            assert!(Role::Admin.can(*permission));
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use ring::{aead, hmac};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// RFC 6238 defaults, which is what every authenticator app expects.
+const TOTP_STEP_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Accept codes from one step before and after the current one to tolerate clock drift.
+const TOTP_ALLOWED_SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Shared secret of a TOTP authenticator.
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; 20];
+        thread_rng().fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    pub fn from_base32(encoded: &str) -> Result<Self, anyhow::Error> {
+        let mut secret = Vec::new();
+        let (mut buffer, mut bits) = (0u32, 0u32);
+        for c in encoded.trim_end_matches('=').bytes() {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|&a| a == c.to_ascii_uppercase())
+                .context("The secret is not valid base32.")? as u32;
+            buffer = (buffer << 5) | value;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                secret.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        Ok(Self(secret))
+    }
+
+    /// Unpadded base32, the encoding used by `otpauth://` URIs.
+    pub fn to_base32(&self) -> String {
+        let mut encoded = String::new();
+        let (mut buffer, mut bits) = (0u32, 0u32);
+        for byte in &self.0 {
+            buffer = (buffer << 8) | u32::from(*byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+            }
+            buffer &= (1 << bits) - 1;
+        }
+        if bits > 0 {
+            encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+        }
+        encoded
+    }
+
+    /// The code displayed by an authenticator app at `time`.
+    pub fn code_at(&self, time: DateTime<Utc>) -> String {
+        self.code_for_step(time.timestamp().div_euclid(TOTP_STEP_SECONDS))
+    }
+
+    fn code_for_step(&self, step: i64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.0);
+        let digest = hmac::sign(&key, &step.to_be_bytes());
+        let digest = digest.as_ref();
+        // Dynamic truncation, RFC 4226 section 5.3.
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(TOTP_DIGITS),
+            width = TOTP_DIGITS as usize
+        )
+    }
+
+    /// Returns the time step matched by `code`, if any, ignoring steps up to `last_used_step`
+    /// so that a code cannot be replayed.
+    pub fn verify(
+        &self,
+        code: &str,
+        time: DateTime<Utc>,
+        last_used_step: Option<i64>,
+    ) -> Option<i64> {
+        let current_step = time.timestamp().div_euclid(TOTP_STEP_SECONDS);
+        (current_step - TOTP_ALLOWED_SKEW_STEPS..=current_step + TOTP_ALLOWED_SKEW_STEPS)
+            .filter(|step| last_used_step.map_or(true, |last_used| *step > last_used))
+            .find(|step| constant_time_eq(self.code_for_step(*step).as_bytes(), code.as_bytes()))
+    }
+
+    /// URI understood by authenticator apps, usually rendered as a QR code.
+    pub fn provisioning_uri(&self, issuer: &str, username: &str) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+            issuer = urlencoding(issuer),
+            username = urlencoding(username),
+            secret = self.to_base32(),
+            digits = TOTP_DIGITS,
+            period = TOTP_STEP_SECONDS,
+        )
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn urlencoding(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Encrypts TOTP secrets at rest with AES-256-GCM.
+pub struct SecretCipher {
+    key: aead::LessSafeKey,
+}
+
+impl SecretCipher {
+    /// `key` must be 32 bytes, base64-encoded.
+    pub fn new(key: &str) -> Result<Self, anyhow::Error> {
+        let key = base64::decode(key).context("The encryption key is not valid base64.")?;
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, &key)
+            .map_err(|_| anyhow::anyhow!("The encryption key must be 32 bytes long."))?;
+        Ok(Self {
+            key: aead::LessSafeKey::new(key),
+        })
+    }
+
+    /// Returns base64(nonce || ciphertext || tag).
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String, anyhow::Error> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret."))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(in_out);
+        Ok(base64::encode(sealed))
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let sealed = base64::decode(sealed).context("The encrypted secret is not valid base64.")?;
+        if sealed.len() < aead::NONCE_LEN {
+            anyhow::bail!("The encrypted secret is too short.");
+        }
+        let (nonce, ciphertext) = sealed.split_at(aead::NONCE_LEN);
+        let mut nonce_bytes = [0u8; aead::NONCE_LEN];
+        nonce_bytes.copy_from_slice(nonce);
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce_bytes),
+                aead::Aad::empty(),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret."))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Second factor settings of a user, with the secret already decrypted.
+pub struct TwoFactorEnrolment {
+    pub secret: TotpSecret,
+    pub enabled: bool,
+    pub last_used_step: Option<i64>,
+}
+
+#[tracing::instrument(name = "Get two-factor enrolment", skip(pool, cipher))]
+pub async fn get_two_factor_enrolment(
+    pool: &PgPool,
+    cipher: &SecretCipher,
+    user_id: Uuid,
+) -> Result<Option<TwoFactorEnrolment>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT encrypted_secret, enabled_at, last_used_step
+        FROM user_two_factor
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch two-factor enrolment.")?;
+    match row {
+        Some(row) => Ok(Some(TwoFactorEnrolment {
+            secret: TotpSecret(cipher.decrypt(&row.encrypted_secret)?),
+            enabled: row.enabled_at.is_some(),
+            last_used_step: row.last_used_step,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Starts (or restarts) enrolment with a fresh secret. It stays inactive until confirmed.
+#[tracing::instrument(name = "Start two-factor enrolment", skip(pool, cipher, secret))]
+pub async fn store_pending_two_factor(
+    pool: &PgPool,
+    cipher: &SecretCipher,
+    user_id: Uuid,
+    secret: &TotpSecret,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_two_factor (user_id, encrypted_secret, enabled_at, last_used_step)
+        VALUES ($1, $2, NULL, NULL)
+        ON CONFLICT (user_id) DO UPDATE
+        SET encrypted_secret = EXCLUDED.encrypted_secret, enabled_at = NULL, last_used_step = NULL
+        "#,
+        user_id,
+        cipher.encrypt(&secret.0)?
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store two-factor secret.")?;
+    Ok(())
+}
+
+/// Activates the pending secret and replaces any previous recovery codes.
+///
+/// Returns the plaintext recovery codes. Only their hashes are stored.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(pool))]
+pub async fn enable_two_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    used_step: i64,
+) -> Result<Vec<String>, anyhow::Error> {
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        "UPDATE user_two_factor SET enabled_at = now(), last_used_step = $2 WHERE user_id = $1",
+        user_id,
+        used_step
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication.")?;
+    sqlx::query!(
+        "DELETE FROM two_factor_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete old recovery codes.")?;
+    for code in &recovery_codes {
+        sqlx::query!(
+            "INSERT INTO two_factor_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            user_id,
+            compute_recovery_code_hash(code)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store a recovery code.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    // Recovery codes are removed by the foreign key cascade.
+    sqlx::query!("DELETE FROM user_two_factor WHERE user_id = $1", user_id)
+        .execute(pool)
+        .await
+        .context("Failed to disable two-factor authentication.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Record used TOTP step", skip(pool))]
+pub async fn record_used_step(
+    pool: &PgPool,
+    user_id: Uuid,
+    step: i64,
+) -> Result<bool, anyhow::Error> {
+    // Compare-and-set, so that two concurrent requests cannot both use the same code.
+    let updated = sqlx::query!(
+        r#"
+        UPDATE user_two_factor
+        SET last_used_step = $2
+        WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the used TOTP step.")?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// Marks a recovery code as used. Returns `false` if it is unknown or was already used.
+#[tracing::instrument(name = "Use a recovery code", skip(pool, code))]
+pub async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE two_factor_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        compute_recovery_code_hash(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code.")?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// Recovery codes are random, so like API tokens they only need a fast hash.
+fn compute_recovery_code_hash(code: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(code.trim().to_lowercase().as_bytes())
+    )
+}
+
+fn generate_recovery_code() -> String {
+    let code: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect::<String>()
+        .to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SecretCipher, TotpSecret};
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_none, assert_some_eq};
+
+    /// The SHA-1 secret used by the RFC 6238 test vectors.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        // RFC 6238 lists 8-digit codes; authenticator apps use the last 6 digits.
+        let vectors = [
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_111_111_111, "050471"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+        ];
+        for (timestamp, code) in vectors.iter() {
+            assert_eq!(rfc_secret().code_at(Utc.timestamp(*timestamp, 0)), *code);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_steps_are_accepted() {
+        let now = Utc.timestamp(1_111_111_111, 0);
+        let previous_code = rfc_secret().code_at(Utc.timestamp(1_111_111_111 - 30, 0));
+        let next_code = rfc_secret().code_at(Utc.timestamp(1_111_111_111 + 30, 0));
+
+        assert!(rfc_secret().verify(&previous_code, now, None).is_some());
+        assert!(rfc_secret().verify(&next_code, now, None).is_some());
+    }
+
+    #[test]
+    fn stale_codes_are_rejected() {
+        let now = Utc.timestamp(1_111_111_111, 0);
+        let stale_code = rfc_secret().code_at(Utc.timestamp(1_111_111_111 - 90, 0));
+
+        assert_none!(rfc_secret().verify(&stale_code, now, None));
+    }
+
+    #[test]
+    fn codes_cannot_be_replayed() {
+        let now = Utc.timestamp(1_111_111_111, 0);
+        let code = rfc_secret().code_at(now);
+        let step = 1_111_111_111 / 30;
+
+        assert_some_eq!(rfc_secret().verify(&code, now, None), step);
+        assert_none!(rfc_secret().verify(&code, now, Some(step)));
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        let secret = TotpSecret::generate();
+        let decoded = TotpSecret::from_base32(&secret.to_base32()).unwrap();
+
+        assert_eq!(secret.0, decoded.0);
+        assert_eq!(rfc_secret().to_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn provisioning_uris_follow_the_key_uri_format() {
+        let uri = rfc_secret().provisioning_uri("zero2prod", "ursula le guin");
+
+        assert_eq!(
+            uri,
+            "otpauth://totp/zero2prod:ursula%20le%20guin?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+            &issuer=zero2prod&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn secrets_survive_an_encryption_round_trip() {
+        let cipher = SecretCipher::new(&base64::encode([7u8; 32])).unwrap();
+
+        let sealed = cipher.encrypt(b"12345678901234567890").unwrap();
+
+        assert!(!sealed.contains("12345678901234567890"));
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), b"12345678901234567890");
+    }
+
+    #[test]
+    fn tampered_secrets_are_rejected() {
+        let cipher = SecretCipher::new(&base64::encode([7u8; 32])).unwrap();
+        let mut sealed = base64::decode(cipher.encrypt(b"secret").unwrap()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        assert!(cipher.decrypt(&base64::encode(sealed)).is_err());
+    }
+}
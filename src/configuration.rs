@@ -1,22 +1,42 @@
+use crate::authentication::SecretCipher;
 use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::secret::Secret;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 use std::convert::{TryFrom, TryInto};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-#[derive(serde::Deserialize, Clone)]
+mod validation;
+
+pub use validation::ValidationReport;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub rate_limit: RateLimitSettings,
+    pub challenge: ChallengeSettings,
+    pub login_protection: LoginProtectionSettings,
+    pub two_factor: TwoFactorSettings,
+    pub telemetry: TelemetrySettings,
+    pub metrics: MetricsSettings,
+    pub health: HealthSettings,
+    pub reload: ReloadSettings,
+    pub shutdown: ShutdownSettings,
+    pub security: SecuritySettings,
+    pub limits: LimitSettings,
+    pub pages: PageSettings,
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
-    pub authorization_token: String,
+    pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
 }
 
@@ -30,18 +50,274 @@ impl EmailClientSettings {
     }
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct RateLimitSettings {
+    pub store: RateLimitStoreKind,
+    pub per_ip: TokenBucketSettings,
+    pub per_email: TokenBucketSettings,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// Buckets live in the process. Each replica enforces its own limits.
+    Memory,
+    /// Buckets live in the `rate_limit_buckets` table and are shared across replicas.
+    Postgres,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct TokenBucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    /// One token is added back to the bucket every `refill_interval_seconds`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_interval_seconds: u64,
+}
+
+impl TokenBucketSettings {
+    /// Tokens added per second.
+    pub fn refill_rate(&self) -> f64 {
+        1.0 / self.refill_interval_seconds as f64
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct ChallengeSettings {
+    pub provider: ChallengeProvider,
+    /// Response accepted by the `stub` provider.
+    pub stub_response: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeProvider {
+    Disabled,
+    Stub,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct LoginProtectionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failed_attempts_per_user: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failed_attempts_per_ip: u32,
+    /// Length of the first lockout. Each further failed attempt doubles it.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_lockout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lockout_seconds: u64,
+    /// Failed attempts older than this are forgotten.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub attempt_window_seconds: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct TwoFactorSettings {
+    /// Shown next to the account name in authenticator apps.
+    pub issuer: String,
+    /// base64-encoded 32-byte key used to encrypt TOTP secrets at rest.
+    pub encryption_key: Secret<String>,
+}
+
+impl TwoFactorSettings {
+    pub fn cipher(&self) -> Result<SecretCipher, anyhow::Error> {
+        SecretCipher::new(self.encryption_key.expose_secret())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct TelemetrySettings {
+    /// Default log filter, in `RUST_LOG` syntax. `RUST_LOG` takes precedence at startup.
+    pub log_filter: String,
+    pub otlp: OtlpSettings,
+}
+
+/// Export of spans as distributed traces, to an OpenTelemetry collector.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct OtlpSettings {
+    pub enabled: bool,
+    /// Base URL of the collector's OTLP/HTTP receiver. Spans are sent to `{endpoint}/v1/traces`.
+    pub endpoint: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+impl OtlpSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// Where Prometheus metrics are exposed.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct MetricsSettings {
+    /// Serve `/metrics` on `admin_port` only, rather than next to the API, so that it can be
+    /// kept off the public network.
+    pub serve_on_admin_port: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub admin_port: u16,
+}
+
+/// Checks behind `/health/ready`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct HealthSettings {
+    /// Applies to each check separately.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// Also require the email provider to be reachable. Off by default, so that an outage of
+    /// the provider does not take the whole application out of rotation.
+    pub check_email_provider: bool,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// How configuration changes are picked up without a restart. A reload is always triggered by
+/// `SIGHUP`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct ReloadSettings {
+    /// Also reload when a configuration file changes.
+    pub watch_config_files: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub watch_interval_seconds: u64,
+}
+
+/// How long a stopping application waits for in-flight work. A shutdown is triggered by
+/// `SIGTERM` or `SIGINT`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct ShutdownSettings {
+    /// Deadline for in-flight requests and email sends. Whatever is still running then is
+    /// cancelled.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_seconds: u64,
+}
+
+impl ShutdownSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_seconds)
+    }
+}
+
+/// Browser-facing protections: security headers, cross-origin access and CSRF checks.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct SecuritySettings {
+    /// Origins, e.g. `https://blog.example.com`, whose pages may call `POST /subscriptions`.
+    pub cors_allowed_origins: Vec<String>,
+    /// How long browsers may cache the answer to a preflight request.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cors_max_age_seconds: u64,
+    /// Sent as `Content-Security-Policy`. An empty value leaves the header out, as for the
+    /// other headers below.
+    pub content_security_policy: String,
+    /// Sent as `X-Frame-Options`.
+    pub frame_options: String,
+    /// Sent as `Referrer-Policy`.
+    pub referrer_policy: String,
+}
+
+/// Bounds on the work a single request, or all of them together, can cause.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct LimitSettings {
+    /// Requests beyond this many in flight are turned away with `503 Service Unavailable`.
+    /// Health checks are exempt.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrent_requests: usize,
+    /// Sent as `Retry-After` to the requests that are turned away.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_after_seconds: u64,
+    /// Applies to every route without limits of its own.
+    pub default: RouteLimitSettings,
+    /// `POST /subscriptions`.
+    pub subscriptions: RouteLimitSettings,
+    /// `POST /newsletters`.
+    pub newsletters: RouteLimitSettings,
+    /// `POST /admin/imports`. The body is a CSV file.
+    pub imports: RouteLimitSettings,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct RouteLimitSettings {
+    /// Requests still running after this long are cancelled.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// Largest JSON or form body accepted.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_body_bytes: usize,
+}
+
+impl RouteLimitSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// Server-rendered pages shown to subscribers: the signup form and the outcome of their clicks.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct PageSettings {
+    /// Directory of templates overriding the built-in ones, file by file. Empty to use the
+    /// built-in templates only.
+    pub template_directory: String,
+    /// Confirmation links older than this are rejected, and the subscriber has to sign up again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_link_ttl_hours: u64,
+    /// Version of the consent wording on the signup form, recorded with every signup as proof
+    /// of what the subscriber agreed to. Change it whenever the wording changes.
+    pub consent_text_version: String,
+}
+
+impl PageSettings {
+    pub fn confirmation_link_ttl(&self) -> Duration {
+        Duration::from_secs(self.confirmation_link_ttl_hours * 60 * 60)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub tls: TlsSettings,
+    /// IP addresses or CIDR ranges of the proxies in front of the application, such as a load
+    /// balancer. Only their `X-Forwarded-For` headers are used to tell the client's address.
+    pub trusted_proxies: Vec<String>,
+}
+
+/// HTTPS termination, for deployments without a proxy in front of the application.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct TlsSettings {
+    /// Serve HTTPS, rather than plain HTTP, on `application.port`.
+    pub enabled: bool,
+    /// PEM-encoded certificate chain, leaf first.
+    pub certificate_path: String,
+    /// PEM-encoded PKCS#8 or RSA private key.
+    pub private_key_path: String,
+    /// The certificate and key are reloaded when either file changes, checking this often.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub watch_interval_seconds: u64,
+    /// Also listen for plain HTTP on `redirect_port`, redirecting every request to HTTPS.
+    pub redirect_http: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub redirect_port: u16,
+    /// `max-age` of the `Strict-Transport-Security` header. Zero leaves the header out.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hsts_max_age_seconds: u64,
+}
+
+impl TlsSettings {
+    pub fn watch_interval(&self) -> Duration {
+        Duration::from_secs(self.watch_interval_seconds)
+    }
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub struct DatabaseSettings {
     pub username: String,
-    pub password: String,
+    pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
@@ -59,7 +335,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password)
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -71,16 +347,23 @@ impl DatabaseSettings {
     }
 }
 
+/// Selects `configuration/{environment}.yaml`, layered on top of `base.yaml`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Environment {
     Local,
+    Staging,
     Production,
+    /// Any other environment with a configuration file of its own, e.g. `qa`.
+    Custom(String),
 }
 
 impl Environment {
     pub fn as_str(&self) -> &str {
         match self {
             Environment::Local => "local",
+            Environment::Staging => "staging",
             Environment::Production => "production",
+            Environment::Custom(name) => name,
         }
     }
 }
@@ -92,37 +375,261 @@ impl TryFrom<String> for Environment {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.as_str() {
             "local" => Ok(Self::Local),
+            "staging" => Ok(Self::Staging),
             "production" => Ok(Self::Production),
+            "base" => Err("`base` is shared by every environment and cannot be selected.".into()),
+            other
+                if !other.is_empty()
+                    && other
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Ok(Self::Custom(other.into()))
+            }
             other => Err(format!(
-                "{} is not a supported environment. Use either `local` or `production`.",
+                "{} is not a valid environment name. Use `local`, `staging`, `production` or the \
+                name of another file in the configuration directory.",
                 other
             )),
         }
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+#[derive(thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("Invalid APP_ENVIRONMENT: {0}")]
+    InvalidEnvironment(String),
+    #[error("There is no configuration file for the `{environment}` environment at {path}.")]
+    MissingEnvironmentFile { environment: String, path: String },
+    #[error("Failed to read {variable} from {path}.")]
+    SecretFileError {
+        variable: String,
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Both {variable} and {variable}_FILE are set. Use only one of them.")]
+    ConflictingSecret { variable: String },
+    #[error("Failed to determine the current directory.")]
+    CurrentDirectoryError(#[source] std::io::Error),
+    #[error("Failed to load the configuration.")]
+    LoadError(#[from] config::ConfigError),
+    #[error(transparent)]
+    ValidationError(#[from] ValidationReport),
+}
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    load_configuration(None)
+}
+
+/// Loads and validates the configuration, layering (lowest priority first):
+/// `base.yaml`, the `APP_ENVIRONMENT` file, the optional `config_file` given with `--config`,
+/// and `APP_`-prefixed environment variables.
+pub fn load_configuration(config_file: Option<&Path>) -> Result<Settings, ConfigurationError> {
+    let environment = environment()?;
     let mut settings = config::Config::default();
 
-    let base_path = std::env::current_dir().expect("Failed to determine current directory");
-    let configuration_directory = base_path.join("configuration");
+    for file in configuration_files(&environment, config_file)? {
+        settings.merge(config::File::from(file).required(true))?;
+    }
+
+    // Merge settings with APP prefix.
+    // E.g `APP_APPLICATION__PORT=5001` would set `Settings.application.port`
+    settings.merge(config::Environment::with_prefix("app").separator("__"))?;
 
-    settings.merge(config::File::from(configuration_directory.join("base")).required(true))?;
+    // Secrets mounted as files take precedence, e.g. `APP_DATABASE__PASSWORD_FILE=/run/secrets/db`.
+    for (key, value) in read_secret_files(std::env::vars())? {
+        settings.set(&key, value)?;
+    }
 
+    let settings = Settings::deserialize_from(&settings)?;
+    settings.validate_for(&environment)?;
+    Ok(settings)
+}
+
+/// The environment selected by `APP_ENVIRONMENT`, `local` by default.
+///
+/// Fails if the environment has no configuration file.
+pub fn environment() -> Result<Environment, ConfigurationError> {
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
+        .map_err(ConfigurationError::InvalidEnvironment)?;
 
-    // Merge environment-specific configuration, e.g. local.yml, production.yml.
-    settings.merge(
-        config::File::from(configuration_directory.join(environment.as_str())).required(true),
-    )?;
+    let environment_file = environment_file(&environment)?;
+    if !environment_file.exists() {
+        return Err(ConfigurationError::MissingEnvironmentFile {
+            environment: environment.as_str().into(),
+            path: environment_file.display().to_string(),
+        });
+    }
+    Ok(environment)
+}
 
-    // Merge settings with APP prefix.
-    // E.g `APP_APPLICATION__PORT=5001` would set `Settings.application.port`
-    settings.merge(config::Environment::with_prefix("app").separator("__"))?;
+/// The YAML files merged by `load_configuration`, in order.
+pub fn configuration_files(
+    environment: &Environment,
+    config_file: Option<&Path>,
+) -> Result<Vec<PathBuf>, ConfigurationError> {
+    let mut files = vec![
+        configuration_directory()?.join("base.yaml"),
+        environment_file(environment)?,
+    ];
+    files.extend(config_file.map(Path::to_path_buf));
+    Ok(files)
+}
+
+fn configuration_directory() -> Result<PathBuf, ConfigurationError> {
+    let base_path = std::env::current_dir().map_err(ConfigurationError::CurrentDirectoryError)?;
+    Ok(base_path.join("configuration"))
+}
+
+// Environment-specific configuration, e.g. local.yaml, production.yaml.
+fn environment_file(environment: &Environment) -> Result<PathBuf, ConfigurationError> {
+    Ok(configuration_directory()?.join(format!("{}.yaml", environment.as_str())))
+}
+
+/// Reads the files pointed at by `APP_*_FILE` variables, e.g. Docker or Kubernetes secret mounts.
+///
+/// Returns `(key, file contents)` pairs, with keys in the dotted form used by `config`.
+fn read_secret_files(
+    variables: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<(String, String)>, ConfigurationError> {
+    let variables: Vec<(String, String)> = variables.collect();
+    let mut secrets = Vec::new();
+    for (variable, path) in &variables {
+        let name = match variable
+            .strip_prefix("APP_")
+            .and_then(|v| v.strip_suffix("_FILE"))
+        {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let plain_variable = format!("APP_{}", name);
+        if variables.iter().any(|(v, _)| v == &plain_variable) {
+            return Err(ConfigurationError::ConflictingSecret {
+                variable: plain_variable,
+            });
+        }
+        let value = std::fs::read_to_string(path).map_err(|source| {
+            ConfigurationError::SecretFileError {
+                variable: variable.clone(),
+                path: path.clone(),
+                source,
+            }
+        })?;
+        let key = name.to_lowercase().replace("__", ".");
+        // Files written by editors or `echo` usually end with a newline that is not part of the secret.
+        secrets.push((key, value.trim_end_matches(&['\r', '\n'][..]).to_string()));
+    }
+    Ok(secrets)
+}
+
+impl Settings {
+    /// The effective configuration as pretty-printed JSON, with secrets redacted.
+    pub fn redacted(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the configuration.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        configuration_files, get_configuration, read_secret_files, ConfigurationError, Environment,
+        Settings,
+    };
+    use std::io::Write;
+
+    fn secret_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn secrets_do_not_appear_in_debug_output() {
+        let settings = get_configuration().unwrap();
+        let token = settings.email_client.authorization_token.expose_secret();
 
-    // Attempt to deserialize into Settings struct
-    settings.try_into()
+        assert!(!format!("{:?}", settings).contains(token.as_str()));
+        assert!(!settings.redacted().contains(token.as_str()));
+    }
+
+    #[test]
+    fn every_deserialization_problem_is_reported_with_its_key() {
+        let mut config = config::Config::default();
+        for file in configuration_files(&Environment::Local, None).unwrap() {
+            config.merge(config::File::from(file)).unwrap();
+        }
+        config.set("application.port", "not a port").unwrap();
+        config.set("health.timeout_milliseconds", "soon").unwrap();
+
+        let report = Settings::deserialize_from(&config).unwrap_err();
+
+        assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("application.port: "));
+        assert!(report.problems[1].starts_with("health.timeout_milliseconds: "));
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let path = secret_file("s3cr3t\n");
+        let variables = vec![
+            ("HOME".to_string(), "/root".to_string()),
+            (
+                "APP_DATABASE__PASSWORD_FILE".to_string(),
+                path.display().to_string(),
+            ),
+        ];
+
+        let secrets = read_secret_files(variables.into_iter()).unwrap();
+
+        assert_eq!(
+            secrets,
+            vec![("database.password".to_string(), "s3cr3t".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_secret_cannot_be_set_both_directly_and_from_a_file() {
+        let path = secret_file("s3cr3t");
+        let variables = vec![
+            ("APP_DATABASE__PASSWORD".to_string(), "other".to_string()),
+            (
+                "APP_DATABASE__PASSWORD_FILE".to_string(),
+                path.display().to_string(),
+            ),
+        ];
+
+        let result = read_secret_files(variables.into_iter());
+
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::ConflictingSecret { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_secret_files_are_reported() {
+        let variables = vec![(
+            "APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE".to_string(),
+            "/does/not/exist".to_string(),
+        )];
+
+        let result = read_secret_files(variables.into_iter());
+
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::SecretFileError { .. })
+        ));
+    }
 }
@@ -0,0 +1,401 @@
+use crate::configuration::{
+    ApplicationSettings, ChallengeProvider, ChallengeSettings, DatabaseSettings,
+    EmailClientSettings, Environment, HealthSettings, LimitSettings, LoginProtectionSettings,
+    MetricsSettings, PageSettings, RateLimitSettings, ReloadSettings, SecuritySettings, Settings,
+    ShutdownSettings, TelemetrySettings, TokenBucketSettings, TwoFactorSettings,
+};
+use crate::utils::TrustedProxies;
+use actix_http::header::HeaderValue;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+
+/// Every problem found in a configuration, so that they can all be fixed in one go.
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("Invalid configuration:\n{}", .problems.iter().map(|p| format!("  - {}", p)).collect::<Vec<_>>().join("\n"))]
+pub struct ValidationReport {
+    pub problems: Vec<String>,
+}
+
+// The two-factor encryption key committed in `local.yaml`, for development only.
+const DEVELOPMENT_ENCRYPTION_KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+impl Settings {
+    /// Deserializes the merged configuration, after checking it section by section so that the
+    /// problems of every section are reported at once, each with the path of the offending key.
+    pub(super) fn deserialize_from(config: &config::Config) -> Result<Self, ValidationReport> {
+        let mut problems = Vec::new();
+        check_section::<DatabaseSettings>(config, "database", &mut problems);
+        check_section::<ApplicationSettings>(config, "application", &mut problems);
+        check_section::<EmailClientSettings>(config, "email_client", &mut problems);
+        check_section::<RateLimitSettings>(config, "rate_limit", &mut problems);
+        check_section::<ChallengeSettings>(config, "challenge", &mut problems);
+        check_section::<LoginProtectionSettings>(config, "login_protection", &mut problems);
+        check_section::<TwoFactorSettings>(config, "two_factor", &mut problems);
+        check_section::<TelemetrySettings>(config, "telemetry", &mut problems);
+        check_section::<MetricsSettings>(config, "metrics", &mut problems);
+        check_section::<HealthSettings>(config, "health", &mut problems);
+        check_section::<ReloadSettings>(config, "reload", &mut problems);
+        check_section::<ShutdownSettings>(config, "shutdown", &mut problems);
+        check_section::<SecuritySettings>(config, "security", &mut problems);
+        check_section::<LimitSettings>(config, "limits", &mut problems);
+        check_section::<PageSettings>(config, "pages", &mut problems);
+        ValidationReport::from_problems(problems)?;
+        config.clone().try_into().map_err(|e| ValidationReport {
+            problems: vec![e.to_string()],
+        })
+    }
+
+    /// Checks the settings that deserialization alone cannot catch.
+    pub fn validate(&self) -> Result<(), ValidationReport> {
+        ValidationReport::from_problems(self.problems())
+    }
+
+    /// Like `validate`, also rejecting development-only secrets outside the `local` environment.
+    pub fn validate_for(&self, environment: &Environment) -> Result<(), ValidationReport> {
+        let mut problems = self.problems();
+        if environment != &Environment::Local
+            && self.two_factor.encryption_key.expose_secret() == DEVELOPMENT_ENCRYPTION_KEY
+        {
+            problems.push(format!(
+                "two_factor.encryption_key is the public development key, which is only allowed \
+                in the `local` environment, not in `{}`.",
+                environment.as_str()
+            ));
+        }
+        ValidationReport::from_problems(problems)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        check_url(
+            &mut problems,
+            "application.base_url",
+            &self.application.base_url,
+        );
+        if self.application.host.trim().is_empty() {
+            problems.push("application.host must not be empty.".into());
+        }
+        if self.application.port != 0
+            && self.application.port == self.database.port
+            && is_same_host(&self.application.host, &self.database.host)
+        {
+            problems.push(format!(
+                "application.port and database.port are both {} on the same host.",
+                self.application.port
+            ));
+        }
+
+        if let Err(e) = TrustedProxies::parse(&self.application.trusted_proxies) {
+            problems.push(format!("application.trusted_proxies: {}", e));
+        }
+
+        let tls = &self.application.tls;
+        if tls.enabled {
+            if tls.certificate_path.trim().is_empty() || tls.private_key_path.trim().is_empty() {
+                problems.push(
+                    "application.tls.certificate_path and private_key_path are required when TLS \
+                    is enabled."
+                        .into(),
+                );
+            }
+            if tls.watch_interval_seconds == 0 {
+                problems.push(
+                    "application.tls.watch_interval_seconds must be greater than zero.".into(),
+                );
+            }
+            if tls.redirect_http
+                && tls.redirect_port != 0
+                && tls.redirect_port == self.application.port
+            {
+                problems.push(format!(
+                    "application.tls.redirect_port and application.port are both {}.",
+                    self.application.port
+                ));
+            }
+        }
+
+        check_url(
+            &mut problems,
+            "email_client.base_url",
+            &self.email_client.base_url,
+        );
+        if let Err(e) = self.email_client.sender() {
+            problems.push(format!("email_client.sender_email: {}", e));
+        }
+        if self.email_client.timeout_milliseconds == 0 {
+            problems.push("email_client.timeout_milliseconds must be greater than zero.".into());
+        }
+
+        check_token_bucket(&mut problems, "rate_limit.per_ip", &self.rate_limit.per_ip);
+        check_token_bucket(
+            &mut problems,
+            "rate_limit.per_email",
+            &self.rate_limit.per_email,
+        );
+
+        if self.challenge.provider == ChallengeProvider::Stub
+            && self.challenge.stub_response.is_none()
+        {
+            problems.push("challenge.stub_response is required by the `stub` provider.".into());
+        }
+
+        let login_protection = &self.login_protection;
+        if login_protection.max_failed_attempts_per_user == 0
+            || login_protection.max_failed_attempts_per_ip == 0
+        {
+            problems
+                .push("login_protection.max_failed_attempts_* must be greater than zero.".into());
+        }
+        if login_protection.base_lockout_seconds > login_protection.max_lockout_seconds {
+            problems.push(
+                "login_protection.base_lockout_seconds must not exceed max_lockout_seconds.".into(),
+            );
+        }
+
+        if self.two_factor.issuer.trim().is_empty() {
+            problems.push("two_factor.issuer must not be empty.".into());
+        }
+        if let Err(e) = self.two_factor.cipher() {
+            problems.push(format!("two_factor.encryption_key: {}", e));
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.telemetry.log_filter) {
+            problems.push(format!("telemetry.log_filter is invalid: {}", e));
+        }
+        if self.telemetry.otlp.enabled {
+            check_url(
+                &mut problems,
+                "telemetry.otlp.endpoint",
+                &self.telemetry.otlp.endpoint,
+            );
+            if self.telemetry.otlp.timeout_milliseconds == 0 {
+                problems
+                    .push("telemetry.otlp.timeout_milliseconds must be greater than zero.".into());
+            }
+        }
+        if self.health.timeout_milliseconds == 0 {
+            problems.push("health.timeout_milliseconds must be greater than zero.".into());
+        }
+        if self.metrics.serve_on_admin_port
+            && self.metrics.admin_port != 0
+            && self.metrics.admin_port == self.application.port
+        {
+            problems.push(format!(
+                "metrics.admin_port and application.port are both {}.",
+                self.application.port
+            ));
+        }
+        if self.reload.watch_config_files && self.reload.watch_interval_seconds == 0 {
+            problems.push("reload.watch_interval_seconds must be greater than zero.".into());
+        }
+
+        for origin in &self.security.cors_allowed_origins {
+            if !is_origin(origin) {
+                problems.push(format!(
+                    "security.cors_allowed_origins must hold origins such as \
+                    `https://example.com`, without a path or trailing slash. Got `{}`.",
+                    origin
+                ));
+            }
+        }
+        for (key, value) in [
+            (
+                "security.content_security_policy",
+                &self.security.content_security_policy,
+            ),
+            ("security.frame_options", &self.security.frame_options),
+            ("security.referrer_policy", &self.security.referrer_policy),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!("{} is not a valid header value.", key));
+            }
+        }
+
+        if self.limits.max_concurrent_requests == 0 {
+            problems.push("limits.max_concurrent_requests must be greater than zero.".into());
+        }
+        for (key, route) in [
+            ("limits.default", &self.limits.default),
+            ("limits.subscriptions", &self.limits.subscriptions),
+            ("limits.newsletters", &self.limits.newsletters),
+            ("limits.imports", &self.limits.imports),
+        ] {
+            if route.timeout_milliseconds == 0 || route.max_body_bytes == 0 {
+                problems.push(format!(
+                    "{}.timeout_milliseconds and max_body_bytes must be greater than zero.",
+                    key
+                ));
+            }
+        }
+
+        let template_directory = &self.pages.template_directory;
+        if !template_directory.is_empty() && !std::path::Path::new(template_directory).is_dir() {
+            problems.push(format!(
+                "pages.template_directory {} is not a directory.",
+                template_directory
+            ));
+        }
+        if self.pages.confirmation_link_ttl_hours == 0 {
+            problems.push("pages.confirmation_link_ttl_hours must be greater than zero.".into());
+        }
+        if self.pages.consent_text_version.trim().is_empty() {
+            problems.push("pages.consent_text_version must not be empty.".into());
+        }
+
+        problems
+    }
+}
+
+fn check_section<T: DeserializeOwned>(
+    config: &config::Config,
+    key: &str,
+    problems: &mut Vec<String>,
+) {
+    let value = match config.get::<config::Value>(key) {
+        Ok(value) => value,
+        Err(config::ConfigError::NotFound(_)) => {
+            problems.push(format!("{} is missing.", key));
+            return;
+        }
+        Err(e) => {
+            problems.push(format!("{}: {}", key, e));
+            return;
+        }
+    };
+    if let Err(e) = serde_path_to_error::deserialize::<_, T>(value) {
+        // The path is relative to the section, and `.` when the section itself is invalid.
+        let path = match e.path().to_string().as_str() {
+            "." => key.to_string(),
+            path => format!("{}.{}", key, path),
+        };
+        problems.push(format!("{}: {}", path, e.into_inner()));
+    }
+}
+
+impl ValidationReport {
+    fn from_problems(problems: Vec<String>) -> Result<(), Self> {
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Self { problems })
+        }
+    }
+}
+
+fn check_url(problems: &mut Vec<String>, key: &str, value: &str) {
+    match Url::parse(value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+        Ok(_) => problems.push(format!("{} must be an http(s) URL, got `{}`.", key, value)),
+        Err(e) => problems.push(format!("{} is not a valid URL ({}): `{}`.", key, e, value)),
+    }
+}
+
+fn is_origin(value: &str) -> bool {
+    match Url::parse(value) {
+        Ok(url) => {
+            (url.scheme() == "http" || url.scheme() == "https")
+                && url.origin().ascii_serialization() == value
+        }
+        Err(_) => false,
+    }
+}
+
+fn check_token_bucket(problems: &mut Vec<String>, key: &str, bucket: &TokenBucketSettings) {
+    if bucket.capacity == 0 {
+        problems.push(format!("{}.capacity must be greater than zero.", key));
+    }
+    if bucket.refill_interval_seconds == 0 {
+        problems.push(format!(
+            "{}.refill_interval_seconds must be greater than zero.",
+            key
+        ));
+    }
+}
+
+fn is_same_host(a: &str, b: &str) -> bool {
+    let is_local = |host: &str| matches!(host, "localhost" | "127.0.0.1" | "0.0.0.0" | "::1");
+    a == b || (is_local(a) && is_local(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{get_configuration, Environment};
+    use crate::secret::Secret;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn the_base_configuration_is_valid() {
+        assert_ok!(get_configuration());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut settings = get_configuration().unwrap();
+        settings.application.base_url = "not a url".into();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.application.port = settings.database.port;
+        settings.application.host = "localhost".into();
+
+        let report = settings.validate().unwrap_err();
+
+        assert_eq!(report.problems.len(), 4, "{}", report);
+        assert!(report.problems[0].starts_with("application.base_url"));
+        assert!(report.problems[1].starts_with("application.port"));
+        assert!(report.problems[2].starts_with("email_client.sender_email"));
+        assert!(report.problems[3].starts_with("email_client.timeout_milliseconds"));
+    }
+
+    #[test]
+    fn non_http_urls_are_rejected() {
+        let mut settings = get_configuration().unwrap();
+        settings.email_client.base_url = "ftp://example.com".into();
+
+        assert_err!(settings.validate());
+    }
+
+    #[test]
+    fn invalid_encryption_keys_are_rejected() {
+        let mut settings = get_configuration().unwrap();
+        settings.two_factor.encryption_key = Secret::new("c2hvcnQ=".into());
+
+        let report = settings.validate().unwrap_err();
+
+        assert!(report.problems[0].starts_with("two_factor.encryption_key"));
+    }
+
+    #[test]
+    fn the_development_encryption_key_is_rejected_outside_local() {
+        let settings = get_configuration().unwrap();
+        assert_ok!(settings.validate_for(&Environment::Local));
+
+        let report = settings.validate_for(&Environment::Production).unwrap_err();
+
+        assert!(report.problems[0].starts_with("two_factor.encryption_key"));
+    }
+
+    #[test]
+    fn the_otlp_endpoint_is_only_checked_when_export_is_enabled() {
+        let mut settings = get_configuration().unwrap();
+        settings.telemetry.otlp.endpoint = "collector:4318".into();
+        assert_ok!(settings.validate());
+
+        settings.telemetry.otlp.enabled = true;
+        let report = settings.validate().unwrap_err();
+
+        assert!(report.problems[0].starts_with("telemetry.otlp.endpoint"));
+    }
+
+    #[test]
+    fn cors_origins_must_not_have_a_path() {
+        let mut settings = get_configuration().unwrap();
+        settings.security.cors_allowed_origins = vec!["https://blog.example.com".into()];
+        assert_ok!(settings.validate());
+
+        settings.security.cors_allowed_origins = vec!["https://blog.example.com/".into()];
+        let report = settings.validate().unwrap_err();
+
+        assert!(report.problems[0].starts_with("security.cors_allowed_origins"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::utils::client_ip;
+use actix_http::header;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres};
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+/// Steps of the double opt-in recorded in the `consent_events` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentEventKind {
+    Signup,
+    Confirmation,
+}
+
+impl ConsentEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentEventKind::Signup => "signup",
+            ConsentEventKind::Confirmation => "confirmation",
+        }
+    }
+}
+
+impl TryFrom<String> for ConsentEventKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "signup" => Ok(Self::Signup),
+            "confirmation" => Ok(Self::Confirmation),
+            other => Err(format!("{} is not a supported consent event.", other)),
+        }
+    }
+}
+
+/// What the subscriber agreed to when signing up. Confirmations carry no terms.
+pub struct Terms<'a> {
+    /// Where the subscriber signed up, e.g. the page showing the form.
+    pub source: &'a str,
+    /// Unknown for subscribers imported from elsewhere.
+    pub consent_text_version: Option<&'a str>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ConsentEvent {
+    pub kind: ConsentEventKind,
+    pub occurred_at: DateTime<Utc>,
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+    pub consent_text_version: Option<String>,
+}
+
+/// Where a signup comes from: the source the form declares, else the page it was posted from.
+pub fn signup_source(declared: Option<&str>, request: &HttpRequest) -> String {
+    declared
+        .filter(|source| !source.trim().is_empty())
+        .map(str::to_owned)
+        .or_else(|| {
+            request
+                .headers()
+                .get(header::REFERER)
+                .and_then(|referer| referer.to_str().ok())
+                .map(str::to_owned)
+        })
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Appends a step of the double opt-in, with the IP and user agent of `request`, to the
+/// subscriber's consent history.
+///
+/// Pass the transaction that performs the step, so that it is only recorded if it is committed.
+pub async fn record_consent_event<'e, E>(
+    executor: E,
+    request: &HttpRequest,
+    subscriber_id: Uuid,
+    kind: ConsentEventKind,
+    terms: Option<Terms<'_>>,
+) -> Result<(), anyhow::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok());
+    insert_consent_event(
+        executor,
+        subscriber_id,
+        kind,
+        &client_ip(request),
+        user_agent,
+        terms,
+    )
+    .await
+}
+
+/// Like `record_consent_event`, for steps that do not happen within a request, such as imports.
+#[tracing::instrument(name = "Record consent event", skip(executor, ip, user_agent, terms))]
+pub async fn insert_consent_event<'e, E>(
+    executor: E,
+    subscriber_id: Uuid,
+    kind: ConsentEventKind,
+    ip: &str,
+    user_agent: Option<&str>,
+    terms: Option<Terms<'_>>,
+) -> Result<(), anyhow::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events
+            (event_id, subscriber_id, kind, occurred_at, ip, user_agent, source, consent_text_version)
+        VALUES ($1, $2, $3, now(), $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        kind.as_str(),
+        ip,
+        user_agent,
+        terms.as_ref().map(|terms| terms.source),
+        terms.as_ref().and_then(|terms| terms.consent_text_version)
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record a consent event.")?;
+    Ok(())
+}
+
+/// The subscriber's consent history, oldest first.
+#[tracing::instrument(name = "List consent events", skip(pool))]
+pub async fn list_consent_events(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentEvent>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT kind, occurred_at, ip, user_agent, source, consent_text_version
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at, event_id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch consent events.")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(ConsentEvent {
+                kind: row.kind.try_into().map_err(anyhow::Error::msg)?,
+                occurred_at: row.occurred_at,
+                ip: row.ip,
+                user_agent: row.user_agent,
+                source: row.source,
+                consent_text_version: row.consent_text_version,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConsentEventKind;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn kinds_round_trip_through_their_stored_name() {
+        for kind in &[ConsentEventKind::Signup, ConsentEventKind::Confirmation] {
+            assert_eq!(
+                ConsentEventKind::try_from(kind.as_str().to_string()),
+                Ok(*kind)
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Just enough RFC 4180 CSV to import and export subscribers.
+
+/// Splits CSV into records as the bytes come in, so that uploads never have to be held in
+/// memory as a whole.
+///
+/// Quoted fields may contain commas, line breaks and doubled quotes. Both `\n` and `\r\n` end
+/// records, blank lines are skipped and invalid UTF-8 is replaced.
+#[derive(Default)]
+pub struct CsvReader {
+    record: Vec<String>,
+    field: Vec<u8>,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    StartOfField,
+    Unquoted,
+    Quoted,
+    // A quote inside a quoted field: either the closing one or the first of a doubled quote.
+    QuoteInQuoted,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::StartOfField
+    }
+}
+
+impl CsvReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next chunk of input, returning the records it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        for &byte in chunk {
+            match (self.state, byte) {
+                (State::StartOfField, b'"') => self.state = State::Quoted,
+                (State::Quoted, b'"') => self.state = State::QuoteInQuoted,
+                (State::Quoted, _) => self.field.push(byte),
+                (State::QuoteInQuoted, b'"') => {
+                    self.field.push(b'"');
+                    self.state = State::Quoted;
+                }
+                // Also after a closing quote, where anything but a separator is taken as is.
+                (_, b',') => self.end_field(),
+                (_, b'\n') => {
+                    self.end_field();
+                    records.extend(self.end_record());
+                }
+                (_, b'\r') => {}
+                (_, _) => {
+                    self.field.push(byte);
+                    self.state = State::Unquoted;
+                }
+            }
+        }
+        records
+    }
+
+    /// The last record, if the input does not end with a line break.
+    pub fn finish(mut self) -> Option<Vec<String>> {
+        if self.state == State::StartOfField && self.record.is_empty() {
+            return None;
+        }
+        self.end_field();
+        self.end_record()
+    }
+
+    fn end_field(&mut self) {
+        let field = std::mem::take(&mut self.field);
+        self.record
+            .push(String::from_utf8_lossy(&field).into_owned());
+        self.state = State::StartOfField;
+    }
+
+    fn end_record(&mut self) -> Option<Vec<String>> {
+        let record = std::mem::take(&mut self.record);
+        if record.len() == 1 && record[0].is_empty() {
+            None
+        } else {
+            Some(record)
+        }
+    }
+}
+
+/// Appends one record, quoting the fields that need it, followed by `\r\n`.
+///
+/// Fields that spreadsheets would evaluate as a formula, such as a subscriber named
+/// `=HYPERLINK(...)`, are prefixed with `'` so that they are shown as text instead.
+pub fn write_record<S: AsRef<str>>(output: &mut String, fields: &[S]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        let field = field.as_ref();
+        let escaped;
+        let field = if field.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+            escaped = format!("'{}", field);
+            escaped.as_str()
+        } else {
+            field
+        };
+        if field.contains(&[',', '"', '\n', '\r'][..]) {
+            output.push('"');
+            output.push_str(&field.replace('"', "\"\""));
+            output.push('"');
+        } else {
+            output.push_str(field);
+        }
+    }
+    output.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_record, CsvReader};
+
+    fn read(chunks: &[&[u8]]) -> Vec<Vec<String>> {
+        let mut reader = CsvReader::new();
+        let mut records: Vec<_> = chunks.iter().flat_map(|chunk| reader.push(chunk)).collect();
+        records.extend(reader.finish());
+        records
+    }
+
+    #[test]
+    fn records_end_with_line_breaks_or_the_input() {
+        assert_eq!(
+            read(&[b"email,name\r\na@example.com,Ann\nb@example.com,Bob"]),
+            vec![
+                vec!["email", "name"],
+                vec!["a@example.com", "Ann"],
+                vec!["b@example.com", "Bob"],
+            ]
+        );
+        assert_eq!(read(&[b"a,b\n"]), vec![vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn quoted_fields_can_contain_separators_line_breaks_and_quotes() {
+        assert_eq!(
+            read(&[b"\"Le Guin, Ursula\",\"line\nbreak\",\"say \"\"hi\"\"\"\n"]),
+            vec![vec!["Le Guin, Ursula", "line\nbreak", "say \"hi\""]]
+        );
+    }
+
+    #[test]
+    fn empty_fields_are_kept_and_blank_lines_skipped() {
+        assert_eq!(
+            read(&[b"a,,\n\n\r\n,b\n"]),
+            vec![vec!["a", "", ""], vec!["", "b"]]
+        );
+    }
+
+    #[test]
+    fn records_can_span_chunks() {
+        let input = "\"ü, ö\",ß\nx,y\n".as_bytes();
+        for split in 0..input.len() {
+            assert_eq!(
+                read(&[&input[..split], &input[split..]]),
+                vec![vec!["ü, ö", "ß"], vec!["x", "y"]],
+                "split at {}",
+                split
+            );
+        }
+    }
+
+    #[test]
+    fn written_records_read_back_the_same() {
+        let record = vec!["plain", "with, comma", "with \"quotes\"", "two\nlines", ""];
+        let mut output = String::new();
+        write_record(&mut output, &record);
+        assert_eq!(read(&[output.as_bytes()]), vec![record]);
+    }
+
+    #[test]
+    fn formulas_are_written_as_text() {
+        let mut output = String::new();
+        write_record(
+            &mut output,
+            &["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx", "a=b"],
+        );
+        assert_eq!(
+            read(&[output.as_bytes()]),
+            vec![vec![
+                "'=1+1",
+                "'+1",
+                "'-1",
+                "'@SUM(A1)",
+                "'\tx",
+                "'\rx",
+                "a=b"
+            ]]
+        );
+    }
+}
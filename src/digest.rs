@@ -0,0 +1,220 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::i18n::{translate, Locale};
+use crate::routes::{preferences_token, EmailFooter};
+use crate::templates::escape_html;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often due digests are looked for.
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often a subscriber receives newsletter issues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestFrequency {
+    /// Each issue as soon as it is published.
+    Immediate,
+    /// The issues of the past week, bundled in a single email.
+    Weekly,
+}
+
+impl DigestFrequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestFrequency::Immediate => "immediate",
+            DigestFrequency::Weekly => "weekly",
+        }
+    }
+}
+
+impl TryFrom<String> for DigestFrequency {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "immediate" => Ok(Self::Immediate),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!("{} is not a supported digest frequency.", other)),
+        }
+    }
+}
+
+/// Keeps an issue sent to `list` for the next digest of its weekly subscribers, skipping those
+/// whose delivery is paused as immediate delivery does.
+#[tracing::instrument(name = "Queue an issue for digests", skip(pool, text, html))]
+pub async fn queue_for_digests(
+    pool: &PgPool,
+    list_id: Uuid,
+    title: &str,
+    text: &str,
+    html: &str,
+) -> Result<u64, anyhow::Error> {
+    let queued = sqlx::query!(
+        r#"
+        INSERT INTO digest_entries (subscriber_id, title, text_content, html_content, queued_at)
+        SELECT id, $2, $3, $4, now()
+        FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        WHERE status = 'confirmed'
+          AND digest_frequency = 'weekly'
+          AND list_memberships.list_id = $1
+          AND (paused_until IS NULL OR paused_until <= now())
+        "#,
+        list_id,
+        title,
+        text,
+        html
+    )
+    .execute(pool)
+    .await
+    .context("Failed to queue the issue for digests.")?
+    .rows_affected();
+    Ok(queued)
+}
+
+/// Runs `send_due_digests` every `DIGEST_CHECK_INTERVAL`, logging its failures.
+pub async fn send_digests_periodically(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    base_url: String,
+) {
+    let mut ticks = actix_web::rt::time::interval(DIGEST_CHECK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = send_due_digests(&pool, &email_client, &base_url).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send digests."
+            );
+        }
+    }
+}
+
+struct DueSubscriber {
+    id: Uuid,
+    email: String,
+    locale: String,
+    preferences_token: Option<String>,
+}
+
+/// Sends a digest to every subscriber whose oldest queued issue is a week old, and returns how
+/// many were sent.
+///
+/// Issues still queued when a subscriber switches back to immediate delivery go out in a last
+/// digest. A digest that cannot be sent stays queued and is tried again at the next check.
+#[tracing::instrument(name = "Send due digests", skip_all)]
+pub async fn send_due_digests(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<usize, anyhow::Error> {
+    let due = sqlx::query_as!(
+        DueSubscriber,
+        r#"
+        SELECT id, email, locale, preferences_token
+        FROM subscriptions
+        WHERE (paused_until IS NULL OR paused_until <= now())
+          AND EXISTS (
+              SELECT 1 FROM digest_entries
+              WHERE digest_entries.subscriber_id = subscriptions.id
+                AND queued_at <= now() - interval '7 days'
+          )
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscribers with a due digest.")?;
+    let mut sent = 0;
+    for subscriber in due {
+        match send_digest(pool, email_client, base_url, &subscriber).await {
+            Ok(()) => sent += 1,
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_id = %subscriber.id,
+                "Failed to send a digest."
+            ),
+        }
+    }
+    Ok(sent)
+}
+
+struct Entry {
+    title: String,
+    text_content: String,
+    html_content: String,
+    queued_at: DateTime<Utc>,
+}
+
+/// Dequeues the subscriber's issues and sends them. They are only deleted once the email is
+/// sent.
+async fn send_digest(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    subscriber: &DueSubscriber,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(subscriber.email.clone()).map_err(anyhow::Error::msg)?;
+    let locale = Locale::parse(&subscriber.locale).unwrap_or_default();
+    let token = match &subscriber.preferences_token {
+        Some(token) => token.clone(),
+        None => preferences_token(pool, subscriber.id)
+            .await
+            .context("Failed to create a preferences token.")?,
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let mut entries = sqlx::query_as!(
+        Entry,
+        r#"
+        DELETE FROM digest_entries
+        WHERE subscriber_id = $1
+        RETURNING title, text_content, html_content, queued_at
+        "#,
+        subscriber.id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to dequeue the digest entries.")?;
+    entries.sort_by_key(|entry| entry.queued_at);
+
+    let footer = EmailFooter::new(base_url, &token, locale);
+    let text_body: String = entries
+        .iter()
+        .map(|entry| format!("{}\n\n{}\n\n", entry.title, entry.text_content))
+        .collect();
+    let html_body = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "<h1>{}</h1>{}",
+                escape_html(&entry.title),
+                entry.html_content
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("<hr />");
+    email_client
+        .send_email(
+            &email,
+            &translate(locale, "digest-subject", &[]),
+            &format!("{}{}", text_body.trim_end(), footer.text),
+            &format!("{}{}", html_body, footer.html),
+        )
+        .await
+        .context("Failed to send the digest.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to dequeue a digest.")?;
+    Ok(())
+}
@@ -1,12 +1,43 @@
 use crate::domain::SubscriberEmail;
+use crate::metrics::EmailMetrics;
+use crate::secret::Secret;
+use crate::telemetry::inject_trace_context;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 pub struct EmailClient {
+    // Swapped as a whole on configuration reloads. In-flight requests keep using the
+    // configuration they started with.
+    inner: RwLock<Arc<EmailClientInner>>,
+    metrics: Option<EmailMetrics>,
+    // Awaited by graceful shutdowns.
+    sends_in_progress: AtomicUsize,
+}
+
+/// Counts a send as in progress until dropped, even if the send is cancelled.
+struct SendInProgress<'a>(&'a AtomicUsize);
+
+impl<'a> SendInProgress<'a> {
+    fn start(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for SendInProgress<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct EmailClientInner {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
-    authorization_token: String,
+    authorization_token: Secret<String>,
 }
 
 #[derive(serde::Serialize)]
@@ -24,11 +55,11 @@ struct SendEmailRequest<'a> {
     html_body: &'a str,
 }
 
-impl EmailClient {
-    pub fn new(
+impl EmailClientInner {
+    fn new(
         base_url: String,
         sender: SubscriberEmail,
-        authorization_token: String,
+        authorization_token: Secret<String>,
         timeout: Duration,
     ) -> Self {
         Self {
@@ -42,6 +73,56 @@ impl EmailClient {
             authorization_token,
         }
     }
+}
+
+impl EmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            inner: RwLock::new(Arc::new(EmailClientInner::new(
+                base_url,
+                sender,
+                authorization_token,
+                timeout,
+            ))),
+            metrics: None,
+            sends_in_progress: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of calls to `send_email` that have not returned yet.
+    pub fn sends_in_progress(&self) -> usize {
+        self.sends_in_progress.load(Ordering::SeqCst)
+    }
+
+    /// Records every call to the email provider in `metrics`.
+    pub fn with_metrics(mut self, metrics: EmailMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Replaces the configuration used by subsequent calls to `send_email`.
+    pub fn reconfigure(
+        &self,
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+    ) {
+        let inner = EmailClientInner::new(base_url, sender, authorization_token, timeout);
+        *self.inner.write().unwrap() = Arc::new(inner);
+    }
+
+    /// Succeeds if the email provider answers at all, whatever the response status.
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        let inner = Arc::clone(&self.inner.read().unwrap());
+        inner.http_client.get(&inner.base_url).send().await?;
+        Ok(())
+    }
 
     pub async fn send_email(
         &self,
@@ -50,25 +131,40 @@ impl EmailClient {
         text_content: &str,
         html_content: &str,
     ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
+        let _in_progress = SendInProgress::start(&self.sends_in_progress);
+        let inner = Arc::clone(&self.inner.read().unwrap());
+        let url = format!("{}/email", inner.base_url);
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
+            from: inner.sender.as_ref(),
             to: subscriber_email.as_ref(),
             subject,
             text_body: text_content,
             html_body: html_content,
         };
-        self.http_client
+        // Lets the email provider's traces be joined with ours.
+        let mut trace_headers = HeaderMap::new();
+        inject_trace_context(&mut trace_headers);
+        let started = Instant::now();
+        let outcome = inner
+            .http_client
             .post(url)
-            .header("X-Postmark-Server-Token", &self.authorization_token)
+            .header(
+                "X-Postmark-Server-Token",
+                inner.authorization_token.expose_secret(),
+            )
+            .headers(trace_headers)
             // `json` method is available when the "json" feature is enabled on the `reqwest` crate
             // It automatically sets Content-Type to "application/json"
             .json(&request_body)
             // .timeout(Duration::from_millis(5000))
             .send()
-            .await?
+            .await
             // Returns an Err when HTTP status code is greater than or equal to 400
-            .error_for_status()?;
+            .and_then(|response| response.error_for_status());
+        if let Some(metrics) = &self.metrics {
+            metrics.observe(started, &outcome);
+        }
+        outcome?;
         Ok(())
     }
 }
@@ -77,6 +173,7 @@ impl EmailClient {
 mod tests {
     use crate::domain::SubscriberEmail;
     use crate::email_client::EmailClient;
+    use crate::secret::Secret;
     use claim::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -118,7 +215,12 @@ mod tests {
     }
 
     fn email_client(base_url: String) -> EmailClient {
-        EmailClient::new(base_url, email(), Faker.fake(), Duration::from_millis(200))
+        EmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        )
     }
 
     #[tokio::test]
@@ -0,0 +1,230 @@
+use crate::csv::write_record;
+use actix_web::web::Bytes;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Subscribers are read this many at a time.
+const PAGE_SIZE: i64 = 1000;
+
+const CSV_HEADER: [&str; 8] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "locale",
+    "subscribed_at",
+    "paused_until",
+    "lists",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One row per subscriber, after a header row. Lists are separated by spaces.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Csv
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// Filters for `stream_subscribers`. Unset filters match everything.
+#[derive(Debug, Default, Clone)]
+pub struct ExportFilters {
+    pub status: Option<SubscriptionStatus>,
+    /// Slug of a list the subscribers are members of.
+    pub list: Option<String>,
+    /// Bounds on the signup date.
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// A subscriber as exported. Tokens are left out, since they stand in for credentials.
+#[derive(Debug, serde::Serialize)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub locale: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub paused_until: Option<DateTime<Utc>>,
+    /// Slugs of the lists the subscriber is a member of.
+    pub lists: Vec<String>,
+}
+
+struct ExportState {
+    pool: PgPool,
+    filters: ExportFilters,
+    format: ExportFormat,
+    // Signup date and id of the last subscriber exported so far.
+    after: Option<(DateTime<Utc>, Uuid)>,
+    done: bool,
+}
+
+/// The subscribers matching `filters`, oldest first, as chunks of `format` ready to be sent.
+///
+/// Subscribers are read a page at a time, so that neither the table nor a connection is held
+/// while a slow client downloads the export. Signups made during the export are included if
+/// they come after the current page.
+pub fn stream_subscribers(
+    pool: PgPool,
+    filters: ExportFilters,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    let state = ExportState {
+        pool,
+        filters,
+        format,
+        after: None,
+        done: false,
+    };
+    futures_util::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        let page = match next_page(&state).await {
+            Ok(page) => page,
+            Err(e) => {
+                state.done = true;
+                return Some((Err(e), state));
+            }
+        };
+        let mut chunk = String::new();
+        if state.format == ExportFormat::Csv && state.after.is_none() {
+            write_record(&mut chunk, &CSV_HEADER);
+        }
+        for subscriber in &page {
+            if let Err(e) = write_subscriber(&mut chunk, subscriber, state.format) {
+                state.done = true;
+                return Some((Err(e), state));
+            }
+        }
+        state.done = (page.len() as i64) < PAGE_SIZE;
+        if let Some(last) = page.last() {
+            state.after = Some((last.subscribed_at, last.id));
+        }
+        Some((Ok(Bytes::from(chunk)), state))
+    })
+}
+
+#[tracing::instrument(name = "Fetch a page of subscribers to export", skip(state))]
+async fn next_page(state: &ExportState) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let filters = &state.filters;
+    let after_subscribed_at = state.after.map(|(subscribed_at, _)| subscribed_at);
+    let after_id = state.after.map(|(_, id)| id);
+    sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            locale,
+            subscribed_at,
+            paused_until,
+            ARRAY(
+                SELECT lists.slug
+                FROM list_memberships
+                JOIN lists ON lists.list_id = list_memberships.list_id
+                WHERE list_memberships.subscriber_id = subscriptions.id
+                ORDER BY lists.slug
+            ) AS "lists!"
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+          AND ($2::TEXT IS NULL OR EXISTS (
+                SELECT 1
+                FROM list_memberships
+                JOIN lists ON lists.list_id = list_memberships.list_id
+                WHERE list_memberships.subscriber_id = subscriptions.id AND lists.slug = $2
+              ))
+          AND ($3::TIMESTAMPTZ IS NULL OR subscribed_at >= $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR subscribed_at < $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR (subscribed_at, id) > ($5, $6::UUID))
+        ORDER BY subscribed_at, id
+        LIMIT $7
+        "#,
+        filters.status.map(|status| status.as_str()),
+        filters.list,
+        filters.since,
+        filters.until,
+        after_subscribed_at,
+        after_id,
+        PAGE_SIZE
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to fetch subscribers to export.")
+}
+
+fn write_subscriber(
+    chunk: &mut String,
+    subscriber: &ExportedSubscriber,
+    format: ExportFormat,
+) -> Result<(), anyhow::Error> {
+    match format {
+        ExportFormat::Csv => write_record(
+            chunk,
+            &[
+                subscriber.id.to_string(),
+                subscriber.email.clone(),
+                subscriber.name.clone(),
+                subscriber.status.clone(),
+                subscriber.locale.clone(),
+                subscriber.subscribed_at.to_rfc3339(),
+                subscriber
+                    .paused_until
+                    .map(|paused_until| paused_until.to_rfc3339())
+                    .unwrap_or_default(),
+                subscriber.lists.join(" "),
+            ],
+        ),
+        ExportFormat::Ndjson => {
+            chunk.push_str(
+                &serde_json::to_string(subscriber).context("Failed to serialize a subscriber.")?,
+            );
+            chunk.push('\n');
+        }
+    }
+    Ok(())
+}
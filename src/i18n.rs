@@ -0,0 +1,229 @@
+use actix_http::header::{self, HeaderMap};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+/// A locale we ship a catalog for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Locale(&'static str);
+
+impl Locale {
+    /// Used when nothing better is known, and for messages missing from other catalogs.
+    pub const DEFAULT: Locale = Locale("en");
+
+    /// Every shipped locale, the default first.
+    pub const ALL: [Locale; 3] = [Locale::DEFAULT, Locale("fr"), Locale("de")];
+
+    /// Matches a language tag, e.g. `fr-CA`, on its primary language.
+    pub fn parse(tag: &str) -> Option<Locale> {
+        let language = tag.trim().split(|c| c == '-' || c == '_').next()?;
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|locale| locale.0.eq_ignore_ascii_case(language))
+    }
+
+    /// The shipped locale the client prefers, according to `Accept-Language`.
+    pub fn negotiate(accept_language: Option<&str>) -> Locale {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((tag, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // A stable sort keeps the client's order between ranges of equal quality.
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| Locale::parse(tag))
+            .unwrap_or(Locale::DEFAULT)
+    }
+
+    /// The shipped locale a request's sender prefers.
+    pub fn of_request(headers: &HeaderMap) -> Locale {
+        Self::negotiate(
+            headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+
+    fn catalog_source(&self) -> &'static str {
+        match self.0 {
+            "fr" => include_str!("../locales/fr.ftl"),
+            "de" => include_str!("../locales/de.ftl"),
+            _ => include_str!("../locales/en.ftl"),
+        }
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::DEFAULT
+    }
+}
+
+static CATALOGS: Lazy<HashMap<Locale, Catalog>> = Lazy::new(|| {
+    Locale::ALL
+        .iter()
+        .map(|locale| (*locale, Catalog::parse(locale.catalog_source())))
+        .collect()
+});
+
+/// The message `key` in `locale`, with `{ $name }` placeholders replaced by `args`.
+///
+/// Messages missing from a catalog fall back to the default locale, then to the key itself,
+/// so that a gap shows up on the page rather than breaking it.
+pub fn translate(locale: Locale, key: &str, args: &[(&str, &str)]) -> String {
+    lookup(locale, key)
+        .map(|message| format_message(message, args))
+        .unwrap_or_else(|| key.to_string())
+}
+
+/// Like `translate`, but `None` when no catalog has the message.
+pub fn lookup(locale: Locale, key: &str) -> Option<&'static str> {
+    [locale, Locale::DEFAULT]
+        .iter()
+        .find_map(|locale| CATALOGS[locale].messages.get(key))
+        .map(String::as_str)
+}
+
+/// Messages in the subset of the Fluent syntax that we use: `key = value` lines, with values
+/// continued on indented lines, and `#` comments.
+struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    fn parse(source: &str) -> Self {
+        let mut messages: HashMap<String, String> = HashMap::new();
+        let mut current: Option<String> = None;
+        for line in source.lines() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                if let Some(message) = current.as_ref().and_then(|key| messages.get_mut(key)) {
+                    if !message.is_empty() {
+                        message.push('\n');
+                    }
+                    message.push_str(line.trim());
+                }
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let key = key.trim().to_string();
+                messages.insert(key.clone(), value.trim().to_string());
+                current = Some(key);
+            }
+        }
+        Self { messages }
+    }
+}
+
+fn format_message(message: &str, args: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find("{ $") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        output.push_str(&rest[..start]);
+        let name = rest[start + 3..end].trim();
+        if let Some((_, value)) = args.iter().find(|(key, _)| *key == name) {
+            output.push_str(value);
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_message, Catalog, Locale, CATALOGS};
+    use std::collections::BTreeSet;
+
+    fn variables(message: &str) -> BTreeSet<&str> {
+        message
+            .match_indices("{ $")
+            .filter_map(|(start, _)| {
+                let end = message[start..].find('}')? + start;
+                Some(message[start + 3..end].trim())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_shipped_catalog_is_complete() {
+        let default = &CATALOGS[&Locale::DEFAULT].messages;
+        for locale in Locale::ALL {
+            let catalog = &CATALOGS[&locale].messages;
+            for (key, message) in default {
+                let translation = catalog
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} is missing from {}.", key, locale.as_str()));
+                assert_eq!(
+                    variables(translation),
+                    variables(message),
+                    "{} in {} does not use the same variables.",
+                    key,
+                    locale.as_str()
+                );
+            }
+            for key in catalog.keys() {
+                assert!(
+                    default.contains_key(key),
+                    "{} in {} is not in the default catalog.",
+                    key,
+                    locale.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn accept_language_is_negotiated_by_quality() {
+        assert_eq!(
+            Locale::negotiate(Some("fr-CH, fr;q=0.9, en;q=0.8")).as_str(),
+            "fr"
+        );
+        assert_eq!(
+            Locale::negotiate(Some("it, de;q=0.5, en;q=0.7")).as_str(),
+            "en"
+        );
+        assert_eq!(Locale::negotiate(Some("it")), Locale::DEFAULT);
+        assert_eq!(Locale::negotiate(Some("de;q=0")), Locale::DEFAULT);
+        assert_eq!(Locale::negotiate(None), Locale::DEFAULT);
+    }
+
+    #[test]
+    fn values_can_span_several_lines() {
+        let catalog = Catalog::parse("# Comment\nkey = first\n    second\nother = x\n");
+
+        assert_eq!(catalog.messages["key"], "first\nsecond");
+        assert_eq!(catalog.messages["other"], "x");
+    }
+
+    #[test]
+    fn variables_are_replaced() {
+        let message = format_message(
+            "Hi { $name }, {$unknown}bye { $missing }!",
+            &[("name", "Ursula")],
+        );
+
+        assert_eq!(message, "Hi Ursula, {$unknown}bye !");
+    }
+}
@@ -0,0 +1,766 @@
+use crate::consent::{insert_consent_event, ConsentEventKind, Terms};
+use crate::csv::{write_record, CsvReader};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::i18n::Locale;
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, insert_subscriber, send_confirmation_email,
+    store_token, UNIQUE_VIOLATION,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::{pin_mut, Stream, StreamExt};
+use sqlx::{Acquire, Executor, PgPool, Postgres, Transaction};
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The source of imported signups in the consent history.
+pub const IMPORT_SOURCE: &str = "import";
+
+// Rows of an upload are stored this many at a time.
+const BATCH_SIZE: usize = 1000;
+
+/// How the rows of an upload are imported.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ImportOptions {
+    /// Imported subscribers are confirmed straight away, rather than sent a confirmation email.
+    #[serde(default)]
+    pub confirmed: bool,
+    /// Slug of a list that imported subscribers join, on top of the default one.
+    pub list: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Running,
+    Completed,
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Running => "running",
+            ImportStatus::Completed => "completed",
+        }
+    }
+}
+
+impl TryFrom<String> for ImportStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "running" => Ok(Self::Running),
+            "completed" => Ok(Self::Completed),
+            other => Err(format!("{} is not a supported import status.", other)),
+        }
+    }
+}
+
+/// What became of an uploaded row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowOutcome {
+    Imported,
+    /// The address is already subscribed, possibly from an earlier row of the same upload.
+    Duplicate,
+    /// The name or the address does not pass validation.
+    Invalid,
+    /// The confirmation email could not be sent.
+    Failed,
+}
+
+impl RowOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RowOutcome::Imported => "imported",
+            RowOutcome::Duplicate => "duplicate",
+            RowOutcome::Invalid => "invalid",
+            RowOutcome::Failed => "failed",
+        }
+    }
+}
+
+impl TryFrom<String> for RowOutcome {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "imported" => Ok(Self::Imported),
+            "duplicate" => Ok(Self::Duplicate),
+            "invalid" => Ok(Self::Invalid),
+            "failed" => Ok(Self::Failed),
+            other => Err(format!("{} is not a supported row outcome.", other)),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ImportJob {
+    pub job_id: Uuid,
+    pub status: ImportStatus,
+    pub confirmed: bool,
+    pub list: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub rows: RowCounts,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RowCounts {
+    pub total: i64,
+    /// Not processed yet.
+    pub pending: i64,
+    pub imported: i64,
+    pub duplicate: i64,
+    pub invalid: i64,
+    pub failed: i64,
+}
+
+/// A row that was not imported, as listed in the error report.
+#[derive(Debug)]
+pub struct RejectedRow {
+    pub line: i32,
+    pub email: String,
+    pub name: String,
+    pub outcome: RowOutcome,
+    pub error: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidCsv(String),
+    #[error("There is no list named `{0}`.")]
+    UnknownList(String),
+    #[error("The upload is larger than {0} bytes.")]
+    TooLarge(usize),
+    #[error("Failed to read the upload: {0}")]
+    UploadFailed(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Stores the rows of a CSV upload as a new job, for `run_import_job` to import.
+///
+/// The header must name an `email` and a `name` column, and may name a `locale` column. Other
+/// columns are ignored. Rows are only validated when they are imported, so that problems end up
+/// in the error report instead of failing the upload.
+#[tracing::instrument(name = "Create import job", skip(pool, upload, options))]
+pub async fn create_import_job<S, B, E>(
+    pool: &PgPool,
+    upload: S,
+    max_bytes: usize,
+    options: &ImportOptions,
+    created_by: &str,
+    created_from: &str,
+) -> Result<Uuid, ImportError>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let list_id = match &options.list {
+        Some(slug) => Some(
+            sqlx::query!("SELECT list_id FROM lists WHERE slug = $1", slug)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to look up the list.")?
+                .ok_or_else(|| ImportError::UnknownList(slug.clone()))?
+                .list_id,
+        ),
+        None => None,
+    };
+    let job_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO import_jobs (job_id, status, confirmed, list_id, created_by, created_from, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+        job_id,
+        ImportStatus::Running.as_str(),
+        options.confirmed,
+        list_id,
+        created_by,
+        created_from
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the import job.")?;
+
+    pin_mut!(upload);
+    let mut reader = CsvReader::new();
+    let mut rows = Rows::default();
+    let mut received = 0;
+    while let Some(chunk) = upload.next().await {
+        let chunk = chunk.map_err(|e| ImportError::UploadFailed(e.to_string()))?;
+        received += chunk.as_ref().len();
+        if received > max_bytes {
+            return Err(ImportError::TooLarge(max_bytes));
+        }
+        for record in reader.push(chunk.as_ref()) {
+            rows.add(record)?;
+        }
+        if rows.lines.len() >= BATCH_SIZE {
+            rows.store(&mut transaction, job_id).await?;
+        }
+    }
+    if let Some(record) = reader.finish() {
+        rows.add(record)?;
+    }
+    if rows.columns.is_none() {
+        return Err(ImportError::InvalidCsv("The upload is empty.".into()));
+    }
+    rows.store(&mut transaction, job_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an import job.")?;
+    Ok(job_id)
+}
+
+/// Positions of the columns we import.
+struct Columns {
+    email: usize,
+    name: usize,
+    locale: Option<usize>,
+}
+
+impl Columns {
+    fn parse(header: &[String]) -> Result<Self, ImportError> {
+        let position = |column: &str| {
+            header.iter().position(|name| {
+                // Spreadsheets tend to start their exports with a byte order mark.
+                name.trim_start_matches('\u{feff}')
+                    .trim()
+                    .eq_ignore_ascii_case(column)
+            })
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Self {
+                email,
+                name,
+                locale: position("locale"),
+            }),
+            _ => Err(ImportError::InvalidCsv(
+                "The header must name an `email` and a `name` column.".into(),
+            )),
+        }
+    }
+}
+
+/// Rows of an upload waiting to be stored.
+#[derive(Default)]
+struct Rows {
+    columns: Option<Columns>,
+    // Records read so far, including the header.
+    records: i32,
+    lines: Vec<i32>,
+    emails: Vec<String>,
+    names: Vec<String>,
+    locales: Vec<String>,
+}
+
+impl Rows {
+    fn add(&mut self, record: Vec<String>) -> Result<(), ImportError> {
+        self.records += 1;
+        let columns = match &self.columns {
+            Some(columns) => columns,
+            None => {
+                self.columns = Some(Columns::parse(&record)?);
+                return Ok(());
+            }
+        };
+        let field = |i: usize| record.get(i).map(|field| field.trim()).unwrap_or_default();
+        // Missing or unknown locales fall back to the default one, as on signup.
+        let locale = columns
+            .locale
+            .and_then(|i| Locale::parse(field(i)))
+            .unwrap_or_default();
+        self.lines.push(self.records);
+        self.emails.push(field(columns.email).into());
+        self.names.push(field(columns.name).into());
+        self.locales.push(locale.as_str().into());
+        Ok(())
+    }
+
+    async fn store(
+        &mut self,
+        transaction: &mut Transaction<'_, Postgres>,
+        job_id: Uuid,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO import_rows (job_id, line, email, name, locale)
+            SELECT $1::UUID, * FROM UNNEST($2::INT4[], $3::TEXT[], $4::TEXT[], $5::TEXT[])
+            "#,
+            job_id,
+            &std::mem::take(&mut self.lines),
+            &std::mem::take(&mut self.emails),
+            &std::mem::take(&mut self.names),
+            &std::mem::take(&mut self.locales)
+        )
+        .execute(transaction)
+        .await
+        .context("Failed to store the rows of an import.")?;
+        Ok(())
+    }
+}
+
+/// Runs `run_import_job` in the background, logging its failure.
+pub fn spawn_import_job(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    base_url: String,
+    job_id: Uuid,
+) {
+    actix_web::rt::spawn(async move {
+        if let Err(e) = run_import_job(&pool, &email_client, &base_url, job_id).await {
+            tracing::error!(error = ?e, %job_id, "Import job failed.");
+        }
+    });
+}
+
+/// Runs the jobs left unfinished by a previous run of the application, one after the other.
+pub async fn resume_import_jobs(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    base_url: String,
+) -> Result<(), anyhow::Error> {
+    let jobs = sqlx::query!(
+        "SELECT job_id FROM import_jobs WHERE status = $1 ORDER BY created_at",
+        ImportStatus::Running.as_str()
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to fetch unfinished import jobs.")?;
+    for job in jobs {
+        tracing::info!(job_id = %job.job_id, "Resuming import job.");
+        if let Err(e) = run_import_job(&pool, &email_client, &base_url, job.job_id).await {
+            tracing::error!(error = ?e, job_id = %job.job_id, "Import job failed.");
+        }
+    }
+    Ok(())
+}
+
+/// Settings of a job, as needed to import its rows.
+struct Job {
+    job_id: Uuid,
+    confirmed: bool,
+    list_id: Option<Uuid>,
+    created_from: String,
+}
+
+/// Imports the rows of the job that are still pending, then marks the job as completed.
+///
+/// Each row is imported in a transaction of its own, so that jobs interrupted by a shutdown or a
+/// crash carry on where they stopped. Several workers can share a job.
+#[tracing::instrument(name = "Run import job", skip(pool, email_client, base_url))]
+pub async fn run_import_job(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    job_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let job = sqlx::query_as!(
+        Job,
+        "SELECT job_id, confirmed, list_id, created_from FROM import_jobs WHERE job_id = $1",
+        job_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the import job.")?;
+    while import_next_row(pool, email_client, base_url, &job).await? {}
+    sqlx::query!(
+        r#"
+        UPDATE import_jobs
+        SET status = $2, finished_at = now()
+        WHERE job_id = $1
+          AND finished_at IS NULL
+          AND NOT EXISTS (SELECT 1 FROM import_rows WHERE job_id = $1 AND outcome IS NULL)
+        "#,
+        job_id,
+        ImportStatus::Completed.as_str()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark the import job as completed.")?;
+    Ok(())
+}
+
+/// Imports the first pending row of the job. Returns `false` if there is none left.
+async fn import_next_row(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    job: &Job,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let row = sqlx::query!(
+        r#"
+        SELECT line, email, name, locale
+        FROM import_rows
+        WHERE job_id = $1 AND outcome IS NULL
+        ORDER BY line
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        job.job_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the next row to import.")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+    let line = row.line;
+    let new_subscriber = match parse_row(row.name, row.email) {
+        Ok(new_subscriber) => new_subscriber,
+        Err(e) => {
+            set_outcome(
+                &mut transaction,
+                job,
+                line,
+                RowOutcome::Invalid,
+                Some(e.as_str()),
+            )
+            .await?;
+            return commit(transaction).await;
+        }
+    };
+    let is_subscribed = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM subscriptions WHERE lower(email) = lower($1)) AS "exists!""#,
+        new_subscriber.email.as_ref()
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to look for an existing subscriber.")?
+    .exists;
+    if is_subscribed {
+        set_outcome(
+            &mut transaction,
+            job,
+            line,
+            RowOutcome::Duplicate,
+            Some(DUPLICATE),
+        )
+        .await?;
+        return commit(transaction).await;
+    }
+
+    let locale = Locale::parse(&row.locale).unwrap_or_default();
+    let preferences_token = generate_subscription_token();
+    // Signups that slip in after the check above must not abort the whole transaction.
+    let mut savepoint = transaction
+        .begin()
+        .await
+        .context("Failed to create a savepoint.")?;
+    let subscriber_id = match insert_subscriber(
+        &mut savepoint,
+        &new_subscriber,
+        locale,
+        &preferences_token,
+    )
+    .await
+    {
+        Ok(subscriber_id) => subscriber_id,
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            savepoint
+                .rollback()
+                .await
+                .context("Failed to roll back to the savepoint.")?;
+            set_outcome(
+                &mut transaction,
+                job,
+                line,
+                RowOutcome::Duplicate,
+                Some(DUPLICATE),
+            )
+            .await?;
+            return commit(transaction).await;
+        }
+        Err(e) => return Err(e).context("Failed to insert an imported subscriber."),
+    };
+    if job.confirmed {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
+            subscriber_id
+        )
+        .execute(&mut savepoint)
+        .await
+        .context("Failed to confirm an imported subscriber.")?;
+    }
+    if let Some(list_id) = job.list_id {
+        sqlx::query!(
+            r#"
+            INSERT INTO list_memberships (subscriber_id, list_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            subscriber_id,
+            list_id
+        )
+        .execute(&mut savepoint)
+        .await
+        .context("Failed to add an imported subscriber to the list.")?;
+    }
+    // The consent was given elsewhere, to a text we do not know.
+    insert_consent_event(
+        &mut savepoint,
+        subscriber_id,
+        ConsentEventKind::Signup,
+        &job.created_from,
+        None,
+        Some(Terms {
+            source: IMPORT_SOURCE,
+            consent_text_version: None,
+        }),
+    )
+    .await?;
+    let subscription_token = if job.confirmed {
+        None
+    } else {
+        let subscription_token = generate_subscription_token();
+        store_token(&mut savepoint, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store the confirmation token of an imported subscriber.")?;
+        Some(subscription_token)
+    };
+    savepoint
+        .commit()
+        .await
+        .context("Failed to release the savepoint.")?;
+
+    if let Some(subscription_token) = subscription_token {
+        // Sent before committing: a crash in between sends the email twice rather than never.
+        let sent = send_confirmation_email(
+            email_client,
+            new_subscriber,
+            base_url,
+            &subscription_token,
+            &preferences_token,
+            locale,
+        )
+        .await;
+        if let Err(e) = sent {
+            tracing::warn!(error = ?e, line, "Failed to send a confirmation email.");
+            transaction
+                .rollback()
+                .await
+                .context("Failed to roll back the import of a row.")?;
+            set_outcome(
+                pool,
+                job,
+                line,
+                RowOutcome::Failed,
+                Some("The confirmation email could not be sent."),
+            )
+            .await?;
+            return Ok(true);
+        }
+    }
+    set_outcome(&mut transaction, job, line, RowOutcome::Imported, None).await?;
+    commit(transaction).await
+}
+
+const DUPLICATE: &str = "The address is already subscribed.";
+
+fn parse_row(name: String, email: String) -> Result<NewSubscriber, String> {
+    let name = SubscriberName::parse(name)?;
+    let email = SubscriberEmail::parse(email)?;
+    Ok(NewSubscriber { email, name })
+}
+
+async fn set_outcome<'e, E>(
+    executor: E,
+    job: &Job,
+    line: i32,
+    outcome: RowOutcome,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query!(
+        "UPDATE import_rows SET outcome = $3, error = $4 WHERE job_id = $1 AND line = $2",
+        job.job_id,
+        line,
+        outcome.as_str(),
+        error
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record the outcome of an imported row.")?;
+    Ok(())
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<bool, anyhow::Error> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import a row.")?;
+    Ok(true)
+}
+
+/// `None` if there is no such job.
+#[tracing::instrument(name = "Get import job", skip(pool))]
+pub async fn get_import_job(
+    pool: &PgPool,
+    job_id: Uuid,
+) -> Result<Option<ImportJob>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            import_jobs.job_id,
+            import_jobs.status,
+            import_jobs.confirmed,
+            lists.slug AS "list?",
+            import_jobs.created_by,
+            import_jobs.created_at,
+            import_jobs.finished_at,
+            COUNT(import_rows.line) AS "total!",
+            COUNT(import_rows.line) FILTER (WHERE import_rows.outcome IS NULL) AS "pending!",
+            COUNT(import_rows.line) FILTER (WHERE import_rows.outcome = 'imported') AS "imported!",
+            COUNT(import_rows.line) FILTER (WHERE import_rows.outcome = 'duplicate') AS "duplicate!",
+            COUNT(import_rows.line) FILTER (WHERE import_rows.outcome = 'invalid') AS "invalid!",
+            COUNT(import_rows.line) FILTER (WHERE import_rows.outcome = 'failed') AS "failed!"
+        FROM import_jobs
+        LEFT JOIN lists ON lists.list_id = import_jobs.list_id
+        LEFT JOIN import_rows ON import_rows.job_id = import_jobs.job_id
+        WHERE import_jobs.job_id = $1
+        GROUP BY import_jobs.job_id, lists.slug
+        "#,
+        job_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the import job.")?;
+
+    row.map(|row| {
+        Ok(ImportJob {
+            job_id: row.job_id,
+            status: row.status.try_into().map_err(anyhow::Error::msg)?,
+            confirmed: row.confirmed,
+            list: row.list,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            finished_at: row.finished_at,
+            rows: RowCounts {
+                total: row.total,
+                pending: row.pending,
+                imported: row.imported,
+                duplicate: row.duplicate,
+                invalid: row.invalid,
+                failed: row.failed,
+            },
+        })
+    })
+    .transpose()
+}
+
+/// The rows of the job that were not imported, in upload order.
+#[tracing::instrument(name = "List rejected rows", skip(pool))]
+pub async fn list_rejected_rows(
+    pool: &PgPool,
+    job_id: Uuid,
+) -> Result<Vec<RejectedRow>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT line, email, name, outcome AS "outcome!", error AS "error!"
+        FROM import_rows
+        WHERE job_id = $1 AND outcome <> 'imported'
+        ORDER BY line
+        "#,
+        job_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the rejected rows.")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(RejectedRow {
+                line: row.line,
+                email: row.email,
+                name: row.name,
+                outcome: row.outcome.try_into().map_err(anyhow::Error::msg)?,
+                error: row.error,
+            })
+        })
+        .collect()
+}
+
+/// The rejected rows as CSV, with the line of each row in the upload.
+pub fn error_report(rows: &[RejectedRow]) -> String {
+    let mut report = String::new();
+    write_record(&mut report, &["line", "email", "name", "outcome", "error"]);
+    for row in rows {
+        write_record(
+            &mut report,
+            &[
+                row.line.to_string().as_str(),
+                &row.email,
+                &row.name,
+                row.outcome.as_str(),
+                &row.error,
+            ],
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Columns, RowOutcome};
+    use std::convert::TryFrom;
+
+    fn header(columns: &[&str]) -> Vec<String> {
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    #[test]
+    fn columns_are_found_in_any_order_and_case() {
+        let columns = Columns::parse(&header(&["\u{feff}Name", "id", " EMAIL "])).unwrap();
+        assert_eq!(columns.name, 0);
+        assert_eq!(columns.email, 2);
+        assert_eq!(columns.locale, None);
+    }
+
+    #[test]
+    fn headers_without_email_or_name_are_rejected() {
+        assert!(Columns::parse(&header(&["email", "locale"])).is_err());
+        assert!(Columns::parse(&header(&["name"])).is_err());
+    }
+
+    #[test]
+    fn outcomes_round_trip_through_their_stored_name() {
+        for outcome in &[
+            RowOutcome::Imported,
+            RowOutcome::Duplicate,
+            RowOutcome::Invalid,
+            RowOutcome::Failed,
+        ] {
+            assert_eq!(
+                RowOutcome::try_from(outcome.as_str().to_string()),
+                Ok(*outcome)
+            );
+        }
+    }
+}
@@ -1,7 +1,27 @@
 #![allow(clippy::toplevel_ref_arg)]
+pub mod abuse_protection;
+pub mod api_error;
+pub mod audit;
+pub mod authentication;
 pub mod configuration;
+pub mod consent;
+pub mod csv;
+pub mod digest;
 pub mod domain;
 pub mod email_client;
+pub mod export;
+pub mod i18n;
+pub mod import;
+pub mod limits;
+pub mod metrics;
+pub mod personal_data;
+pub mod reload;
 pub mod routes;
+pub mod secret;
+pub mod security;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
+pub mod tls;
+pub mod utils;
@@ -0,0 +1,287 @@
+use crate::api_error::{invalid_request, ApiError};
+use crate::configuration::{LimitSettings, RouteLimitSettings};
+use crate::routes::error_chain_fmt;
+use actix_http::StatusCode;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Requests rejected before, or instead of, reaching their handler.
+#[derive(thiserror::Error)]
+pub enum LimitError {
+    #[error("The server is handling too many requests.")]
+    Overloaded { retry_after: Duration },
+    #[error("The request took longer than {} ms.", .0.as_millis())]
+    TimedOut(Duration),
+    #[error("The request body is larger than {0} bytes.")]
+    PayloadTooLarge(usize),
+}
+
+impl std::fmt::Debug for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<&LimitError> for ApiError {
+    fn from(e: &LimitError) -> Self {
+        let error = match e {
+            LimitError::Overloaded { retry_after } => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "overloaded")
+                    .with_retry_after(*retry_after)
+            }
+            LimitError::TimedOut(_) => ApiError::new(StatusCode::GATEWAY_TIMEOUT, "timed_out"),
+            LimitError::PayloadTooLarge(_) => {
+                ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large")
+            }
+        };
+        error.with_detail(e.to_string())
+    }
+}
+
+impl ResponseError for LimitError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
+
+/// Caps JSON bodies at `max_body_bytes`, answering larger ones with a `LimitError`.
+/// Malformed bodies are answered with `invalid_request`.
+pub fn json_config(limits: &RouteLimitSettings) -> web::JsonConfig {
+    let max_body_bytes = limits.max_body_bytes;
+    web::JsonConfig::default()
+        .limit(max_body_bytes)
+        .error_handler(move |e, _: &HttpRequest| payload_error(e, max_body_bytes))
+}
+
+/// Caps form bodies at `max_body_bytes`, answering larger ones with a `LimitError`.
+/// Malformed bodies are answered with `invalid_request`.
+pub fn form_config(limits: &RouteLimitSettings) -> web::FormConfig {
+    let max_body_bytes = limits.max_body_bytes;
+    web::FormConfig::default()
+        .limit(max_body_bytes)
+        .error_handler(move |e, _: &HttpRequest| payload_error(e, max_body_bytes))
+}
+
+/// Largest body accepted by handlers that read it as a stream, which `web::PayloadConfig` does
+/// not cover. Handlers enforce it themselves.
+pub struct UploadLimit(pub usize);
+
+pub fn upload_limit(limits: &RouteLimitSettings) -> web::Data<UploadLimit> {
+    web::Data::new(UploadLimit(limits.max_body_bytes))
+}
+
+fn payload_error(e: impl ResponseError + 'static, max_body_bytes: usize) -> actix_web::Error {
+    if e.status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+        LimitError::PayloadTooLarge(max_body_bytes).into()
+    } else {
+        invalid_request(e)
+    }
+}
+
+/// Middleware that cancels requests running for longer than their route's timeout, answering
+/// `504 Gateway Timeout` instead.
+///
+/// Cancelling drops the handler at its next `.await`: work that it already did, such as
+/// emails already sent, is not undone.
+#[derive(Clone)]
+pub struct RequestTimeouts {
+    default: Duration,
+    // Keyed by route pattern, e.g. `/newsletters`.
+    routes: HashMap<String, Duration>,
+}
+
+impl RequestTimeouts {
+    pub fn new(limits: &LimitSettings) -> Self {
+        Self {
+            default: limits.default.timeout(),
+            routes: HashMap::new(),
+        }
+        .route("/subscriptions", &limits.subscriptions)
+        .route("/newsletters", &limits.newsletters)
+        .route("/admin/imports", &limits.imports)
+    }
+
+    fn route(mut self, pattern: &str, limits: &RouteLimitSettings) -> Self {
+        self.routes.insert(pattern.into(), limits.timeout());
+        self
+    }
+
+    fn timeout(&self, pattern: Option<&str>) -> Duration {
+        pattern
+            .and_then(|pattern| self.routes.get(pattern))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTimeouts
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestTimeoutsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTimeoutsMiddleware {
+            service: Rc::new(service),
+            timeouts: Rc::new(self.clone()),
+        }))
+    }
+}
+
+pub struct RequestTimeoutsMiddleware<S> {
+    service: Rc<S>,
+    timeouts: Rc<RequestTimeouts>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTimeoutsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let timeout = self.timeouts.timeout(req.match_pattern().as_deref());
+        Box::pin(async move {
+            match actix_web::rt::time::timeout(timeout, service.call(req)).await {
+                Ok(outcome) => outcome,
+                Err(_) => {
+                    tracing::warn!(
+                        timeout_ms = timeout.as_millis() as u64,
+                        "Request timed out."
+                    );
+                    Err(LimitError::TimedOut(timeout).into())
+                }
+            }
+        })
+    }
+}
+
+/// Middleware that sheds load once `max_concurrent_requests` are in flight, across all workers.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    in_flight: Arc<AtomicUsize>,
+    max_concurrent_requests: usize,
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limits: &LimitSettings) -> Self {
+        Self {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_concurrent_requests: limits.max_concurrent_requests,
+            retry_after: Duration::from_secs(limits.retry_after_seconds),
+        }
+    }
+
+    /// Takes a slot, released when the returned guard is dropped.
+    fn try_acquire(&self) -> Result<InFlight, LimitError> {
+        let previous = self.in_flight.fetch_add(1, Ordering::SeqCst);
+        // Also gives the slot back when the request is turned away.
+        let guard = InFlight(self.in_flight.clone());
+        if previous >= self.max_concurrent_requests {
+            return Err(LimitError::Overloaded {
+                retry_after: self.retry_after,
+            });
+        }
+        Ok(guard)
+    }
+}
+
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ConcurrencyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ConcurrencyLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ConcurrencyLimitMiddleware {
+            service: Rc::new(service),
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub struct ConcurrencyLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: ConcurrencyLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        // Probes must keep answering on a busy instance, or it would be restarted.
+        let slot = if req.path().starts_with("/health") {
+            None
+        } else {
+            Some(self.limit.try_acquire())
+        };
+        Box::pin(async move {
+            let _slot = slot.transpose()?;
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcurrencyLimit;
+    use crate::configuration::get_configuration;
+
+    #[test]
+    fn slots_are_released_when_requests_complete() {
+        let mut limits = get_configuration().unwrap().limits;
+        limits.max_concurrent_requests = 1;
+        let limit = ConcurrencyLimit::new(&limits);
+
+        let slot = limit.try_acquire();
+        assert!(slot.is_ok());
+        assert!(limit.try_acquire().is_err());
+
+        drop(slot);
+        assert!(limit.try_acquire().is_ok());
+    }
+}
@@ -1,14 +1,228 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use anyhow::Context;
+use futures_util::Stream;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+use zero2prod::configuration::{configuration_files, environment, load_configuration, Settings};
+use zero2prod::email_client::EmailClient;
+use zero2prod::import::{
+    create_import_job, error_report, get_import_job, list_rejected_rows, run_import_job,
+    ImportOptions,
+};
+use zero2prod::reload::reload_on_file_change;
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::telemetry::{
+    get_subscriber, get_tracer_provider, init_subscriber, init_tracer, shutdown_tracer,
+};
+
+const USAGE: &str = "Usage: zero2prod [--config <path>] [--print-config]
+       zero2prod [--config <path>] import <file> [--confirmed] [--list <slug>] [--report <path>]
+
+Options:
+  --config <path>   YAML file layered on top of configuration/base.yaml and the
+                    APP_ENVIRONMENT file, below APP_* environment variables.
+  --print-config    Print the effective configuration, with secrets redacted, and exit.
+
+Commands:
+  import <file>     Import the subscribers of a CSV file with `email` and `name` columns,
+                    and optionally a `locale` column, then print a summary. Imports that
+                    are interrupted are finished by the server when it next starts.
+    --confirmed     Confirm imported subscribers instead of sending confirmation emails.
+    --list <slug>   Add imported subscribers to this list, on top of the default one.
+    --report <path> Write the rows that were not imported, and why, to this CSV file.";
+
+struct Arguments {
+    config_file: Option<PathBuf>,
+    print_config: bool,
+    import: Option<ImportArguments>,
+}
+
+struct ImportArguments {
+    file: PathBuf,
+    options: ImportOptions,
+    report: Option<PathBuf>,
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut arguments = Arguments {
+        config_file: None,
+        print_config: false,
+        import: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args.next().ok_or("--config requires a path.")?;
+                arguments.config_file = Some(path.into());
+            }
+            "--print-config" => arguments.print_config = true,
+            "import" => {
+                let file = args.next().ok_or("import requires a file.")?;
+                arguments.import = Some(ImportArguments {
+                    file: file.into(),
+                    options: ImportOptions::default(),
+                    report: None,
+                });
+            }
+            "--confirmed" => import_arguments(&mut arguments, &arg)?.options.confirmed = true,
+            "--list" => {
+                let slug = args.next().ok_or("--list requires a slug.")?;
+                import_arguments(&mut arguments, &arg)?.options.list = Some(slug);
+            }
+            "--report" => {
+                let path = args.next().ok_or("--report requires a path.")?;
+                import_arguments(&mut arguments, &arg)?.report = Some(path.into());
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("Unexpected argument `{}`.", other)),
+        }
+    }
+    Ok(arguments)
+}
+
+fn import_arguments<'a>(
+    arguments: &'a mut Arguments,
+    option: &str,
+) -> Result<&'a mut ImportArguments, String> {
+    arguments
+        .import
+        .as_mut()
+        .ok_or_else(|| format!("{} is only valid after `import <file>`.", option))
+}
 
 #[actix_web::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
+async fn main() -> anyhow::Result<()> {
+    let arguments = parse_arguments().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    let configuration = load_configuration(arguments.config_file.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        std::process::exit(1);
+    });
+    if arguments.print_config {
+        println!("{}", configuration.redacted());
+        return Ok(());
+    }
+
+    let tracer = init_tracer(get_tracer_provider(
+        "zero2prod".into(),
+        &configuration.telemetry.otlp,
+    )?);
+    if let Some(import) = arguments.import {
+        // Logs go to stderr, leaving stdout to the summary.
+        let (subscriber, log_filter) = get_subscriber(
+            "zero2prod".into(),
+            configuration.telemetry.log_filter.clone(),
+            std::io::stderr,
+            tracer,
+        );
+        init_subscriber(subscriber, log_filter);
+        let outcome = import_subscribers(configuration, import).await;
+        shutdown_tracer();
+        return outcome;
+    }
+    let (subscriber, log_filter) = get_subscriber(
+        "zero2prod".into(),
+        configuration.telemetry.log_filter.clone(),
+        std::io::stdout,
+        tracer,
+    );
+    init_subscriber(subscriber, log_filter);
+    tracing::info!(
+        configuration = %configuration.redacted(),
+        "Loaded configuration."
+    );
+    let reload = configuration.reload.clone();
+    let config_file = arguments.config_file;
+    let application = Application::build_with_loader(configuration, {
+        let config_file = config_file.clone();
+        Box::new(move || load_configuration(config_file.as_deref()))
+    })
+    .await?;
+    if reload.watch_config_files {
+        actix_web::rt::spawn(reload_on_file_change(
+            application.reloader(),
+            configuration_files(&environment()?, config_file.as_deref())?,
+            Duration::from_secs(reload.watch_interval_seconds),
+        ));
+    }
     // Start the server
     application.run_until_stopped().await?;
+    shutdown_tracer();
+    Ok(())
+}
+
+/// Runs an import to completion in this process, rather than in the background of the server.
+async fn import_subscribers(
+    configuration: Settings,
+    import: ImportArguments,
+) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = EmailClient::new(
+        configuration.email_client.base_url.clone(),
+        configuration
+            .email_client
+            .sender()
+            .map_err(anyhow::Error::msg)?,
+        configuration.email_client.authorization_token.clone(),
+        configuration.email_client.timeout(),
+    );
+    let file = File::open(&import.file)
+        .with_context(|| format!("Failed to open {}.", import.file.display()))?;
+    let job_id = create_import_job(
+        &pool,
+        read_chunks(file),
+        usize::MAX,
+        &import.options,
+        "cli",
+        "cli",
+    )
+    .await?;
+    run_import_job(
+        &pool,
+        &email_client,
+        &configuration.application.base_url,
+        job_id,
+    )
+    .await?;
+    let job = get_import_job(&pool, job_id)
+        .await?
+        .context("The import job was deleted while it ran.")?;
+    println!("{}", serde_json::to_string_pretty(&job)?);
+    if let Some(report) = import.report {
+        let rows = list_rejected_rows(&pool, job_id).await?;
+        std::fs::write(&report, error_report(&rows))
+            .with_context(|| format!("Failed to write {}.", report.display()))?;
+    }
     Ok(())
 }
+
+/// Reads a file the way uploads come in, one chunk at a time.
+///
+/// Reads block, so they run on the blocking thread pool rather than on the runtime.
+fn read_chunks(file: File) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    futures_util::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let read = actix_web::rt::task::spawn_blocking(move || {
+            let mut chunk = vec![0; 64 * 1024];
+            let read = file.read(&mut chunk).map(|read| {
+                chunk.truncate(read);
+                chunk
+            });
+            (file, read)
+        })
+        .await;
+        match read {
+            Ok((_, Ok(chunk))) if chunk.is_empty() => None,
+            Ok((file, Ok(chunk))) => Some((Ok(chunk), Some(file))),
+            Ok((_, Err(e))) => Some((Err(e), None)),
+            Err(e) => Some((Err(std::io::Error::new(std::io::ErrorKind::Other, e)), None)),
+        }
+    })
+}
@@ -0,0 +1,281 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Every metric exposed on `/metrics`.
+///
+/// Each application gets its own registry, so that several can run in the same process.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    pub email: EmailMetrics,
+    pub subscriptions_created: IntCounter,
+    pub subscriptions_confirmed: IntCounter,
+    pub newsletter_deliveries: IntCounterVec,
+    newsletter_deliveries_pending: IntGauge,
+}
+
+/// Outcomes of the calls to the email provider.
+#[derive(Clone)]
+pub struct EmailMetrics {
+    attempts: IntCounter,
+    failures: IntCounter,
+    duration: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections opened by the Postgres pool.",
+            ),
+            &["state"],
+        )?;
+        let email = EmailMetrics {
+            attempts: IntCounter::new(
+                "email_send_attempts_total",
+                "Requests made to the email provider.",
+            )?,
+            failures: IntCounter::new(
+                "email_send_failures_total",
+                "Requests to the email provider that failed or timed out.",
+            )?,
+            duration: Histogram::with_opts(HistogramOpts::new(
+                "email_send_duration_seconds",
+                "Time taken by the email provider to accept an email.",
+            ))?,
+        };
+        let subscriptions_created = IntCounter::new(
+            "subscriptions_created_total",
+            "Subscriptions stored pending confirmation.",
+        )?;
+        let subscriptions_confirmed = IntCounter::new(
+            "subscriptions_confirmed_total",
+            "Subscriptions confirmed through their confirmation link.",
+        )?;
+        let newsletter_deliveries = IntCounterVec::new(
+            Opts::new(
+                "newsletter_deliveries_total",
+                "Newsletter issues delivered to subscribers, by outcome.",
+            ),
+            &["outcome"],
+        )?;
+        let newsletter_deliveries_pending = IntGauge::new(
+            "newsletter_deliveries_pending",
+            "Subscribers still waiting for the newsletter issues being published.",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(email.attempts.clone()))?;
+        registry.register(Box::new(email.failures.clone()))?;
+        registry.register(Box::new(email.duration.clone()))?;
+        registry.register(Box::new(subscriptions_created.clone()))?;
+        registry.register(Box::new(subscriptions_confirmed.clone()))?;
+        registry.register(Box::new(newsletter_deliveries.clone()))?;
+        registry.register(Box::new(newsletter_deliveries_pending.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            email,
+            subscriptions_created,
+            subscriptions_confirmed,
+            newsletter_deliveries,
+            newsletter_deliveries_pending,
+        })
+    }
+
+    /// Tracks the delivery of a newsletter issue to `recipients` subscribers.
+    pub fn start_delivery(&self, recipients: usize) -> DeliveryProgress {
+        let remaining = recipients as i64;
+        self.newsletter_deliveries_pending.add(remaining);
+        DeliveryProgress {
+            deliveries: self.newsletter_deliveries.clone(),
+            pending: self.newsletter_deliveries_pending.clone(),
+            remaining,
+        }
+    }
+
+    /// Encodes every metric in the Prometheus text format.
+    pub fn render(&self, pool: &PgPool) -> Result<String, anyhow::Error> {
+        let idle = pool.num_idle() as i64;
+        let size = i64::from(pool.size());
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl EmailMetrics {
+    /// Records a call to the email provider that started at `started`.
+    pub fn observe<T, E>(&self, started: Instant, outcome: &Result<T, E>) {
+        self.attempts.inc();
+        self.duration.observe(started.elapsed().as_secs_f64());
+        if outcome.is_err() {
+            self.failures.inc();
+        }
+    }
+}
+
+/// Progress of a newsletter delivery. Recipients that were not reached when it is dropped,
+/// e.g. because the delivery was aborted, are no longer counted as pending.
+pub struct DeliveryProgress {
+    deliveries: IntCounterVec,
+    pending: IntGauge,
+    remaining: i64,
+}
+
+impl DeliveryProgress {
+    /// `outcome` is one of `sent`, `failed` or `skipped`.
+    pub fn record(&mut self, outcome: &str) {
+        self.deliveries.with_label_values(&[outcome]).inc();
+        self.pending.dec();
+        self.remaining -= 1;
+    }
+}
+
+impl Drop for DeliveryProgress {
+    fn drop(&mut self) {
+        self.pending.sub(self.remaining);
+    }
+}
+
+#[tracing::instrument(name = "Render metrics", skip_all)]
+pub async fn metrics_endpoint(
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = metrics
+        .render(&pool)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type(TextEncoder::new().format_type())
+        .body(body))
+}
+
+/// Middleware that counts and times requests per route. It relies on `Metrics` being
+/// registered as application data.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+            let method = req.method().to_string();
+            // Label by route pattern rather than path, to keep the number of series bounded.
+            let route = req
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".to_string());
+            let started = Instant::now();
+
+            let outcome = service.call(req).await;
+
+            if let Some(metrics) = metrics {
+                let status = match &outcome {
+                    Ok(response) => response.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                };
+                metrics
+                    .http_requests
+                    .with_label_values(&[&method, &route, status.as_str()])
+                    .inc();
+                metrics
+                    .http_request_duration
+                    .with_label_values(&[&method, &route])
+                    .observe(started.elapsed().as_secs_f64());
+            }
+            outcome
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn aborted_deliveries_are_no_longer_pending() {
+        let metrics = Metrics::new().unwrap();
+
+        let mut progress = metrics.start_delivery(3);
+        progress.record("sent");
+        assert_eq!(metrics.newsletter_deliveries_pending.get(), 2);
+        drop(progress);
+
+        assert_eq!(metrics.newsletter_deliveries_pending.get(), 0);
+        assert_eq!(
+            metrics
+                .newsletter_deliveries
+                .with_label_values(&["sent"])
+                .get(),
+            1
+        );
+    }
+}
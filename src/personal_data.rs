@@ -0,0 +1,218 @@
+use crate::audit::{list_audit_events, AuditEvent, AuditEventFilters};
+use crate::consent::{list_consent_events, ConsentEvent};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Everything we hold about a subscriber, as handed out to answer a data subject request.
+///
+/// Newsletter deliveries are only counted, never recorded per subscriber, so there is no
+/// delivery history to include. Audit events only name subscribers by id.
+#[derive(Debug, serde::Serialize)]
+pub struct PersonalData {
+    pub subscription: Subscription,
+    /// Slugs of the lists the subscriber is a member of.
+    pub lists: Vec<String>,
+    pub confirmation_tokens: Vec<ConfirmationToken>,
+    pub email_changes: Vec<EmailChange>,
+    /// Proof of consent: the signup and its confirmation.
+    pub consent: Vec<ConsentEvent>,
+    /// Rows of bulk imports that held the address, whether or not they were imported.
+    pub import_rows: Vec<ImportRow>,
+    pub events: Vec<AuditEvent>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Subscription {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub locale: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub digest_frequency: String,
+    pub preferences_token: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ConfirmationToken {
+    pub subscription_token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct EmailChange {
+    pub new_email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ImportRow {
+    pub job_id: Uuid,
+    pub line: i32,
+    pub email: String,
+    pub name: String,
+    pub locale: String,
+    pub outcome: Option<String>,
+    pub error: Option<String>,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// Addresses are matched regardless of case. At most one subscriber matches, since
+/// `subscriptions_lower_email_key` keeps addresses unique regardless of case as well.
+#[tracing::instrument(name = "Find subscriber by email", skip(pool, email))]
+pub async fn find_subscriber_by_email(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1)",
+        email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    Ok(row.map(|row| row.id))
+}
+
+/// `None` if there is no such subscriber.
+#[tracing::instrument(name = "Export personal data", skip(pool))]
+pub async fn export_personal_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<PersonalData>, anyhow::Error> {
+    let subscription = sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT id, email, name, status, locale, subscribed_at, paused_until, digest_frequency,
+               preferences_token
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscription.")?;
+    let subscription = match subscription {
+        Some(subscription) => subscription,
+        None => return Ok(None),
+    };
+    let lists = sqlx::query!(
+        r#"
+        SELECT lists.slug
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE list_memberships.subscriber_id = $1
+        ORDER BY lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the list memberships.")?
+    .into_iter()
+    .map(|row| row.slug)
+    .collect();
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationToken,
+        r#"
+        SELECT subscription_token, created_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the confirmation tokens.")?;
+    let email_changes = sqlx::query_as!(
+        EmailChange,
+        r#"
+        SELECT new_email, created_at
+        FROM email_changes
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the email changes.")?;
+    let consent = list_consent_events(pool, subscriber_id).await?;
+    let import_rows = sqlx::query_as!(
+        ImportRow,
+        r#"
+        SELECT import_rows.job_id, import_rows.line, import_rows.email, import_rows.name,
+               import_rows.locale, import_rows.outcome, import_rows.error,
+               import_jobs.created_at AS uploaded_at
+        FROM import_rows
+        JOIN import_jobs ON import_jobs.job_id = import_rows.job_id
+        WHERE lower(import_rows.email) = lower($1)
+        ORDER BY import_jobs.created_at, import_rows.line
+        "#,
+        subscription.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the import rows.")?;
+    let filters = AuditEventFilters {
+        target: Some(subscriber_id.to_string()),
+        ..AuditEventFilters::default()
+    };
+    let (events, _) = list_audit_events(pool, &filters, i64::MAX, 0).await?;
+
+    Ok(Some(PersonalData {
+        subscription,
+        lists,
+        confirmation_tokens,
+        email_changes,
+        consent,
+        import_rows,
+        events,
+    }))
+}
+
+/// Deletes the subscriber, along with their tokens, list memberships, pending email changes,
+/// consent history and the rows of bulk imports that held their address. Returns `false` if
+/// there was no such subscriber.
+///
+/// Audit events are append-only and outlive the erasure, which is why they only name
+/// subscribers by id.
+#[tracing::instrument(name = "Erase subscriber", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let erased = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber.")?;
+    let email = match erased {
+        Some(row) => row.email,
+        None => return Ok(false),
+    };
+    // Also rows not processed yet, which would otherwise bring the subscriber back.
+    sqlx::query!(
+        "DELETE FROM import_rows WHERE lower(email) = lower($1)",
+        email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the import rows of the subscriber.")?;
+    // Rate limit buckets are keyed by address. Buckets kept in memory expire on their own.
+    sqlx::query!(
+        "DELETE FROM rate_limit_buckets WHERE key = $1",
+        format!("email:{}", email.to_lowercase())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the rate limit bucket of the subscriber.")?;
+    Ok(true)
+}
@@ -0,0 +1,218 @@
+use crate::abuse_protection::RateLimiter;
+use crate::configuration::{ConfigurationError, Settings};
+use crate::email_client::EmailClient;
+use crate::routes::error_chain_fmt;
+use crate::telemetry;
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Produces the configuration to reload, usually by calling `load_configuration` again.
+pub type SettingsLoader = Box<dyn Fn() -> Result<Settings, ConfigurationError> + Send + Sync>;
+
+#[derive(thiserror::Error)]
+pub enum ReloadError {
+    #[error("The new configuration is invalid.")]
+    InvalidConfiguration(#[from] ConfigurationError),
+    #[error("Changing {} requires a restart.", .0.join(", "))]
+    RestartRequired(Vec<&'static str>),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Applies configuration changes to a running application.
+///
+/// Only the email client, rate limits and log filter are reloadable. A reload that changes
+/// anything else is rejected as a whole, and the running configuration is left untouched.
+pub struct Reloader {
+    current: Mutex<Settings>,
+    loader: SettingsLoader,
+    email_client: Arc<EmailClient>,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl Reloader {
+    pub fn new(
+        current: Settings,
+        loader: SettingsLoader,
+        email_client: Arc<EmailClient>,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> Self {
+        Self {
+            current: Mutex::new(current),
+            loader,
+            email_client,
+            rate_limiter,
+        }
+    }
+
+    #[tracing::instrument(name = "Reload configuration", skip(self))]
+    pub fn reload(&self) -> Result<(), ReloadError> {
+        let new = (self.loader)()?;
+        new.validate().map_err(ConfigurationError::from)?;
+
+        let mut current = self.current.lock().unwrap();
+        let changes = restart_required_changes(&current, &new);
+        if !changes.is_empty() {
+            return Err(ReloadError::RestartRequired(changes));
+        }
+        if new == *current {
+            tracing::info!("The configuration did not change.");
+            return Ok(());
+        }
+
+        if new.telemetry.log_filter != current.telemetry.log_filter {
+            if let Some(log_filter) = telemetry::log_filter() {
+                log_filter.set(&new.telemetry.log_filter)?;
+            }
+        }
+        if new.email_client != current.email_client {
+            let sender = new.email_client.sender().map_err(anyhow::Error::msg)?;
+            self.email_client.reconfigure(
+                new.email_client.base_url.clone(),
+                sender,
+                new.email_client.authorization_token.clone(),
+                new.email_client.timeout(),
+            );
+        }
+        if new.rate_limit != current.rate_limit {
+            self.rate_limiter.update_limits(&new.rate_limit);
+        }
+
+        *current = new;
+        tracing::info!(configuration = %current.redacted(), "Reloaded configuration.");
+        Ok(())
+    }
+}
+
+/// Sections of the configuration that differ, but are only read at startup.
+fn restart_required_changes(current: &Settings, new: &Settings) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if current.application != new.application {
+        changes.push("application");
+    }
+    if current.database != new.database {
+        changes.push("database");
+    }
+    if current.rate_limit.store != new.rate_limit.store {
+        changes.push("rate_limit.store");
+    }
+    if current.challenge != new.challenge {
+        changes.push("challenge");
+    }
+    if current.login_protection != new.login_protection {
+        changes.push("login_protection");
+    }
+    if current.two_factor != new.two_factor {
+        changes.push("two_factor");
+    }
+    if current.telemetry.otlp != new.telemetry.otlp {
+        changes.push("telemetry.otlp");
+    }
+    if current.metrics != new.metrics {
+        changes.push("metrics");
+    }
+    if current.health != new.health {
+        changes.push("health");
+    }
+    if current.reload != new.reload {
+        changes.push("reload");
+    }
+    if current.shutdown != new.shutdown {
+        changes.push("shutdown");
+    }
+    if current.security != new.security {
+        changes.push("security");
+    }
+    if current.limits != new.limits {
+        changes.push("limits");
+    }
+    if current.pages != new.pages {
+        changes.push("pages");
+    }
+    changes
+}
+
+fn log_reload_outcome(outcome: Result<(), ReloadError>) {
+    if let Err(e) = outcome {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to reload the configuration. Keeping the current one."
+        );
+    }
+}
+
+/// Reloads the configuration every time the process receives `SIGHUP`.
+pub async fn reload_on_sighup(reloader: Arc<Reloader>) -> Result<(), std::io::Error> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        tracing::info!("Received SIGHUP.");
+        log_reload_outcome(reloader.reload());
+    }
+    Ok(())
+}
+
+/// Reloads the configuration whenever one of `files` is modified, checking every `interval`.
+///
+/// Polling keeps this portable and works with the symlink swaps used by Kubernetes mounts.
+pub async fn reload_on_file_change(
+    reloader: Arc<Reloader>,
+    files: Vec<PathBuf>,
+    interval: Duration,
+) {
+    let modified = |files: &[PathBuf]| -> Vec<Option<SystemTime>> {
+        files
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let mut last_seen = modified(&files);
+    let mut ticks = actix_web::rt::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let current = modified(&files);
+        if current != last_seen {
+            tracing::info!("A configuration file changed.");
+            last_seen = current;
+            log_reload_outcome(reloader.reload());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::restart_required_changes;
+    use crate::configuration::get_configuration;
+
+    #[test]
+    fn reloadable_changes_do_not_require_a_restart() {
+        let current = get_configuration().unwrap();
+        let mut new = current.clone();
+        new.email_client.timeout_milliseconds += 1;
+        new.rate_limit.per_ip.capacity += 1;
+        new.telemetry.log_filter = "debug".into();
+
+        assert!(restart_required_changes(&current, &new).is_empty());
+    }
+
+    #[test]
+    fn listener_and_database_changes_require_a_restart() {
+        let current = get_configuration().unwrap();
+        let mut new = current.clone();
+        new.application.port += 1;
+        new.database.database_name = "another".into();
+
+        assert_eq!(
+            restart_required_changes(&current, &new),
+            vec!["application", "database"]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    generate_api_token, list_api_tokens, revoke_api_token, store_api_token, ApiToken,
+    Authenticator, Permission,
+};
+use crate::routes::admin::AdminError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct NewApiTokenData {
+    name: String,
+    scopes: Vec<Permission>,
+    expires_in_days: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CreatedApiToken {
+    /// The plaintext token. It cannot be retrieved again.
+    token: String,
+    #[serde(flatten)]
+    metadata: ApiToken,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ApiTokenPath {
+    token_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty, token_name=%body.name)
+)]
+pub async fn create_api_token(
+    body: web::Json<NewApiTokenData>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    if user.scopes.is_some() {
+        return Err(AdminError::Forbidden(
+            "API tokens cannot be used to create other API tokens.".into(),
+        ));
+    }
+    if body.name.trim().is_empty() {
+        return Err(AdminError::ValidationError(
+            "The token name must not be empty.".into(),
+        ));
+    }
+    if body.scopes.is_empty() {
+        return Err(AdminError::ValidationError(
+            "At least one scope must be requested.".into(),
+        ));
+    }
+    // A token can never do more than its owner.
+    for scope in &body.scopes {
+        user.authorize(*scope)?;
+    }
+
+    let token = generate_api_token();
+    let expires_at = body
+        .expires_in_days
+        .map(|days| Utc::now() + chrono::Duration::days(days.into()));
+    let metadata = store_api_token(
+        &pool,
+        user.user_id,
+        &body.name,
+        &token,
+        &body.scopes,
+        expires_at,
+    )
+    .await?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::CreateApiToken,
+        Some(&metadata.token_id.to_string()),
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken { token, metadata }))
+}
+
+#[tracing::instrument(
+    name = "List API tokens",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_api_tokens(
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    let tokens = list_api_tokens(&pool, user.user_id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty, token_id=%path.token_id)
+)]
+pub async fn delete_api_token(
+    path: web::Path<ApiTokenPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    if !revoke_api_token(&pool, user.user_id, path.token_id).await? {
+        return Err(AdminError::NotFound(
+            "There is no active API token with this id.".into(),
+        ));
+    }
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::RevokeApiToken,
+        Some(&path.token_id.to_string()),
+    )
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
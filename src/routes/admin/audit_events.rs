@@ -0,0 +1,89 @@
+use crate::audit::{list_audit_events, AuditAction, AuditEvent, AuditEventFilters};
+use crate::authentication::{Authenticator, Permission};
+use crate::routes::admin::AdminError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct AuditEventsQuery {
+    actor: Option<String>,
+    action: Option<AuditAction>,
+    target: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    /// 1-based.
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditEventsPage {
+    events: Vec<AuditEvent>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+#[tracing::instrument(
+    name = "Query the audit log",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_audit_events(
+    query: web::Query<AuditEventsQuery>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ViewAuditLog)?;
+
+    let AuditEventsQuery {
+        actor,
+        action,
+        target,
+        since,
+        until,
+        page,
+        per_page,
+    } = query.into_inner();
+    let page = page.unwrap_or(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page == 0 {
+        return Err(AdminError::ValidationError(
+            "Pages are numbered from 1.".into(),
+        ));
+    }
+    if per_page == 0 || per_page > MAX_PAGE_SIZE {
+        return Err(AdminError::ValidationError(format!(
+            "per_page must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    let filters = AuditEventFilters {
+        actor,
+        action,
+        target,
+        since,
+        until,
+    };
+    let (events, total) = list_audit_events(
+        &pool,
+        &filters,
+        per_page.into(),
+        i64::from(page - 1) * i64::from(per_page),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(AuditEventsPage {
+        events,
+        page,
+        per_page,
+        total,
+    }))
+}
@@ -0,0 +1,142 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authenticator, Permission};
+use crate::email_client::EmailClient;
+use crate::import::{
+    create_import_job, error_report, get_import_job, list_rejected_rows, spawn_import_job,
+    ImportError, ImportOptions,
+};
+use crate::limits::{LimitError, UploadLimit};
+use crate::routes::admin::AdminError;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::client_ip;
+use actix_http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ImportPath {
+    job_id: Uuid,
+}
+
+impl From<ImportError> for AdminError {
+    fn from(e: ImportError) -> Self {
+        match e {
+            ImportError::InvalidCsv(_)
+            | ImportError::UnknownList(_)
+            | ImportError::UploadFailed(_) => Self::ValidationError(e.to_string()),
+            ImportError::TooLarge(max_body_bytes) => {
+                LimitError::PayloadTooLarge(max_body_bytes).into()
+            }
+            ImportError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+/// Starts importing the subscribers of the CSV file in the body, see `create_import_job`.
+///
+/// Rows are imported in the background: the response describes the job, which can be polled
+/// at its `Location`.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Start subscriber import",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn start_import(
+    query: web::Query<ImportOptions>,
+    body: web::Payload,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    upload_limit: web::Data<UploadLimit>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ImportSubscribers)?;
+
+    let job_id = create_import_job(
+        &pool,
+        body,
+        upload_limit.0,
+        &query,
+        &user.username,
+        &client_ip(&request),
+    )
+    .await?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::ImportSubscribers,
+        Some(&job_id.to_string()),
+    )
+    .await?;
+    let job = get_import_job(&pool, job_id)
+        .await?
+        .context("The import job was deleted as it was created")?;
+    spawn_import_job(
+        pool.get_ref().clone(),
+        email_client.into_inner(),
+        base_url.0.clone(),
+        job_id,
+    );
+
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/admin/imports/{}", job_id)))
+        .json(job))
+}
+
+/// Progress of an import.
+#[tracing::instrument(
+    name = "Get subscriber import",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_import(
+    path: web::Path<ImportPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ImportSubscribers)?;
+
+    let job = get_import_job(&pool, path.job_id)
+        .await?
+        .ok_or_else(|| AdminError::NotFound("The import does not exist.".into()))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// The rows of an import that were not imported so far, and why, as a CSV file.
+#[tracing::instrument(
+    name = "Get subscriber import errors",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_import_errors(
+    path: web::Path<ImportPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ImportSubscribers)?;
+
+    if get_import_job(&pool, path.job_id).await?.is_none() {
+        return Err(AdminError::NotFound("The import does not exist.".into()));
+    }
+    let rows = list_rejected_rows(&pool, path.job_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                r#"attachment; filename="import-{}-errors.csv""#,
+                path.job_id
+            ),
+        ))
+        .body(error_report(&rows)))
+}
@@ -0,0 +1,100 @@
+mod api_tokens;
+mod audit_events;
+mod imports;
+mod reports;
+mod subscribers;
+mod telemetry;
+mod two_factor;
+mod users;
+
+pub use api_tokens::*;
+pub use audit_events::*;
+pub use imports::*;
+pub use reports::*;
+pub use subscribers::*;
+pub use telemetry::*;
+pub use two_factor::*;
+pub use users::*;
+
+use crate::api_error::ApiError;
+use crate::authentication::AuthError;
+use crate::limits::LimitError;
+use crate::routes::error_chain_fmt;
+use crate::utils::too_many_requests;
+use actix_http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::time::Duration;
+
+/// Errors shared by every endpoint under `/admin`.
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts.")]
+    LockedOut { retry_after: Duration },
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error(transparent)]
+    LimitError(#[from] LimitError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for AdminError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => Self::AuthError(e.into()),
+            AuthError::LockedOut { retry_after } => Self::LockedOut { retry_after },
+            AuthError::Forbidden(_) => Self::Forbidden(e.to_string()),
+            AuthError::UnexpectedError(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<&AdminError> for ApiError {
+    fn from(e: &AdminError) -> Self {
+        match e {
+            AdminError::AuthError(_) => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "invalid_credentials")
+                    .with_basic_challenge("admin")
+            }
+            AdminError::LockedOut { retry_after } => too_many_requests(*retry_after),
+            AdminError::Forbidden(detail) => {
+                ApiError::new(StatusCode::FORBIDDEN, "forbidden").with_detail(detail)
+            }
+            AdminError::ValidationError(detail) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "validation_failed").with_detail(detail)
+            }
+            AdminError::Conflict(detail) => {
+                ApiError::new(StatusCode::CONFLICT, "conflict").with_detail(detail)
+            }
+            AdminError::NotFound(detail) => {
+                ApiError::new(StatusCode::NOT_FOUND, "not_found").with_detail(detail)
+            }
+            AdminError::LimitError(e) => e.into(),
+            AdminError::UnexpectedError(_) => ApiError::internal(),
+        }
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
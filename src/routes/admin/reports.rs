@@ -0,0 +1,40 @@
+use crate::authentication::{Authenticator, Permission};
+use crate::routes::admin::AdminError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionsReport {
+    confirmed: i64,
+    pending_confirmation: i64,
+}
+
+#[tracing::instrument(
+    name = "Get subscriptions report",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn subscriptions_report(
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ViewReports)?;
+
+    let report = sqlx::query_as!(
+        SubscriptionsReport,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'confirmed') AS "confirmed!",
+            COUNT(*) FILTER (WHERE status = 'pending_confirmation') AS "pending_confirmation!"
+        FROM subscriptions
+        "#
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to compute the subscriptions report.")?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,165 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authenticator, Permission};
+use crate::export::{stream_subscribers, ExportFilters, ExportFormat, SubscriptionStatus};
+use crate::personal_data::{erase_subscriber, export_personal_data, find_subscriber_by_email};
+use crate::routes::admin::AdminError;
+use actix_http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberPath {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    list: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+/// All subscribers, or those matching the query, as a CSV or NDJSON download.
+///
+/// The response is streamed as subscribers are read, see `stream_subscribers`.
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscribers(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ExportSubscribers)?;
+
+    let ExportQuery {
+        format,
+        status,
+        list,
+        since,
+        until,
+    } = query.into_inner();
+    if let Some(list) = &list {
+        let exists = sqlx::query!("SELECT list_id FROM lists WHERE slug = $1", list)
+            .fetch_optional(pool.get_ref())
+            .await
+            .context("Failed to look up the list to export.")?
+            .is_some();
+        if !exists {
+            return Err(AdminError::ValidationError(format!(
+                "There is no list named `{}`.",
+                list
+            )));
+        }
+    }
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::ExportSubscribers,
+        None,
+    )
+    .await?;
+
+    let filters = ExportFilters {
+        status,
+        list,
+        since,
+        until,
+    };
+    let chunks = stream_subscribers(pool.get_ref().clone(), filters, format);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                r#"attachment; filename="subscribers.{}""#,
+                format.extension()
+            ),
+        ))
+        .streaming(chunks.map(|chunk| chunk.map_err(AdminError::from))))
+}
+
+/// Everything we hold about an email address, to answer a data subject access request.
+#[tracing::instrument(
+    name = "Export subscriber data",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscriber_data(
+    path: web::Path<SubscriberPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ExportSubscribers)?;
+
+    let data = match find_subscriber_by_email(&pool, &path.email).await? {
+        Some(subscriber_id) => export_personal_data(&pool, subscriber_id).await?,
+        None => None,
+    }
+    .ok_or_else(|| AdminError::NotFound("The subscriber does not exist.".into()))?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::ExportSubscribers,
+        Some(&data.subscription.id.to_string()),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// Hard-deletes everything we hold about an email address, to answer a request for erasure.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn erase_subscriber_data(
+    path: web::Path<SubscriberPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::EraseSubscribers)?;
+
+    let not_found = || AdminError::NotFound("The subscriber does not exist.".into());
+    let subscriber_id = find_subscriber_by_email(&pool, &path.email)
+        .await?
+        .ok_or_else(not_found)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !erase_subscriber(&mut transaction, subscriber_id).await? {
+        return Err(not_found());
+    }
+    record_audit_event(
+        &mut transaction,
+        &request,
+        (&user).into(),
+        AuditAction::DeleteSubscriber,
+        Some(&subscriber_id.to_string()),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
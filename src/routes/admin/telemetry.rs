@@ -0,0 +1,79 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authenticator, Permission};
+use crate::routes::admin::AdminError;
+use crate::telemetry::{self, LogFilterHandle};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LogFilter {
+    /// In `RUST_LOG` syntax, e.g. `info,zero2prod::routes=trace`.
+    directives: String,
+}
+
+fn log_filter_handle() -> Result<&'static LogFilterHandle, AdminError> {
+    telemetry::log_filter()
+        .ok_or_else(|| anyhow::anyhow!("The global subscriber was not initialised.").into())
+}
+
+#[tracing::instrument(
+    name = "Get the log filter",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_log_filter(
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageTelemetry)?;
+
+    let directives = log_filter_handle()?.current()?;
+    Ok(HttpResponse::Ok().json(LogFilter { directives }))
+}
+
+/// Replaces the log filter until the next restart, or until a configuration reload changes
+/// `telemetry.log_filter`.
+#[tracing::instrument(
+    name = "Set the log filter",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn set_log_filter(
+    body: web::Json<LogFilter>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageTelemetry)?;
+
+    let directives = body.into_inner().directives;
+    if directives.trim().is_empty() {
+        return Err(AdminError::ValidationError(
+            "The log filter must not be empty.".into(),
+        ));
+    }
+    tracing_subscriber::EnvFilter::try_new(&directives)
+        .map_err(|e| AdminError::ValidationError(format!("Invalid log filter: {}.", e)))?;
+
+    let handle = log_filter_handle()?;
+    let previous = handle.current()?;
+    handle.set(&directives)?;
+    tracing::info!(
+        log_filter.previous = %previous,
+        log_filter.current = %directives,
+        "Changed the log filter."
+    );
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::ChangeLogFilter,
+        Some(&directives),
+    )
+    .await?;
+
+    let directives = handle.current()?;
+    Ok(HttpResponse::Ok().json(LogFilter { directives }))
+}
@@ -0,0 +1,138 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    disable_two_factor as remove_two_factor, enable_two_factor, get_two_factor_enrolment,
+    store_pending_two_factor, AuthenticatedUser, Authenticator, TotpSecret,
+};
+use crate::routes::admin::AdminError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+pub struct TwoFactorEnrolmentData {
+    /// base32 secret, for authenticator apps that cannot scan a QR code.
+    secret: String,
+    /// `otpauth://` URI, to be rendered as a QR code.
+    provisioning_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorConfirmationData {
+    code: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecoveryCodes {
+    /// Single-use codes accepted in place of a TOTP code. They cannot be retrieved again.
+    recovery_codes: Vec<String>,
+}
+
+/// Second factor settings belong to a person, so API tokens cannot change them.
+fn ensure_not_api_token(user: &AuthenticatedUser) -> Result<(), AdminError> {
+    if user.scopes.is_some() {
+        return Err(AdminError::Forbidden(
+            "API tokens cannot manage two-factor authentication.".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Start two-factor enrolment",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn enrol_two_factor(
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    ensure_not_api_token(&user)?;
+    let enrolment = get_two_factor_enrolment(&pool, authenticator.cipher(), user.user_id).await?;
+    if matches!(enrolment, Some(enrolment) if enrolment.enabled) {
+        return Err(AdminError::Conflict(
+            "Two-factor authentication is already enabled. Disable it first to enrol again.".into(),
+        ));
+    }
+
+    let secret = TotpSecret::generate();
+    store_pending_two_factor(&pool, authenticator.cipher(), user.user_id, &secret).await?;
+
+    Ok(HttpResponse::Created().json(TwoFactorEnrolmentData {
+        secret: secret.to_base32(),
+        provisioning_uri: secret
+            .provisioning_uri(authenticator.two_factor_issuer(), &user.username),
+    }))
+}
+
+#[tracing::instrument(
+    name = "Confirm two-factor enrolment",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn confirm_two_factor(
+    body: web::Json<TwoFactorConfirmationData>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    ensure_not_api_token(&user)?;
+    let enrolment =
+        match get_two_factor_enrolment(&pool, authenticator.cipher(), user.user_id).await? {
+            Some(enrolment) if !enrolment.enabled => enrolment,
+            Some(_) => {
+                return Err(AdminError::Conflict(
+                    "Two-factor authentication is already enabled.".into(),
+                ))
+            }
+            None => {
+                return Err(AdminError::NotFound(
+                    "There is no pending two-factor enrolment.".into(),
+                ))
+            }
+        };
+    // Proves that the authenticator app was set up correctly before we start requiring it.
+    let step = enrolment
+        .secret
+        .verify(body.code.trim(), Utc::now(), None)
+        .ok_or_else(|| AdminError::ValidationError("Invalid two-factor code.".into()))?;
+
+    let recovery_codes = enable_two_factor(&pool, user.user_id, step).await?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::EnableTwoFactor,
+        None,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn disable_two_factor(
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    // When enabled, the second factor is checked as part of authentication.
+    let user = authenticator.authenticate(&request).await?;
+    ensure_not_api_token(&user)?;
+    remove_two_factor(&pool, user.user_id).await?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::DisableTwoFactor,
+        None,
+    )
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,276 @@
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    compute_password_hash, spawn_blocking_with_tracing, Authenticator, Permission, Role,
+};
+use crate::routes::admin::AdminError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+
+#[derive(serde::Serialize)]
+pub struct UserSummary {
+    user_id: Uuid,
+    username: String,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewUserData {
+    username: String,
+    password: String,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleData {
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UserPath {
+    user_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "List users",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageUsers)?;
+
+    let rows = sqlx::query!("SELECT user_id, username, role FROM users ORDER BY username")
+        .fetch_all(pool.get_ref())
+        .await
+        .context("Failed to fetch users.")?;
+    let users = rows
+        .into_iter()
+        .map(|row| {
+            Ok(UserSummary {
+                user_id: row.user_id,
+                username: row.username,
+                role: row.role.try_into().map_err(anyhow::Error::msg)?,
+            })
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[tracing::instrument(
+    name = "Create a user",
+    skip_all,
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        new_username=%body.username,
+        new_role=%body.role
+    )
+)]
+pub async fn create_user(
+    body: web::Json<NewUserData>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageUsers)?;
+
+    let NewUserData {
+        username,
+        password,
+        role,
+    } = body.0;
+    if username.trim().is_empty() {
+        return Err(AdminError::ValidationError(
+            "The username must not be empty.".into(),
+        ));
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AdminError::ValidationError(format!(
+            "The password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let new_user_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        new_user_id,
+        username,
+        password_hash,
+        role.as_str()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the new user.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(AdminError::Conflict(format!(
+            "The username {} is already taken.",
+            username
+        )));
+    }
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::CreateUser,
+        Some(&new_user_id.to_string()),
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(UserSummary {
+        user_id: new_user_id,
+        username,
+        role,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip_all,
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        target_user_id=%path.user_id,
+        new_role=%body.role
+    )
+)]
+pub async fn change_user_role(
+    path: web::Path<UserPath>,
+    body: web::Json<RoleData>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageUsers)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    lock_admins(&mut transaction).await?;
+    let updated = sqlx::query!(
+        "UPDATE users SET role = $1 WHERE user_id = $2",
+        body.role.as_str(),
+        path.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the role of the user.")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(AdminError::NotFound("The user does not exist.".into()));
+    }
+    ensure_an_admin_remains(&mut transaction).await?;
+    record_audit_event(
+        &mut transaction,
+        &request,
+        (&user).into(),
+        AuditAction::ChangeUserRole,
+        Some(&path.user_id.to_string()),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Delete a user",
+    skip_all,
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        target_user_id=%path.user_id
+    )
+)]
+pub async fn delete_user(
+    path: web::Path<UserPath>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::ManageUsers)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    lock_admins(&mut transaction).await?;
+    let deleted = sqlx::query!("DELETE FROM users WHERE user_id = $1", path.user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the user.")?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(AdminError::NotFound("The user does not exist.".into()));
+    }
+    ensure_an_admin_remains(&mut transaction).await?;
+    record_audit_event(
+        &mut transaction,
+        &request,
+        (&user).into(),
+        AuditAction::DeleteUser,
+        Some(&path.user_id.to_string()),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Keeps concurrent requests from demoting or deleting admins until the transaction ends.
+/// Otherwise two of them could each remove one of the last two admins, both counting the
+/// other one as remaining in `ensure_an_admin_remains`.
+async fn lock_admins(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), AdminError> {
+    sqlx::query!("SELECT user_id FROM users WHERE role = 'admin' FOR UPDATE")
+        .fetch_all(transaction)
+        .await
+        .context("Failed to lock the admins.")?;
+    Ok(())
+}
+
+/// Nobody could manage users anymore if the last admin were demoted or deleted.
+async fn ensure_an_admin_remains(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), AdminError> {
+    let admins = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM users WHERE role = 'admin'"#)
+        .fetch_one(transaction)
+        .await
+        .context("Failed to count admins.")?
+        .count;
+    if admins == 0 {
+        return Err(AdminError::Conflict(
+            "At least one admin must remain.".into(),
+        ));
+    }
+    Ok(())
+}
@@ -1,5 +1,175 @@
-use actix_web::{HttpResponse, Responder};
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
+use actix_web::{web, HttpResponse, Responder};
+use anyhow::Context;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
 
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Kept for the probes that were configured before `/health/live` existed.
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().finish()
 }
+
+/// The process is up and serving requests. Says nothing about its dependencies.
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Unavailable,
+}
+
+#[derive(serde::Serialize)]
+struct CheckResult {
+    status: Status,
+    duration_ms: u128,
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    status: Status,
+    checks: BTreeMap<&'static str, CheckResult>,
+}
+
+/// The application can serve traffic: Postgres answers, its schema is up-to-date and, if
+/// enabled, the email provider can be reached.
+///
+/// The checks run concurrently. Responds with `503 Service Unavailable` if any fails, along with
+/// a breakdown per dependency. Why a check failed is logged, not served.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let email_provider = async {
+        if settings.check_email_provider {
+            Some(run_check("email_provider", timeout, email_client.check_reachable()).await)
+        } else {
+            None
+        }
+    };
+    let (database, migrations, email_provider) = futures_util::join!(
+        run_check("database", timeout, check_database(&pool)),
+        run_check("migrations", timeout, check_migrations(&pool)),
+        email_provider
+    );
+    let mut checks = BTreeMap::new();
+    checks.insert("database", database);
+    checks.insert("migrations", migrations);
+    if let Some(email_provider) = email_provider {
+        checks.insert("email_provider", email_provider);
+    }
+
+    let ready = checks
+        .values()
+        .all(|check| matches!(check.status, Status::Ok));
+    let readiness = Readiness {
+        status: if ready {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        },
+        checks,
+    };
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        tracing::warn!("The application is not ready.");
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn run_check<F, E>(name: &'static str, timeout: Duration, check: F) -> CheckResult
+where
+    F: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    let started = Instant::now();
+    let error = match actix_web::rt::time::timeout(timeout, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("Timed out after {}ms.", timeout.as_millis())),
+    };
+    // Logged rather than served: errors may tell the address or version of a dependency.
+    if let Some(error) = &error {
+        tracing::warn!(check = name, error = %error, "A readiness check failed.");
+    }
+    CheckResult {
+        status: if error.is_none() {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        },
+        duration_ms: started.elapsed().as_millis(),
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT 1 AS one").fetch_one(pool).await?;
+    Ok(())
+}
+
+/// Fails if a migration shipped with this build has not been applied successfully.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: HashSet<i64> =
+        sqlx::query!("SELECT version FROM _sqlx_migrations WHERE success = true")
+            .fetch_all(pool)
+            .await
+            .context("Failed to read the applied migrations")?
+            .into_iter()
+            .map(|row| row.version)
+            .collect();
+    let pending: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| migration.version.to_string())
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Pending migrations: {}.",
+            pending.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_database, run_check, Status};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn an_unreachable_database_is_reported() {
+        // Nothing listens on port 1.
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::from_millis(500))
+            .connect_lazy_with(PgConnectOptions::new().host("127.0.0.1").port(1));
+
+        let result = run_check("database", Duration::from_secs(2), check_database(&pool)).await;
+
+        assert!(matches!(result.status, Status::Unavailable));
+    }
+
+    #[tokio::test]
+    async fn slow_checks_time_out() {
+        let never = futures_util::future::pending::<Result<(), String>>();
+
+        let result = run_check("never", Duration::from_millis(10), never).await;
+
+        assert!(matches!(result.status, Status::Unavailable));
+        assert!(result.duration_ms < 1000);
+    }
+}
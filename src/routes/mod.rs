@@ -1,9 +1,15 @@
+mod admin;
 mod health_check;
 mod newsletters;
+mod pages;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
 
+pub use admin::*;
 pub use health_check::*;
 pub use newsletters::*;
+pub use pages::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
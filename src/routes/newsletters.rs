@@ -1,16 +1,43 @@
-use actix_http::{
-    header::{self, HeaderMap, HeaderValue},
-    StatusCode,
-};
+use actix_http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient, routes::error_chain_fmt};
+use crate::{
+    api_error::ApiError,
+    audit::{record_audit_event, AuditAction},
+    authentication::{AuthError, Authenticator, Permission},
+    digest::queue_for_digests,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    i18n::Locale,
+    metrics::Metrics,
+    routes::{error_chain_fmt, preferences_token, EmailFooter, DEFAULT_LIST},
+    startup::ApplicationBaseUrl,
+    utils::too_many_requests,
+};
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
+    #[serde(flatten)]
+    issue: IssueSource,
+    /// Slug of the list to send the issue to. Defaults to `DEFAULT_LIST`.
+    list: Option<String>,
+}
+
+/// What to send: an issue given in full, or a draft stored with `create_draft`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum IssueSource {
+    Draft { draft_id: Uuid },
+    Issue(Issue),
+}
+
+#[derive(serde::Deserialize)]
+pub struct Issue {
     title: String,
     content: Content,
 }
@@ -21,121 +48,6 @@ pub struct Content {
     text: String,
 }
 
-struct Credentials {
-    #[allow(dead_code)]
-    username: String,
-    #[allow(dead_code)]
-    password: String,
-}
-
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header is missing.")?
-        .to_str()
-        .context("The 'Authorization' header is not a valid UTF-8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authentication scheme is not 'Basic'.")?;
-    let decoded_bytes = base64::decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF-8.")?;
-
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-
-    Ok(Credentials { username, password })
-}
-
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    // expected_password_hash is stored in PHC string format: "${algorithm}${algorithm version}${$-separated algorithm parameters}${hash}${salt}"
-    let (user_id, expected_password_hash_phc) = get_stored_credentials(&credentials.username, pool)
-        .await
-        .map_err(PublishError::UnexpectedError)?
-        // Using ok_or_else converts the Option to Result and makes it convenient to propagate any Err with `?`.
-        .ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username.")))?;
-
-    // let current_span = tracing::Span::current();
-    // actix_web::rt::task::spawn_blocking(move || {
-    //     // tracing::info_span!("Verify password hash")
-    //     //     .in_scope(|| verify_password_hash(expected_password_hash_phc, credentials.password))
-    //     current_span
-    //         .in_scope(|| verify_password_hash(expected_password_hash_phc, credentials.password))
-    // })
-
-    // Move CPU-intensive hashing to a separate thread
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash_phc, credentials.password)
-    })
-    .await
-    .context("failed to spawn blocking task.")
-    .map_err(PublishError::UnexpectedError)??;
-
-    Ok(user_id)
-}
-
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash_phc, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash_phc: String,
-    password_candidate: String,
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(&expected_password_hash_phc)
-        .context("Failed to parse hash in PHC string format")
-        .map_err(PublishError::UnexpectedError)?;
-
-    // Execute the function within the scope of this span.
-    Argon2::default()
-        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
-        .context("Invalid password")
-        .map_err(PublishError::AuthError)
-}
-
-// Copied function signature from `spawn_blocking`
-pub fn spawn_blocking_with_tracing<F, R>(f: F) -> actix_web::rt::task::JoinHandle<R>
-where
-    F: FnOnce() -> R + Send + 'static,
-    R: Send + 'static,
-{
-    let current_span = tracing::Span::current();
-    actix_web::rt::task::spawn_blocking(move || current_span.in_scope(f))
-}
-
-#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, String)>, anyhow::Error> {
-    let row: Option<_> = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash
-        FROM users
-        WHERE username = $1
-        "#,
-        username,
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to perform a query to retrieve stored credentials.")?
-    .map(|row| (row.user_id, row.password_hash));
-
-    Ok(row)
-}
-
 #[tracing::instrument(
     name = "Publish a newsletter issue",
     // skip(body, pool, email_client, request),
@@ -148,32 +60,50 @@ pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    authenticator: web::Data<Authenticator>,
+    metrics: web::Data<Metrics>,
+    base_url: web::Data<ApplicationBaseUrl>,
     request: web::HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::PublishNewsletters)?;
 
-    let subscribers = get_confirmed_subscribers(&pool).await?;
+    let BodyData { issue, list } = body.0;
+    let (issue, draft_id) = match issue {
+        IssueSource::Issue(issue) => (issue, None),
+        IssueSource::Draft { draft_id } => (get_draft(&pool, draft_id).await?, Some(draft_id)),
+    };
+    let list = list.as_deref().unwrap_or(DEFAULT_LIST);
+    let list_id = find_list(&pool, list).await?;
+    let subscribers = get_confirmed_subscribers(&pool, list_id).await?;
+    let mut progress = metrics.start_delivery(subscribers.len());
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                email_client
+                let token = match subscriber.preferences_token {
+                    Some(token) => token,
+                    None => preferences_token(pool.get_ref(), subscriber.id)
+                        .await
+                        .context("Failed to create a preferences token")?,
+                };
+                let footer = EmailFooter::new(&base_url.0, &token, subscriber.locale);
+                let outcome = email_client
                     .send_email(
                         &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
+                        &issue.title,
+                        &format!("{}{}", issue.content.text, footer.text),
+                        &format!("{}{}", issue.content.html, footer.html),
                     )
-                    .await
-                    // unlike `context`, `with_context` is lazy, which avoids the runtime cost of format! heap allocation
-                    .with_context(|| {
-                        // format! allocates memory on the heap for the output string
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
+                    .await;
+                progress.record(if outcome.is_ok() { "sent" } else { "failed" });
+                // unlike `context`, `with_context` is lazy, which avoids the runtime cost of format! heap allocation
+                outcome.with_context(|| {
+                    // format! allocates memory on the heap for the output string
+                    format!("Failed to send newsletter issue to {}", subscriber.email)
+                })?;
             }
             Err(error) => {
+                progress.record("skipped");
                 tracing::warn!(
                     error.cause_chain = ?error,
                     "Skipping a confirmed subscriber. Their stored contact details are invalid."
@@ -181,17 +111,131 @@ pub async fn publish_newsletter(
             }
         };
     }
+    queue_for_digests(
+        &pool,
+        list_id,
+        &issue.title,
+        &issue.content.text,
+        &issue.content.html,
+    )
+    .await?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        (&user).into(),
+        AuditAction::PublishNewsletter,
+        Some(&issue.title),
+    )
+    .await?;
+    // A draft is only sent once.
+    if let Some(draft_id) = draft_id {
+        sqlx::query!(
+            "DELETE FROM newsletter_drafts WHERE draft_id = $1",
+            draft_id
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to delete the published draft")?;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(serde::Serialize)]
+pub struct Draft {
+    draft_id: Uuid,
+    title: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Stores an issue for someone allowed to publish to send later, with `draft_id` as the body
+/// of `publish_newsletter`.
+#[tracing::instrument(
+    name = "Create a newsletter draft",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_draft(
+    body: web::Json<Issue>,
+    pool: web::Data<PgPool>,
+    authenticator: web::Data<Authenticator>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user = authenticator.authenticate(&request).await?;
+    user.authorize(Permission::DraftNewsletters)?;
+
+    let Issue { title, content } = body.0;
+    let draft = Draft {
+        draft_id: Uuid::new_v4(),
+        title,
+        created_at: Utc::now(),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_drafts
+            (draft_id, title, text_content, html_content, created_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        draft.draft_id,
+        draft.title,
+        content.text,
+        content.html,
+        user.user_id,
+        draft.created_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the draft")?;
+    Ok(HttpResponse::Created().json(draft))
+}
+
+#[tracing::instrument(name = "Get newsletter draft", skip(pool))]
+async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Issue, PublishError> {
+    let draft = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_drafts
+        WHERE draft_id = $1
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the draft")?
+    .ok_or(PublishError::UnknownDraft(draft_id))?;
+    Ok(Issue {
+        title: draft.title,
+        content: Content {
+            text: draft.text_content,
+            html: draft.html_content,
+        },
+    })
+}
+
 struct ConfirmedSubscriber {
+    id: Uuid,
     email: SubscriberEmail,
+    locale: Locale,
+    preferences_token: Option<String>,
 }
 
+#[tracing::instrument(name = "Find list", skip(pool))]
+async fn find_list(pool: &PgPool, list: &str) -> Result<Uuid, PublishError> {
+    let list_id = sqlx::query!("SELECT list_id FROM lists WHERE slug = $1", list)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up the list")?
+        .ok_or_else(|| PublishError::UnknownList(list.to_string()))?
+        .list_id;
+    Ok(list_id)
+}
+
+/// Confirmed members of the list who get issues as soon as they are published, and whose
+/// delivery is not paused. Weekly subscribers get theirs through `queue_for_digests`.
 #[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
+    list_id: Uuid,
+) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, PublishError> {
     // struct Row {
     //     email: String,
     // }
@@ -210,18 +254,29 @@ async fn get_confirmed_subscribers(
 
     let rows = sqlx::query!(
         r#"
-        SELECT email
+        SELECT id, email, locale, preferences_token
         FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
         WHERE status = 'confirmed'
-        "#
+          AND digest_frequency = 'immediate'
+          AND list_memberships.list_id = $1
+          AND (paused_until IS NULL OR paused_until <= now())
+        "#,
+        list_id
     )
     .fetch_all(pool)
-    .await?;
+    .await
+    .context("Failed to fetch the confirmed subscribers")?;
 
     let confirmed_subscribers = rows
         .into_iter()
         .map(|r| match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
+            Ok(email) => Ok(ConfirmedSubscriber {
+                id: r.id,
+                email,
+                locale: Locale::parse(&r.locale).unwrap_or_default(),
+                preferences_token: r.preferences_token,
+            }),
             Err(error) => Err(anyhow::anyhow!(error)),
         })
         .collect();
@@ -232,40 +287,64 @@ async fn get_confirmed_subscribers(
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts.")]
+    LockedOut { retry_after: Duration },
+    #[error("Not allowed to {0}.")]
+    Forbidden(Permission),
+    #[error("There is no list named {0:?}.")]
+    UnknownList(String),
+    #[error("There is no draft with id {0}.")]
+    UnknownDraft(Uuid),
     #[error(transparent)]
     // Only one variant can use #[from] for the same wrapped data type. In this case, anyhow::Errors propagated by "?" will be transformed to UnexpectedError.
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<AuthError> for PublishError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => Self::AuthError(e.into()),
+            AuthError::LockedOut { retry_after } => Self::LockedOut { retry_after },
+            AuthError::Forbidden(permission) => Self::Forbidden(permission),
+            AuthError::UnexpectedError(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
 impl std::fmt::Debug for PublishError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
     }
 }
 
-impl ResponseError for PublishError {
-    fn error_response(&self) -> HttpResponse {
-        match self {
-            PublishError::UnexpectedError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
-            }
+impl From<&PublishError> for ApiError {
+    fn from(e: &PublishError) -> Self {
+        match e {
+            PublishError::UnexpectedError(_) => ApiError::internal(),
             PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
+                ApiError::new(StatusCode::UNAUTHORIZED, "invalid_credentials")
+                    .with_basic_challenge("publish")
+            }
+            PublishError::LockedOut { retry_after } => too_many_requests(*retry_after),
+            PublishError::Forbidden(_) => {
+                ApiError::new(StatusCode::FORBIDDEN, "forbidden").with_detail(e.to_string())
+            }
+            PublishError::UnknownList(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "unknown_list").with_detail(e.to_string())
+            }
+            PublishError::UnknownDraft(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "unknown_draft").with_detail(e.to_string())
             }
         }
     }
+}
 
-    // If we implement `error_response`, we don't need to implement `status_code`
-    //
-    // fn status_code(&self) -> actix_http::StatusCode {
-    //     match self {
-    //         PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
-    //         PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-    //     }
-    // }
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
 }
@@ -0,0 +1,18 @@
+use crate::i18n::Locale;
+use crate::templates::{Page, Templates};
+use actix_http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// The signup form.
+pub async fn home(templates: web::Data<Templates>, request: HttpRequest) -> HttpResponse {
+    let locale = Locale::of_request(request.headers());
+    templates.response(StatusCode::OK, Page::Signup, locale, &[])
+}
+
+/// The stylesheet shared by every page, served from our origin to satisfy the default
+/// `Content-Security-Policy`.
+pub async fn stylesheet(templates: web::Data<Templates>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(templates.stylesheet().to_string())
+}
@@ -0,0 +1,686 @@
+use crate::abuse_protection::{RateLimitError, RateLimiter};
+use crate::api_error::ApiError;
+use crate::audit::{record_audit_event, Actor, AuditAction};
+use crate::configuration::PageSettings;
+use crate::digest::DigestFrequency;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::i18n::{translate, Locale};
+use crate::personal_data::{erase_subscriber, export_personal_data};
+use crate::routes::{error_chain_fmt, generate_subscription_token};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{escape_html, Page, Templates};
+use actix_http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+/// The list every new subscriber joins, and that newsletters go to unless told otherwise.
+pub const DEFAULT_LIST: &str = "newsletter";
+
+/// Longest pause a subscriber can ask for.
+const MAX_PAUSE_WEEKS: u32 = 52;
+
+/// Link to the preference center, appended to every email sent to a subscriber.
+pub struct EmailFooter {
+    pub text: String,
+    pub html: String,
+}
+
+impl EmailFooter {
+    pub fn new(base_url: &str, preferences_token: &str, locale: Locale) -> Self {
+        let link = format!("{}/preferences?token={}", base_url, preferences_token);
+        Self {
+            text: format!(
+                "\n\n--\n{}",
+                translate(locale, "email-footer-text", &[("link", &link)])
+            ),
+            html: format!(
+                "<hr /><p><a href=\"{}\">{}</a></p>",
+                link,
+                escape_html(&translate(locale, "email-footer-link", &[]))
+            ),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesQuery {
+    token: String,
+    /// What the subscriber just did, set when they are sent back to the page.
+    notice: Option<Notice>,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    Saved,
+    EmailSent,
+}
+
+impl Notice {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Notice::Saved => "saved",
+            Notice::EmailSent => "email_sent",
+        }
+    }
+
+    fn message_key(&self) -> &'static str {
+        match self {
+            Notice::Saved => "preferences-saved",
+            Notice::EmailSent => "preferences-email-sent",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct NameForm {
+    token: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FrequencyForm {
+    token: String,
+    frequency: DigestFrequency,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PauseForm {
+    token: String,
+    /// Zero resumes delivery.
+    weeks: u32,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailForm {
+    token: String,
+    email: String,
+}
+
+/// A token alone, as in links and in forms without other fields.
+#[derive(serde::Deserialize)]
+pub struct TokenParameters {
+    token: String,
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Show subscriber preferences", skip_all)]
+pub async fn get_preferences(
+    query: web::Query<PreferencesQuery>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = match find_subscriber(&pool, &query.token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let locale = subscriber.locale();
+    let lists = get_lists(&pool, subscriber.id).await?;
+    let delivery = match subscriber.paused_until.filter(|until| *until > Utc::now()) {
+        Some(until) => translate(
+            locale,
+            "preferences-paused-until",
+            &[("date", &until.format("%Y-%m-%d").to_string())],
+        ),
+        None => translate(locale, "preferences-active", &[]),
+    };
+    let notice = query
+        .notice
+        .map(|notice| translate(locale, notice.message_key(), &[]))
+        .unwrap_or_default();
+    Ok(templates.response(
+        StatusCode::OK,
+        Page::Preferences,
+        locale,
+        &[
+            ("token", &query.token),
+            ("name", &subscriber.name),
+            ("email", &subscriber.email),
+            ("delivery", &delivery),
+            ("notice", &notice),
+            ("lists", &lists_html(&lists)),
+            (
+                "frequencies",
+                &frequencies_html(locale, subscriber.digest_frequency()),
+            ),
+        ],
+    ))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Update subscriber name", skip_all)]
+pub async fn update_name(
+    form: web::Form<NameForm>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let NameForm { token, name } = form.0;
+    let subscriber = match find_subscriber(&pool, &token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let name = SubscriberName::parse(name).map_err(PreferencesError::ValidationError)?;
+    sqlx::query!(
+        "UPDATE subscriptions SET name = $1 WHERE id = $2",
+        name.as_ref(),
+        subscriber.id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber name")?;
+    Ok(back_to_preferences(&token, Notice::Saved))
+}
+
+/// Replaces the subscriber's list memberships with the lists ticked in the form, sent as
+/// repeated `list` fields.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Update subscriber lists", skip_all)]
+pub async fn update_lists(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let token = match form.iter().find(|(key, _)| key == "token") {
+        Some((_, token)) => token,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let subscriber = match find_subscriber(&pool, token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let slugs: Vec<String> = form
+        .iter()
+        .filter(|(key, _)| key == "list")
+        .map(|(_, slug)| slug.clone())
+        .collect();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        "DELETE FROM list_memberships WHERE subscriber_id = $1",
+        subscriber.id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the subscriber from their lists")?;
+    // Unknown slugs are ignored.
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id)
+        SELECT $1, list_id FROM lists WHERE slug = ANY($2)
+        "#,
+        subscriber.id,
+        &slugs[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to add the subscriber to their lists")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update list memberships")?;
+    Ok(back_to_preferences(token, Notice::Saved))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "Update subscriber digest frequency",
+    skip_all,
+    fields(frequency = %form.frequency.as_str())
+)]
+pub async fn update_frequency(
+    form: web::Form<FrequencyForm>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = match find_subscriber(&pool, &form.token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    sqlx::query!(
+        "UPDATE subscriptions SET digest_frequency = $1 WHERE id = $2",
+        form.frequency.as_str(),
+        subscriber.id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the digest frequency")?;
+    Ok(back_to_preferences(&form.token, Notice::Saved))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Pause subscriber delivery", skip_all, fields(weeks = %form.weeks))]
+pub async fn pause_delivery(
+    form: web::Form<PauseForm>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = match find_subscriber(&pool, &form.token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    if form.weeks > MAX_PAUSE_WEEKS {
+        return Err(PreferencesError::ValidationError(format!(
+            "Delivery can be paused for at most {} weeks.",
+            MAX_PAUSE_WEEKS
+        )));
+    }
+    let paused_until =
+        (form.weeks > 0).then(|| Utc::now() + chrono::Duration::weeks(form.weeks.into()));
+    sqlx::query!(
+        "UPDATE subscriptions SET paused_until = $1 WHERE id = $2",
+        paused_until,
+        subscriber.id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to pause delivery")?;
+    Ok(back_to_preferences(&form.token, Notice::Saved))
+}
+
+/// Sends a confirmation link to the new address. The address only changes once it is opened.
+#[allow(clippy::async_yields_async, clippy::too_many_arguments)]
+#[tracing::instrument(name = "Request an email change", skip_all)]
+pub async fn request_email_change(
+    form: web::Form<EmailForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let EmailForm { token, email } = form.0;
+    let subscriber = match find_subscriber(&pool, &token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let new_email = SubscriberEmail::parse(email).map_err(PreferencesError::ValidationError)?;
+    if new_email.as_ref() == subscriber.email {
+        return Ok(back_to_preferences(&token, Notice::Saved));
+    }
+    rate_limiter
+        .check_email(new_email.as_ref())
+        .await
+        .map_err(PreferencesError::RateLimitError)?;
+    let change_token = generate_subscription_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_changes (token, subscriber_id, new_email, created_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        change_token,
+        subscriber.id,
+        new_email.as_ref(),
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the email change")?;
+
+    let locale = subscriber.locale();
+    let link = format!(
+        "{}/preferences/email/confirm?token={}",
+        base_url.0, change_token
+    );
+    // Unlike other emails, no preferences footer: the new address is not verified yet, so
+    // whoever receives this must not get hold of the preferences token.
+    let html_body = format!(
+        "<a href=\"{}\">{}</a>",
+        link,
+        escape_html(&translate(locale, "email-change-link", &[]))
+    );
+    let text_body = translate(locale, "email-change-text-link", &[("link", &link)]);
+    email_client
+        .send_email(
+            &new_email,
+            &translate(locale, "email-change-subject", &[]),
+            &text_body,
+            &html_body,
+        )
+        .await
+        .context("Failed to send the email change confirmation")?;
+    Ok(back_to_preferences(&token, Notice::EmailSent))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Confirm an email change", skip_all)]
+pub async fn confirm_email_change(
+    query: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    settings: web::Data<PageSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let change = sqlx::query!(
+        r#"
+        SELECT email_changes.subscriber_id, email_changes.new_email, email_changes.created_at,
+               subscriptions.locale
+        FROM email_changes
+        JOIN subscriptions ON subscriptions.id = email_changes.subscriber_id
+        WHERE email_changes.token = $1
+        "#,
+        query.token
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the email change")?;
+    let change = match change {
+        Some(change) => change,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let locale = Locale::parse(&change.locale).unwrap_or_default();
+    let age = Utc::now() - change.created_at;
+    if age
+        .to_std()
+        .map_or(false, |age| age > settings.confirmation_link_ttl())
+    {
+        return Ok(templates.response(StatusCode::GONE, Page::ExpiredLink, locale, &[]));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let updated = sqlx::query!(
+        "UPDATE subscriptions SET email = $1 WHERE id = $2",
+        change.new_email,
+        change.subscriber_id
+    )
+    .execute(&mut transaction)
+    .await;
+    match updated {
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            return Err(PreferencesError::EmailTaken)
+        }
+        outcome => outcome.context("Failed to update the subscriber email")?,
+    };
+    // Every pending change of this subscriber is now stale.
+    sqlx::query!(
+        "DELETE FROM email_changes WHERE subscriber_id = $1",
+        change.subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the email changes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email")?;
+    Ok(templates.response(
+        StatusCode::OK,
+        Page::EmailChanged,
+        locale,
+        &[("email", &change.new_email)],
+    ))
+}
+
+/// Everything we hold about the subscriber, as a JSON download.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Export own subscriber data", skip_all)]
+pub async fn export_own_data(
+    query: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = match find_subscriber(&pool, &query.token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let data = export_personal_data(&pool, subscriber.id)
+        .await?
+        .context("The subscriber was deleted during the export")?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        SUBSCRIBER,
+        AuditAction::ExportSubscribers,
+        Some(&subscriber.id.to_string()),
+    )
+    .await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            r#"attachment; filename="subscription.json""#,
+        ))
+        .json(data))
+}
+
+/// Hard-deletes everything we hold about the subscriber.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Erase own subscriber data", skip_all)]
+pub async fn erase_own_data(
+    form: web::Form<TokenParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = match find_subscriber(&pool, &form.token).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&templates, &request)),
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    erase_subscriber(&mut transaction, subscriber.id).await?;
+    record_audit_event(
+        &mut transaction,
+        &request,
+        SUBSCRIBER,
+        AuditAction::DeleteSubscriber,
+        Some(&subscriber.id.to_string()),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber")?;
+    Ok(templates.response(StatusCode::OK, Page::Erased, subscriber.locale(), &[]))
+}
+
+/// Actor of the audit events of requests authenticated by a preferences token.
+const SUBSCRIBER: Actor<'static> = Actor {
+    user_id: None,
+    name: "subscriber",
+};
+
+pub(crate) const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Another subscriber already uses this email address.")]
+    EmailTaken,
+    #[error(transparent)]
+    RateLimitError(RateLimitError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<&PreferencesError> for ApiError {
+    fn from(e: &PreferencesError) -> Self {
+        match e {
+            PreferencesError::ValidationError(detail) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "validation_failed").with_detail(detail)
+            }
+            PreferencesError::EmailTaken => {
+                ApiError::new(StatusCode::CONFLICT, "email_taken").with_detail(e.to_string())
+            }
+            PreferencesError::RateLimitError(e) => e.into(),
+            PreferencesError::UnexpectedError(_) => ApiError::internal(),
+        }
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+    email: String,
+    locale: String,
+    paused_until: Option<DateTime<Utc>>,
+    digest_frequency: String,
+}
+
+impl Subscriber {
+    fn locale(&self) -> Locale {
+        Locale::parse(&self.locale).unwrap_or_default()
+    }
+
+    fn digest_frequency(&self) -> DigestFrequency {
+        DigestFrequency::try_from(self.digest_frequency.clone())
+            .unwrap_or(DigestFrequency::Immediate)
+    }
+}
+
+#[tracing::instrument(name = "Find subscriber by preferences token", skip_all)]
+async fn find_subscriber(
+    pool: &PgPool,
+    preferences_token: &str,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, name, email, locale, paused_until, digest_frequency
+        FROM subscriptions
+        WHERE preferences_token = $1
+        "#,
+        preferences_token
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the preferences token")?;
+    Ok(subscriber)
+}
+
+/// The subscriber's preferences token, created if they signed up before tokens existed.
+#[tracing::instrument(name = "Get preferences token", skip(executor))]
+pub async fn preferences_token<'e, E>(
+    executor: E,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET preferences_token = COALESCE(preferences_token, $2)
+        WHERE id = $1
+        RETURNING preferences_token AS "preferences_token!"
+        "#,
+        subscriber_id,
+        generate_subscription_token()
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.preferences_token)
+}
+
+struct List {
+    slug: String,
+    name: String,
+    is_member: bool,
+}
+
+async fn get_lists(pool: &PgPool, subscriber_id: Uuid) -> Result<Vec<List>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        List,
+        r#"
+        SELECT lists.slug, lists.name,
+               EXISTS (
+                   SELECT 1 FROM list_memberships
+                   WHERE list_memberships.list_id = lists.list_id
+                     AND list_memberships.subscriber_id = $1
+               ) AS "is_member!"
+        FROM lists
+        ORDER BY lists.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the lists")?;
+    Ok(lists)
+}
+
+fn lists_html(lists: &[List]) -> String {
+    lists
+        .iter()
+        .map(|list| {
+            format!(
+                "<label><input type=\"checkbox\" name=\"list\" value=\"{}\"{}> {}</label>\n",
+                escape_html(&list.slug),
+                if list.is_member { " checked" } else { "" },
+                escape_html(&list.name)
+            )
+        })
+        .collect()
+}
+
+fn frequencies_html(locale: Locale, current: DigestFrequency) -> String {
+    [DigestFrequency::Immediate, DigestFrequency::Weekly]
+        .iter()
+        .map(|frequency| {
+            format!(
+                "<option value=\"{}\"{}>{}</option>\n",
+                frequency.as_str(),
+                if *frequency == current {
+                    " selected"
+                } else {
+                    ""
+                },
+                escape_html(&translate(
+                    locale,
+                    &format!("preferences-frequency-{}", frequency.as_str()),
+                    &[]
+                ))
+            )
+        })
+        .collect()
+}
+
+/// Back to the preferences page, which shows `notice`, so that reloading it does not submit
+/// the form again.
+fn back_to_preferences(token: &str, notice: Notice) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((
+            header::LOCATION,
+            format!("/preferences?token={}&notice={}", token, notice.as_str()),
+        ))
+        .finish()
+}
+
+fn invalid_link(templates: &Templates, request: &HttpRequest) -> HttpResponse {
+    let locale = Locale::of_request(request.headers());
+    templates.response(StatusCode::UNAUTHORIZED, Page::InvalidLink, locale, &[])
+}
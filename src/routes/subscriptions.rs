@@ -1,6 +1,19 @@
-use crate::{domain::NewSubscriber, email_client::EmailClient, startup::ApplicationBaseUrl};
+use crate::{
+    abuse_protection::{ChallengeVerifier, RateLimitError, RateLimiter},
+    api_error::{prefers_html, ApiError},
+    configuration::PageSettings,
+    consent::{record_consent_event, signup_source, ConsentEventKind, Terms},
+    domain::NewSubscriber,
+    email_client::EmailClient,
+    i18n::{translate, Locale},
+    metrics::Metrics,
+    routes::{preferences_token, EmailFooter, DEFAULT_LIST},
+    startup::ApplicationBaseUrl,
+    templates::{escape_html, Page, Templates},
+    utils::client_ip,
+};
 use actix_http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
@@ -13,13 +26,23 @@ use uuid::Uuid;
 pub struct SubscriberData {
     pub email: String, // Each argument must implement the FormRequest trait.
     pub name: String,
+    /// Honeypot: hidden from humans by the signup form, so only bots fill it in.
+    pub website: Option<String>,
+    /// Response to the CAPTCHA-style challenge, if one is configured.
+    pub challenge_response: Option<String>,
+    /// Language tag for the emails and pages the subscriber gets. Negotiated from
+    /// `Accept-Language` when missing or not shipped.
+    pub locale: Option<String>,
+    /// Where the form is shown, e.g. a campaign name, kept as proof of consent. Defaults to
+    /// the page the form was posted from.
+    pub source: Option<String>,
 }
 
 // Clippy currently detects an issue between tracing::instrument and an actix_web handler: https://github.com/tokio-rs/tracing/issues/1450
-#[allow(clippy::async_yields_async)]
+#[allow(clippy::async_yields_async, clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, rate_limiter, challenge_verifier, metrics, templates, settings, request),
     // Inject the following fields into all spans of the request
     fields(
         subscriber_email = %form.email,
@@ -34,10 +57,39 @@ pub async fn subscribe(
     // Extract EmailClient from application state
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    challenge_verifier: web::Data<dyn ChallengeVerifier>,
+    metrics: web::Data<Metrics>,
+    templates: web::Data<Templates>,
+    settings: web::Data<PageSettings>,
+    request: HttpRequest,
     // SubscribeError implements the needed actix_web::ResponseError
 ) -> Result<HttpResponse, SubscribeError> {
+    let locale = form
+        .locale
+        .as_deref()
+        .and_then(Locale::parse)
+        .unwrap_or_else(|| Locale::of_request(request.headers()));
+    if matches!(form.website.as_deref(), Some(website) if !website.is_empty()) {
+        // Pretend everything went fine so that bots don't learn to skip the field.
+        tracing::warn!("Honeypot field was filled in. Dropping the submission.");
+        return Ok(check_inbox(&templates, &request, locale, &form.email));
+    }
+    let ip = client_ip(&request);
+    let is_human = challenge_verifier
+        .verify(form.challenge_response.as_deref(), &ip)
+        .await
+        .context("Failed to verify the challenge response")?;
+    if !is_human {
+        return Err(SubscribeError::ChallengeFailed);
+    }
+    let source = signup_source(form.source.as_deref(), &request);
     let new_subscriber: NewSubscriber =
         form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    rate_limiter
+        .check_email(new_subscriber.email.as_ref())
+        .await
+        .map_err(SubscribeError::RateLimitError)?;
     // let mut transaction = pool.begin().await.map_err(SubscribeError::PoolError)?;
     let mut transaction = pool
         .begin()
@@ -49,32 +101,102 @@ pub async fn subscribe(
         Since we implemented `From<anyhow::Error> for SubscribeError`, it will automatically convert to SubscribeError when propagated with '?'.
         */
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let existing = find_existing_subscriber(&mut transaction, new_subscriber.email.as_ref())
         .await
-        // .map_err(|e| SubscribeError::UnexpectedError(Box::new(e)))
-        .context("Failed to insert a new subscriber in the database")?;
-    // .map_err(SubscribeError::InsertSubscriberError)?;
+        .context("Failed to look up an existing subscriber")?;
+    let is_new = existing.is_none();
+    let (subscriber_id, preferences_token) = match existing {
+        None => {
+            let preferences_token = generate_subscription_token();
+            let subscriber_id = insert_subscriber(
+                &mut transaction,
+                &new_subscriber,
+                locale,
+                &preferences_token,
+            )
+            .await
+            // .map_err(|e| SubscribeError::UnexpectedError(Box::new(e)))
+            .context("Failed to insert a new subscriber in the database")?;
+            // .map_err(SubscribeError::InsertSubscriberError)?;
+            (subscriber_id, preferences_token)
+        }
+        // Their confirmation link expired or got lost: send a new one.
+        Some(existing) if existing.status == "pending_confirmation" => {
+            let preferences_token = preferences_token(&mut transaction, existing.id)
+                .await
+                .context("Failed to get the preferences token of a pending subscriber")?;
+            (existing.id, preferences_token)
+        }
+        // Answered like any signup, so that the form does not tell who is subscribed.
+        Some(_) => {
+            tracing::info!("The address is already confirmed. No email is sent.");
+            return Ok(check_inbox(
+                &templates,
+                &request,
+                locale,
+                new_subscriber.email.as_ref(),
+            ));
+        }
+    };
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         // .map_err(|e| SubscribeError::UnexpectedError(Box::new(e)))
         .context("Failed to store the confirmation token for a new subscriber")?;
+    record_consent_event(
+        &mut transaction,
+        &request,
+        subscriber_id,
+        ConsentEventKind::Signup,
+        Some(Terms {
+            source: &source,
+            consent_text_version: Some(&settings.consent_text_version),
+        }),
+    )
+    .await?;
     transaction
         .commit()
         .await
         // .map_err(|e| SubscribeError::UnexpectedError(Box::new(e)))
         .context("Failed to commit SQL transaction to store a new subscriber")?;
     // .map_err(SubscribeError::TransactionCommitError)?;
+    // Not counted again when a pending subscriber asks for a new confirmation link.
+    if is_new {
+        metrics.subscriptions_created.inc();
+    }
+    let email = new_subscriber.email.as_ref().to_owned();
     send_confirmation_email(
         &email_client,
         new_subscriber,
         &base_url.0,
         &subscription_token,
+        &preferences_token,
+        locale,
     )
     .await
     // .map_err(|e| SubscribeError::UnexpectedError(Box::new(e)))
     .context("Failed to send a confirmation email")?;
-    Ok(HttpResponse::Ok().finish())
+    Ok(check_inbox(&templates, &request, locale, &email))
+}
+
+/// The page asking browsers to look for the confirmation email. Other clients get the address
+/// as JSON.
+fn check_inbox(
+    templates: &Templates,
+    request: &HttpRequest,
+    locale: Locale,
+    email: &str,
+) -> HttpResponse {
+    if prefers_html(request.headers()) {
+        templates.response(
+            StatusCode::OK,
+            Page::CheckInbox,
+            locale,
+            &[("email", email)],
+        )
+    } else {
+        HttpResponse::Ok().json(serde_json::json!({ "email": email }))
+    }
 }
 
 #[tracing::instrument(
@@ -86,56 +208,105 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
+    preferences_token: &str,
+    locale: Locale,
 ) -> Result<(), reqwest::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
+    let greeting = translate(locale, "confirmation-email-greeting", &[]);
+    let footer = EmailFooter::new(base_url, preferences_token, locale);
     let html_body = format!(
-        "Welcome to our newsletter!<br />\
-    Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+        "{}<br />\
+    <a href=\"{}\">{}</a>{}",
+        escape_html(&greeting),
+        confirmation_link,
+        escape_html(&translate(locale, "confirmation-email-link", &[])),
+        footer.html
     );
     let plain_text_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
+        "{}\n{}{}",
+        greeting,
+        translate(
+            locale,
+            "confirmation-email-text-link",
+            &[("link", &confirmation_link)]
+        ),
+        footer.text
     );
     email_client
         .send_email(
             &new_subscriber.email,
-            "Welcome!",
+            &translate(locale, "confirmation-email-subject", &[]),
             &plain_text_body,
             &html_body,
         )
         .await
 }
 
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(
+    name = "Look up a subscriber with the same address",
+    skip(transaction, email)
+)]
+async fn find_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        "SELECT id, status FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE",
+        email
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database"
-    skip(transaction, new_subscriber)
+    skip(transaction, new_subscriber, preferences_token)
 )]
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
+    locale: Locale,
+    preferences_token: &str,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, $5)
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status, locale, preferences_token)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
     "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
-        "pending_confirmation"
+        "pending_confirmation",
+        locale.as_str(),
+        preferences_token
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+    INSERT INTO list_memberships (subscriber_id, list_id)
+    SELECT $1, list_id FROM lists WHERE slug = $2
+    "#,
+        subscriber_id,
+        DEFAULT_LIST
     )
     .execute(transaction)
     .await?;
     Ok(subscriber_id)
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let rng = thread_rng();
     rng.sample_iter(Alphanumeric)
         .map(char::from)
@@ -147,7 +318,7 @@ fn generate_subscription_token() -> String {
     name = "Saving subscription token in the database",
     skip(transaction, subscriber_id, subscription_token)
 )]
-async fn store_token(
+pub(crate) async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
@@ -168,6 +339,10 @@ async fn store_token(
 pub enum SubscribeError {
     #[error("{0}")] // Interpolates the inner String as the Display value
     ValidationError(String),
+    #[error("The challenge response is missing or incorrect.")]
+    ChallengeFailed,
+    #[error(transparent)]
+    RateLimitError(RateLimitError),
     // `transparent` delegates `Display` and `source` implementations to `anyhow::Error`
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
@@ -192,21 +367,31 @@ impl std::fmt::Debug for SubscribeError {
     }
 }
 
-impl ResponseError for SubscribeError {
-    // The default status code will be InternalServerError if `ResponseError::status_code` isn't implemented.
-    fn status_code(&self) -> actix_http::StatusCode {
-        match self {
-            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
-            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            // Self::StoreTokenError(_)
-            // | Self::SendEmailError(_)
-            // | Self::PoolError(_)
-            // | Self::InsertSubscriberError(_)
-            // | Self::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+impl From<&SubscribeError> for ApiError {
+    fn from(e: &SubscribeError) -> Self {
+        match e {
+            SubscribeError::ValidationError(detail) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "validation_failed").with_detail(detail)
+            }
+            SubscribeError::ChallengeFailed => {
+                ApiError::new(StatusCode::FORBIDDEN, "challenge_failed").with_detail(e.to_string())
+            }
+            SubscribeError::RateLimitError(e) => e.into(),
+            SubscribeError::UnexpectedError(_) => ApiError::internal(),
         }
     }
 }
 
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
+
 // Automatically implemented using `thiserror`
 //
 // impl std::fmt::Display for SubscribeError {
@@ -1,5 +1,15 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use crate::api_error::ApiError;
+use crate::configuration::PageSettings;
+use crate::consent::{record_consent_event, ConsentEventKind};
+use crate::i18n::Locale;
+use crate::metrics::Metrics;
+use crate::routes::error_chain_fmt;
+use crate::templates::{Page, Templates};
+use actix_http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -8,52 +18,157 @@ pub struct Parameters {
 }
 
 #[allow(clippy::async_yields_async)]
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let subscriber_id =
-        match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-            Ok(Some(id)) => id,
-            Ok(None) => return HttpResponse::Unauthorized().finish(),
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        };
-
-    if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-        return HttpResponse::InternalServerError().finish();
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, metrics, templates, settings, request)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
+    templates: web::Data<Templates>,
+    settings: web::Data<PageSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ConfirmError> {
+    let token = match get_token(&pool, &parameters.subscription_token)
+        .await
+        .context("Failed to look up the subscription token")?
+    {
+        Some(token) => token,
+        None => {
+            let locale = Locale::of_request(request.headers());
+            return Ok(templates.response(
+                StatusCode::UNAUTHORIZED,
+                Page::InvalidLink,
+                locale,
+                &[],
+            ));
+        }
+    };
+    let locale = Locale::parse(&token.subscriber_locale).unwrap_or_default();
+    let name = [("name", token.subscriber_name.as_str())];
+    // Clicking the link again is harmless, however old it is.
+    if token.subscriber_status == "confirmed" {
+        return Ok(templates.response(StatusCode::OK, Page::AlreadyConfirmed, locale, &name));
     }
+    let age = Utc::now() - token.created_at;
+    if age
+        .to_std()
+        .map_or(false, |age| age > settings.confirmation_link_ttl())
+    {
+        return Ok(templates.response(StatusCode::GONE, Page::ExpiredLink, locale, &[]));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let confirmed = confirm_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as confirmed")?;
+    // A concurrent click on the link got there first.
+    if !confirmed {
+        return Ok(templates.response(StatusCode::OK, Page::AlreadyConfirmed, locale, &name));
+    }
+    record_consent_event(
+        &mut transaction,
+        &request,
+        token.subscriber_id,
+        ConsentEventKind::Confirmation,
+        None,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
+    metrics.subscriptions_confirmed.inc();
 
-    HttpResponse::Ok().finish()
+    Ok(templates.response(StatusCode::OK, Page::Confirmed, locale, &name))
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<&ConfirmError> for ApiError {
+    fn from(e: &ConfirmError) -> Self {
+        match e {
+            ConfirmError::UnexpectedError(_) => ApiError::internal(),
+        }
+    }
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
-async fn get_subscriber_id_from_token(
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        ApiError::from(self).status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ApiError::from(self).error_response()
+    }
+}
+
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    subscriber_name: String,
+    subscriber_status: String,
+    subscriber_locale: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(pool, subscription_token))]
+async fn get_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscription_tokens.subscriber_id,
+               subscriptions.name AS subscriber_name,
+               subscriptions.status AS subscriber_status,
+               subscriptions.locale AS subscriber_locale,
+               subscription_tokens.created_at
+        FROM subscription_tokens
+        JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id
+        WHERE subscription_tokens.subscription_token = $1
+        "#,
         subscription_token
     )
-    // use fetch_xxx with SELECT statements
     .fetch_optional(pool)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool, subscriber_id))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
+/// `false` if the subscriber was no longer pending confirmation.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(transaction, subscriber_id)
+)]
+async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
-    Ok(())
+    })?
+    .rows_affected();
+    Ok(updated == 1)
 }
@@ -0,0 +1,68 @@
+/// A value that must never be logged, such as a password or an API token.
+///
+/// `Debug` and `Serialize` print a placeholder instead of the value, so a `Secret` can sit in a
+/// struct that is logged or printed as a whole. Use `expose_secret` at the point of use.
+#[derive(Clone, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T> serde::Serialize for Secret<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[REDACTED]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    fn secrets_are_redacted_in_debug_output() {
+        let secret = Secret::new("hunter2".to_string());
+
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_serialized() {
+        let secret = Secret::new("hunter2".to_string());
+
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""[REDACTED]""#);
+    }
+
+    #[test]
+    fn secrets_deserialize_from_their_plain_value() {
+        let secret: Secret<String> = serde_json::from_str(r#""hunter2""#).unwrap();
+
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::api_error::ApiError;
+use crate::configuration::SecuritySettings;
+use actix_http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_http::StatusCode;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+/// Middleware that adds the configured security headers to every response, unless the handler
+/// set them already. It relies on `SecuritySettings` being registered as application data.
+pub struct SecurityHeaders;
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = SecurityHeadersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let settings = req.app_data::<web::Data<SecuritySettings>>().cloned();
+            let mut response = service.call(req).await?;
+            if let Some(settings) = settings {
+                let headers = response.headers_mut();
+                set_default(headers, header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+                set_default(
+                    headers,
+                    header::CONTENT_SECURITY_POLICY,
+                    &settings.content_security_policy,
+                );
+                set_default(headers, header::X_FRAME_OPTIONS, &settings.frame_options);
+                set_default(headers, header::REFERRER_POLICY, &settings.referrer_policy);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Empty values are left out, as are headers that are already set.
+fn set_default(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if value.is_empty() || headers.contains_key(&name) {
+        return;
+    }
+    // Values are checked when the configuration is validated.
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// Middleware that lets the configured origins read responses to cross-origin requests.
+/// It relies on `SecuritySettings` being registered as application data.
+///
+/// Preflight requests are answered by `cors_preflight`, which has to be routed next to the
+/// protected handlers.
+pub struct Cors;
+
+impl<S, B> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CorsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let allowed_origin = req
+                .app_data::<web::Data<SecuritySettings>>()
+                .and_then(|settings| allowed_origin(req.headers(), settings));
+            let mut response = service.call(req).await?;
+            let headers = response.headers_mut();
+            // Responses differ by origin, so caches must not share them across origins.
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            if let Some(origin) = allowed_origin {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// The request's `Origin`, if it is one of the configured origins.
+fn allowed_origin(headers: &HeaderMap, settings: &SecuritySettings) -> Option<HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    let is_allowed = settings
+        .cors_allowed_origins
+        .iter()
+        .any(|allowed| origin.as_bytes() == allowed.as_bytes());
+    is_allowed.then(|| origin.clone())
+}
+
+/// Answers CORS preflight requests for `POST` handlers wrapped in `Cors`.
+pub async fn cors_preflight(
+    request: HttpRequest,
+    settings: web::Data<SecuritySettings>,
+) -> HttpResponse {
+    let requests_post = request
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .map_or(false, |method| method == "POST");
+    if allowed_origin(request.headers(), &settings).is_none() || !requests_post {
+        return ApiError::new(StatusCode::FORBIDDEN, "cors_rejected")
+            .with_detail("Cross-origin requests are not allowed from this origin.")
+            .error_response();
+    }
+    HttpResponse::NoContent()
+        .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "POST"))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type"))
+        .insert_header((
+            header::ACCESS_CONTROL_MAX_AGE,
+            settings.cors_max_age_seconds,
+        ))
+        .finish()
+}
@@ -0,0 +1,98 @@
+use crate::email_client::EmailClient;
+use actix_web::dev::Server;
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use futures_util::future::{join_all, select, Either};
+use sqlx::PgPool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Stops a running application gracefully.
+///
+/// Triggering a shutdown stops the servers from accepting connections and waits for in-flight
+/// requests. `Application::run_until_stopped` then waits for in-progress email sends and closes
+/// the connection pool before returning. Everything shares one deadline, counted from the
+/// first trigger.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownInner>,
+}
+
+struct ShutdownInner {
+    servers: Vec<Server>,
+    email_client: Arc<EmailClient>,
+    pool: PgPool,
+    timeout: Duration,
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl ShutdownHandle {
+    pub fn new(
+        servers: Vec<Server>,
+        email_client: Arc<EmailClient>,
+        pool: PgPool,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(ShutdownInner {
+                servers,
+                email_client,
+                pool,
+                timeout,
+                deadline: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Returns once the servers have stopped, i.e. once in-flight requests have completed or
+    /// been cancelled at the deadline.
+    #[tracing::instrument(name = "Shut down", skip(self))]
+    pub async fn shutdown(&self) {
+        self.deadline();
+        join_all(self.inner.servers.iter().map(|server| server.stop(true))).await;
+    }
+
+    /// Waits for in-progress email sends, up to the deadline, then closes the connection pool.
+    #[tracing::instrument(name = "Drain background work", skip(self))]
+    pub(crate) async fn drain(&self) {
+        let deadline = self.deadline();
+        let mut ticks = actix_web::rt::time::interval(Duration::from_millis(50));
+        loop {
+            let sends_in_progress = self.inner.email_client.sends_in_progress();
+            if sends_in_progress == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    sends_in_progress,
+                    "Email sends were still in progress at the shutdown deadline."
+                );
+                break;
+            }
+            ticks.tick().await;
+        }
+        self.inner.pool.close().await;
+        tracing::info!("Shut down.");
+    }
+
+    fn deadline(&self) -> Instant {
+        *self
+            .inner
+            .deadline
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Instant::now() + self.inner.timeout)
+    }
+}
+
+/// Shuts the application down when the process receives `SIGTERM` or `SIGINT`.
+pub async fn shutdown_on_signal(handle: ShutdownHandle) -> Result<(), std::io::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let received = match select(Box::pin(terminate.recv()), Box::pin(interrupt.recv())).await {
+        Either::Left(_) => "SIGTERM",
+        Either::Right(_) => "SIGINT",
+    };
+    tracing::info!("Received {}.", received);
+    handle.shutdown().await;
+    Ok(())
+}
@@ -1,54 +1,290 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::abuse_protection::{
+    get_challenge_verifier, ChallengeVerifier, IpRateLimit, RateLimiter,
+};
+use crate::api_error::{not_found, path_config, query_config, ErrorResponses};
+use crate::authentication::{Authenticator, LoginThrottle};
+use crate::configuration::{
+    get_configuration, DatabaseSettings, HealthSettings, LimitSettings, PageSettings,
+    SecuritySettings, Settings,
+};
+use crate::digest::send_digests_periodically;
 use crate::email_client::EmailClient;
+use crate::import::resume_import_jobs;
+use crate::limits::{form_config, json_config, upload_limit, ConcurrencyLimit, RequestTimeouts};
+use crate::metrics::{metrics_endpoint, Metrics, RequestMetrics};
+use crate::reload::{reload_on_sighup, Reloader, SettingsLoader};
 use crate::routes;
+use crate::security::{cors_preflight, Cors, SecurityHeaders};
+use crate::shutdown::{shutdown_on_signal, ShutdownHandle};
+use crate::templates::Templates;
+use crate::tls::{
+    reload_certificate_on_change, run_https_redirect, CertificateResolver, TlsConfig,
+};
+use crate::utils::TrustedProxies;
+use actix_http::{header, Method};
 use actix_web::dev::Server;
+use actix_web::middleware::{Condition, DefaultHeaders};
 use actix_web::{web, App, HttpServer};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
+/// How often expired rate limit buckets and failed logins are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    https_redirect_port: Option<u16>,
+    https_redirect_server: Option<Server>,
+    reloader: Arc<Reloader>,
+    shutdown: ShutdownHandle,
 }
 
 impl Application {
     /// Initializes database connections, email client, binds to TCP port and returns a Server.
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    ///
+    /// Fails if the configuration is invalid, even if it was changed after being loaded.
+    /// Reloads triggered by `SIGHUP` read the configuration with `get_configuration`.
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        Self::build_with_loader(configuration, Box::new(get_configuration)).await
+    }
+
+    /// Like `build`, but reloads read the configuration with `loader`.
+    pub async fn build_with_loader(
+        configuration: Settings,
+        loader: SettingsLoader,
+    ) -> Result<Self, anyhow::Error> {
+        configuration.validate()?;
+        let initial_configuration = configuration.clone();
         let connection_pool = get_connection_pool(&configuration.database);
         let sender_email = configuration
             .email_client
             .sender()
-            .expect("Invalid sender email address.");
+            .map_err(anyhow::Error::msg)?;
         let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
+        let shutdown_timeout = configuration.shutdown.timeout();
+        let metrics = web::Data::new(Metrics::new()?);
+        let email_client = web::Data::new(
+            EmailClient::new(
+                configuration.email_client.base_url,
+                sender_email,
+                configuration.email_client.authorization_token,
+                timeout,
+            )
+            .with_metrics(metrics.email.clone()),
         );
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
+        let rate_limiter = web::Data::new(RateLimiter::new(
+            &configuration.rate_limit,
+            connection_pool.clone(),
+        ));
+        let reloader = Arc::new(Reloader::new(
+            initial_configuration,
+            loader,
+            email_client.clone().into_inner(),
+            rate_limiter.clone().into_inner(),
+        ));
+        actix_web::rt::spawn({
+            let reloader = reloader.clone();
+            async move {
+                if let Err(e) = reload_on_sighup(reloader).await {
+                    tracing::error!(error = %e, "Failed to listen for SIGHUP.");
+                }
+            }
+        });
+        let trusted_proxies = TrustedProxies::parse(&configuration.application.trusted_proxies)
+            .map_err(anyhow::Error::msg)?;
+        let challenge_verifier = get_challenge_verifier(&configuration.challenge);
+        let login_throttle =
+            LoginThrottle::new(configuration.login_protection, connection_pool.clone());
+        actix_web::rt::spawn(prune_abuse_protection_state(
+            rate_limiter.clone().into_inner(),
+            login_throttle.clone(),
+        ));
+        let authenticator = Authenticator::new(
+            connection_pool.clone(),
+            login_throttle,
+            configuration.two_factor.cipher()?,
+            configuration.two_factor.issuer,
+        );
+        let (metrics_port, metrics_server) = if configuration.metrics.serve_on_admin_port {
+            let listener = TcpListener::bind(format!(
+                "{}:{}",
+                configuration.application.host, configuration.metrics.admin_port
+            ))?;
+            let port = listener.local_addr().unwrap().port();
+            let server = run_metrics_server(
+                listener,
+                connection_pool.clone(),
+                metrics.clone(),
+                shutdown_timeout,
+            )?;
+            (Some(port), Some(server))
+        } else {
+            (None, None)
+        };
+        let tls_settings = &configuration.application.tls;
+        let tls = if tls_settings.enabled {
+            let resolver = Arc::new(CertificateResolver::new(tls_settings)?);
+            actix_web::rt::spawn(reload_certificate_on_change(
+                resolver.clone(),
+                tls_settings.watch_interval(),
+            ));
+            Some(TlsConfig::new(tls_settings, resolver))
+        } else {
+            None
+        };
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let (https_redirect_port, https_redirect_server) =
+            if tls_settings.enabled && tls_settings.redirect_http {
+                let listener = TcpListener::bind(format!(
+                    "{}:{}",
+                    configuration.application.host, tls_settings.redirect_port
+                ))?;
+                let redirect_port = listener.local_addr().unwrap().port();
+                let server = run_https_redirect(
+                    listener,
+                    &configuration.application.base_url,
+                    port,
+                    shutdown_timeout,
+                )?;
+                (Some(redirect_port), Some(server))
+            } else {
+                (None, None)
+            };
+        actix_web::rt::spawn({
+            let pool = connection_pool.clone();
+            let email_client = email_client.clone().into_inner();
+            let base_url = configuration.application.base_url.clone();
+            async move {
+                if let Err(e) = resume_import_jobs(pool, email_client, base_url).await {
+                    tracing::error!(error = ?e, "Failed to resume import jobs.");
+                }
+            }
+        });
+        actix_web::rt::spawn(send_digests_periodically(
+            connection_pool.clone(),
+            email_client.clone().into_inner(),
+            configuration.application.base_url.clone(),
+        ));
         let server = run(
             listener,
-            connection_pool,
-            email_client,
+            connection_pool.clone(),
+            email_client.clone(),
             configuration.application.base_url,
+            trusted_proxies,
+            rate_limiter,
+            challenge_verifier,
+            authenticator,
+            metrics,
+            metrics_server.is_none(),
+            configuration.health,
+            configuration.security,
+            configuration.limits,
+            configuration.pages,
+            shutdown_timeout,
+            tls,
         )?;
-        Ok(Self { port, server })
+        let shutdown = ShutdownHandle::new(
+            std::iter::once(server.clone())
+                .chain(metrics_server.clone())
+                .chain(https_redirect_server.clone())
+                .collect(),
+            email_client.into_inner(),
+            connection_pool,
+            shutdown_timeout,
+        );
+        actix_web::rt::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                if let Err(e) = shutdown_on_signal(shutdown).await {
+                    tracing::error!(error = %e, "Failed to listen for SIGTERM.");
+                }
+            }
+        });
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+            https_redirect_port,
+            https_redirect_server,
+            reloader,
+            shutdown,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The port serving `/metrics`, if it is not served next to the API.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// The port redirecting plain HTTP to HTTPS, if there is one.
+    pub fn https_redirect_port(&self) -> Option<u16> {
+        self.https_redirect_port
+    }
+
+    pub fn reloader(&self) -> Arc<Reloader> {
+        self.reloader.clone()
+    }
+
+    /// Lets tests stop the application as `SIGTERM` would.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves requests until a shutdown, then drains in-flight work before returning.
+    ///
+    /// Buffered spans are not exported here, call `shutdown_tracer` afterwards.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let servers = std::iter::once(self.server)
+            .chain(self.metrics_server)
+            .chain(self.https_redirect_server);
+        let outcome = futures_util::future::try_join_all(servers)
+            .await
+            .map(|_| ());
+        self.shutdown.drain().await;
+        outcome
+    }
+}
+
+/// Deletes rate limit buckets and failed logins once they have expired, every
+/// `PRUNE_INTERVAL`. Nothing else would, and each client IP and address leaves one behind.
+async fn prune_abuse_protection_state(
+    rate_limiter: Arc<RateLimiter>,
+    login_throttle: LoginThrottle,
+) {
+    let mut ticks = actix_web::rt::time::interval(PRUNE_INTERVAL);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = rate_limiter.prune().await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to prune rate limit buckets."
+            );
+        }
+        if let Err(e) = login_throttle.prune().await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to prune failed logins."
+            );
+        }
     }
 }
 
@@ -63,11 +299,24 @@ pub struct ApplicationBaseUrl(pub String);
 // Return a Result to the Server, which the caller can .await.
 // If we choose to await here, it would be extremely difficult to run this
 // function in tokio::spawn (not sure why).
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: web::Data<EmailClient>,
     base_url: String,
+    trusted_proxies: TrustedProxies,
+    rate_limiter: web::Data<RateLimiter>,
+    challenge_verifier: Arc<dyn ChallengeVerifier>,
+    authenticator: Authenticator,
+    metrics: web::Data<Metrics>,
+    serve_metrics: bool,
+    health: HealthSettings,
+    security: SecuritySettings,
+    limits: LimitSettings,
+    pages: PageSettings,
+    shutdown_timeout: Duration,
+    tls: Option<TlsConfig>,
 ) -> Result<Server, std::io::Error> {
     // App data (e.g. connection) needs to be cloneable. But PgConnection does not have .clone().
     // Instead, wrap the connection in a smart pointer - Data uses Atomic Reference Counter (Arc) internally.
@@ -75,17 +324,37 @@ pub fn run(
     // Arc increments the number of active references for every clone of it.
     let pool = web::Data::new(db_pool);
 
-    // Although EmailClient is cloneable, we want to avoid creating multiple base_url and sender copies.
-    // Hence we wrap EmailClient with web::Data, which uses an Arc under-the-hood.
-    let email_client = web::Data::new(email_client);
-
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let trusted_proxies = web::Data::new(trusted_proxies);
+
+    let challenge_verifier = web::Data::from(challenge_verifier);
+    let authenticator = web::Data::new(authenticator);
+    let health = web::Data::new(health);
+    let security = web::Data::new(security);
+    let templates = web::Data::new(Templates::load(&pages)?);
+    let pages = web::Data::new(pages);
+    let request_timeouts = RequestTimeouts::new(&limits);
+    let concurrency_limit = ConcurrencyLimit::new(&limits);
+    let hsts = tls.as_ref().and_then(|tls| tls.hsts.clone());
 
     // HttpServer::new takes a closure instead of an App because it needs to spin up multiple
     // worker processes and provide a different App to each of them.
     // Use `move` to capture `connection` from the surrounding environment. Most useful when passing closure to a new thread so that the new thread owns the data.
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
+            .wrap(Condition::new(
+                hsts.is_some(),
+                DefaultHeaders::new().header(
+                    header::STRICT_TRANSPORT_SECURITY,
+                    hsts.clone().unwrap_or_default(),
+                ),
+            ))
+            .wrap(SecurityHeaders)
+            .wrap(request_timeouts.clone())
+            .wrap(concurrency_limit.clone())
+            .wrap(RequestMetrics)
+            // Inside TracingLogger, so that error responses can carry the request id.
+            .wrap(ErrorResponses)
             /*
             tracing_actix_web::TracingLogger is a drop-in replacement for actix_web::middleware::Logger.
             It automatically attaches a unique request_id for each actix-web request.
@@ -94,14 +363,154 @@ pub fn run(
             // Routes combines Handlers with a set of Guards
             // "/" implements the Guard trait and passes the request on only if it fulfils.
             // web::get() is short for Route::new().guard(guard::Get()) and passes only GET requests through to the handler
+            .route("/", web::get().to(routes::home))
+            .route("/static/style.css", web::get().to(routes::stylesheet))
             .route("/health_check", web::get().to(routes::health_check))
-            .route("/newsletters", web::post().to(routes::publish_newsletter))
+            .route("/health/live", web::get().to(routes::liveness))
+            .route("/health/ready", web::get().to(routes::readiness))
+            .service(
+                web::resource("/newsletters")
+                    .app_data(json_config(&limits.newsletters))
+                    .route(web::post().to(routes::publish_newsletter)),
+            )
+            .service(
+                web::resource("/newsletters/drafts")
+                    .app_data(json_config(&limits.newsletters))
+                    .route(web::post().to(routes::create_draft)),
+            )
+            .service(
+                web::scope("/admin")
+                    .route(
+                        "/reports/subscriptions",
+                        web::get().to(routes::subscriptions_report),
+                    )
+                    .route("/subscribers", web::get().to(routes::export_subscribers))
+                    .route(
+                        "/subscribers/{email}",
+                        web::delete().to(routes::erase_subscriber_data),
+                    )
+                    .route(
+                        "/subscribers/{email}/data",
+                        web::get().to(routes::export_subscriber_data),
+                    )
+                    .service(
+                        web::resource("/imports")
+                            .app_data(upload_limit(&limits.imports))
+                            .route(web::post().to(routes::start_import)),
+                    )
+                    .route("/imports/{job_id}", web::get().to(routes::get_import))
+                    .route(
+                        "/imports/{job_id}/errors",
+                        web::get().to(routes::get_import_errors),
+                    )
+                    .route("/users", web::get().to(routes::list_users))
+                    .route("/users", web::post().to(routes::create_user))
+                    .route(
+                        "/users/{user_id}/role",
+                        web::put().to(routes::change_user_role),
+                    )
+                    .route("/users/{user_id}", web::delete().to(routes::delete_user))
+                    .route("/tokens", web::get().to(routes::get_api_tokens))
+                    .route("/tokens", web::post().to(routes::create_api_token))
+                    .route(
+                        "/tokens/{token_id}",
+                        web::delete().to(routes::delete_api_token),
+                    )
+                    .route("/two-factor", web::post().to(routes::enrol_two_factor))
+                    .route(
+                        "/two-factor/confirm",
+                        web::post().to(routes::confirm_two_factor),
+                    )
+                    .route("/two-factor", web::delete().to(routes::disable_two_factor))
+                    .route("/audit-events", web::get().to(routes::get_audit_events))
+                    .route(
+                        "/telemetry/log-filter",
+                        web::get().to(routes::get_log_filter),
+                    )
+                    .route(
+                        "/telemetry/log-filter",
+                        web::put().to(routes::set_log_filter),
+                    ),
+            )
             .route("/subscriptions/confirm", web::get().to(routes::confirm))
-            .route("/subscriptions", web::post().to(routes::subscribe))
+            .route("/preferences", web::get().to(routes::get_preferences))
+            .route("/preferences/name", web::post().to(routes::update_name))
+            .route("/preferences/lists", web::post().to(routes::update_lists))
+            .route(
+                "/preferences/frequency",
+                web::post().to(routes::update_frequency),
+            )
+            .route("/preferences/pause", web::post().to(routes::pause_delivery))
+            .route("/preferences/data", web::get().to(routes::export_own_data))
+            .route("/preferences/erase", web::post().to(routes::erase_own_data))
+            .service(
+                web::resource("/preferences/email")
+                    .wrap(IpRateLimit)
+                    .route(web::post().to(routes::request_email_change)),
+            )
+            .route(
+                "/preferences/email/confirm",
+                web::get().to(routes::confirm_email_change),
+            )
+            .service(
+                web::resource("/subscriptions")
+                    .app_data(form_config(&limits.subscriptions))
+                    .wrap(IpRateLimit)
+                    .wrap(Cors)
+                    .route(web::post().to(routes::subscribe))
+                    .route(web::method(Method::OPTIONS).to(cors_preflight)),
+            )
             .app_data(pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(challenge_verifier.clone())
+            .app_data(authenticator.clone())
+            .app_data(metrics.clone())
+            .app_data(health.clone())
+            .app_data(security.clone())
+            .app_data(templates.clone())
+            .app_data(pages.clone())
+            .app_data(json_config(&limits.default))
+            .app_data(form_config(&limits.default))
+            .app_data(query_config())
+            .app_data(path_config())
+            .default_service(web::route().to(not_found));
+        if serve_metrics {
+            app.route("/metrics", web::get().to(metrics_endpoint))
+        } else {
+            app
+        }
+    })
+    // Shutdowns are triggered by `shutdown_on_signal` instead, so that background work is drained too.
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs());
+    let server = match tls {
+        Some(tls) => server.listen_rustls(listener, tls.server_config)?,
+        None => server.listen(listener)?,
+    }
+    .run();
+    Ok(server)
+}
+
+/// Serves `/metrics` alone, on a port that is not exposed publicly.
+pub fn run_metrics_server(
+    listener: TcpListener,
+    db_pool: PgPool,
+    metrics: web::Data<Metrics>,
+    shutdown_timeout: Duration,
+) -> Result<Server, std::io::Error> {
+    let pool = web::Data::new(db_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .app_data(pool.clone())
+            .app_data(metrics.clone())
     })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
     Ok(server)
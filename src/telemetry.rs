@@ -1,13 +1,96 @@
+use crate::configuration::OtlpSettings;
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use opentelemetry::propagation::Injector;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self as sdktrace, Tracer, TracerProvider};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{EnvFilter, Registry};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+static LOG_FILTER: OnceCell<LogFilterHandle> = OnceCell::new();
+
+/// Changes the log filter of a running subscriber, e.g. to temporarily enable debug logs.
+pub struct LogFilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilterHandle {
+    /// Replaces the filter with `directives`, in `RUST_LOG` syntax (e.g. `info,sqlx=warn`).
+    pub fn set(&self, directives: &str) -> Result<(), anyhow::Error> {
+        let filter = EnvFilter::try_new(directives).context("Invalid log filter directives.")?;
+        self.0
+            .reload(filter)
+            .context("Failed to reload the log filter.")
+    }
+
+    pub fn current(&self) -> Result<String, anyhow::Error> {
+        self.0
+            .with_current(|filter| filter.to_string())
+            .context("Failed to read the log filter.")
+    }
+}
+
+/// Builds the provider of OpenTelemetry tracers.
+///
+/// Spans always get a trace context, so that `traceparent` headers are propagated, but they
+/// are only exported to a collector, with OTLP/HTTP, if `settings.enabled`.
+pub fn get_tracer_provider(
+    service_name: String,
+    settings: &OtlpSettings,
+) -> Result<TracerProvider, anyhow::Error> {
+    let resource =
+        opentelemetry::sdk::Resource::new(vec![KeyValue::new("service.name", service_name)]);
+    let mut builder =
+        TracerProvider::builder().with_config(sdktrace::config().with_resource(resource));
+    if settings.enabled {
+        // The HTTP transport leaves timeouts to the client.
+        let http_client = reqwest::Client::builder()
+            .timeout(settings.timeout())
+            .build()
+            .context("Failed to build the OTLP HTTP client.")?;
+        let exporter = SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(format!(
+                    "{}/v1/traces",
+                    settings.endpoint.trim_end_matches('/')
+                ))
+                .with_http_client(http_client),
+        )
+        .build_span_exporter()
+        .context("Failed to build the OTLP exporter.")?;
+        // Exports from a dedicated thread, since actix-web workers run single-threaded runtimes.
+        builder = builder.with_batch_exporter(exporter, opentelemetry::runtime::TokioCurrentThread);
+    }
+    Ok(builder.build())
+}
+
+/// Registers the tracer provider globally, so that `shutdown_tracer` can flush it, and returns
+/// a tracer for `get_subscriber`.
+pub fn init_tracer(provider: TracerProvider) -> Tracer {
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"), Some(env!("CARGO_PKG_VERSION")));
+    global::set_tracer_provider(provider);
+    tracer
+}
+
+/// Exports the spans that are still buffered. Call before exiting.
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
 
 /// Compose multiple layers into a `tracing`'s subscriber.
 ///
+/// The filter can be changed at runtime through the returned `LogFilterHandle`.
+/// Spans are also turned into OpenTelemetry spans by `tracer`.
+///
 /// # Implementation Notes
 ///
 /// Return `impl Subscriber` as return type to avoid having to spell out the actual
@@ -17,18 +100,100 @@ pub fn get_subscriber(
     name: String,
     env_filter: String,
     sink: impl MakeWriter + Send + Sync + 'static,
-) -> impl Subscriber + Send + Sync {
+    tracer: Tracer,
+) -> (impl Subscriber + Send + Sync, LogFilterHandle) {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
         .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(formatting_layer);
+    (subscriber, LogFilterHandle(handle))
 }
 
 /// Register a subscriber as global default to process span data.
-pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+///
+/// Its `LogFilterHandle` is then available through `log_filter`.
+/// Trace contexts are read from and written to W3C `traceparent` headers.
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync, log_filter: LogFilterHandle) {
     LogTracer::init().expect("Failed to set logger");
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set subscriber");
+    LOG_FILTER
+        .set(log_filter)
+        .unwrap_or_else(|_| panic!("The subscriber was already initialised."));
+}
+
+/// The filter of the global subscriber, if `init_subscriber` was called.
+pub fn log_filter() -> Option<&'static LogFilterHandle> {
+    LOG_FILTER.get()
+}
+
+/// Adds the trace context of the current span to the headers of an outgoing request.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::OtlpSettings;
+    use crate::telemetry::get_tracer_provider;
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn spans_are_exported_to_the_collector_over_otlp() {
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .and(header("Content-Type", "application/x-protobuf"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&collector)
+            .await;
+        let settings = OtlpSettings {
+            enabled: true,
+            endpoint: collector.uri(),
+            timeout_milliseconds: 1000,
+        };
+        let provider = get_tracer_provider("newsletter".into(), &settings).unwrap();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test", None)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Publish a newsletter").in_scope(|| {});
+        });
+        // Flushing blocks until the export completes, which needs this thread to run the collector.
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let requests = collector.received_requests().await.unwrap();
+        // Protobuf keeps strings as they are.
+        let body = String::from_utf8_lossy(&requests[0].body);
+        assert!(body.contains("newsletter"));
+        assert!(body.contains("Publish a newsletter"));
+    }
 }
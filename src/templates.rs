@@ -0,0 +1,227 @@
+use crate::configuration::PageSettings;
+use crate::i18n::{translate, Locale};
+use actix_http::{header, StatusCode};
+use actix_web::HttpResponse;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The pages shown to subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Page {
+    Signup,
+    CheckInbox,
+    Confirmed,
+    AlreadyConfirmed,
+    ExpiredLink,
+    InvalidLink,
+    Preferences,
+    EmailChanged,
+    Erased,
+}
+
+impl Page {
+    const ALL: [Page; 9] = [
+        Page::Signup,
+        Page::CheckInbox,
+        Page::Confirmed,
+        Page::AlreadyConfirmed,
+        Page::ExpiredLink,
+        Page::InvalidLink,
+        Page::Preferences,
+        Page::EmailChanged,
+        Page::Erased,
+    ];
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            Page::Signup => "signup.html",
+            Page::CheckInbox => "check_inbox.html",
+            Page::Confirmed => "confirmed.html",
+            Page::AlreadyConfirmed => "already_confirmed.html",
+            Page::ExpiredLink => "expired_link.html",
+            Page::InvalidLink => "invalid_link.html",
+            Page::Preferences => "preferences.html",
+            Page::EmailChanged => "email_changed.html",
+            Page::Erased => "erased.html",
+        }
+    }
+
+    fn built_in(&self) -> &'static str {
+        match self {
+            Page::Signup => include_str!("../templates/signup.html"),
+            Page::CheckInbox => include_str!("../templates/check_inbox.html"),
+            Page::Confirmed => include_str!("../templates/confirmed.html"),
+            Page::AlreadyConfirmed => include_str!("../templates/already_confirmed.html"),
+            Page::ExpiredLink => include_str!("../templates/expired_link.html"),
+            Page::InvalidLink => include_str!("../templates/invalid_link.html"),
+            Page::Preferences => include_str!("../templates/preferences.html"),
+            Page::EmailChanged => include_str!("../templates/email_changed.html"),
+            Page::Erased => include_str!("../templates/erased.html"),
+        }
+    }
+}
+
+const STYLESHEET: &str = "style.css";
+
+/// Templates for the subscriber pages, read once at startup.
+///
+/// Each file found in `PageSettings::template_directory` replaces the built-in template of
+/// the same name, so a theme only needs to provide the files it changes. `{{ name }}`
+/// placeholders are replaced with HTML-escaped values: `lang` is the page's locale, other names
+/// are values passed by the handler, or else messages from the locale's catalog.
+/// `{{{ name }}}` placeholders are replaced with handler values as is, for markup that the
+/// handler built and escaped itself.
+pub struct Templates {
+    pages: HashMap<Page, String>,
+    stylesheet: String,
+}
+
+impl Templates {
+    pub fn load(settings: &PageSettings) -> Result<Self, std::io::Error> {
+        let directory = Path::new(&settings.template_directory);
+        let read = |file_name: &str, built_in: &str| -> Result<String, std::io::Error> {
+            let path = directory.join(file_name);
+            if settings.template_directory.is_empty() || !path.exists() {
+                return Ok(built_in.to_string());
+            }
+            tracing::info!(path = %path.display(), "Using a custom template.");
+            std::fs::read_to_string(path)
+        };
+        let mut pages = HashMap::new();
+        for page in Page::ALL {
+            pages.insert(page, read(page.file_name(), page.built_in())?);
+        }
+        Ok(Self {
+            pages,
+            stylesheet: read(STYLESHEET, include_str!("../templates/style.css"))?,
+        })
+    }
+
+    pub fn render(&self, page: Page, locale: Locale, values: &[(&str, &str)]) -> String {
+        render(&self.pages[&page], locale, values)
+    }
+
+    /// `page` as an HTML response with the given status.
+    pub fn response(
+        &self,
+        status: StatusCode,
+        page: Page,
+        locale: Locale,
+        values: &[(&str, &str)],
+    ) -> HttpResponse {
+        HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .insert_header((header::CONTENT_LANGUAGE, locale.as_str()))
+            .body(self.render(page, locale, values))
+    }
+
+    pub fn stylesheet(&self) -> &str {
+        &self.stylesheet
+    }
+}
+
+/// Replaces `{{ name }}` and `{{{ name }}}` placeholders.
+fn render(template: &str, locale: Locale, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        if rest[start..].starts_with("{{{") {
+            let end = match rest[start..].find("}}}") {
+                Some(end) => start + end,
+                None => break,
+            };
+            output.push_str(&rest[..start]);
+            let name = rest[start + 3..end].trim();
+            if let Some((_, value)) = values.iter().find(|(key, _)| *key == name) {
+                output.push_str(value);
+            }
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        output.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        let value = if name == "lang" {
+            locale.as_str().to_string()
+        } else if let Some((_, value)) = values.iter().find(|(key, _)| *key == name) {
+            value.to_string()
+        } else {
+            translate(locale, name, values)
+        };
+        output.push_str(&escape_html(&value));
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Page};
+    use crate::i18n::{lookup, Locale};
+
+    #[test]
+    fn placeholders_are_replaced_with_escaped_values() {
+        let html = render(
+            "<p lang=\"{{ lang }}\">Hi {{ name }}, {{ signup-submit }}!</p>",
+            Locale::parse("fr").unwrap(),
+            &[("name", "<script>alert(1)</script>")],
+        );
+
+        assert_eq!(
+            html,
+            "<p lang=\"fr\">Hi &lt;script&gt;alert(1)&lt;/script&gt;, S'abonner!</p>"
+        );
+    }
+
+    #[test]
+    fn raw_placeholders_are_replaced_with_values_as_is() {
+        let html = render(
+            "<ul>{{{ items }}}{{{ missing }}}</ul>",
+            Locale::DEFAULT,
+            &[("items", "<li>a</li>")],
+        );
+
+        assert_eq!(html, "<ul><li>a</li></ul>");
+    }
+
+    #[test]
+    fn unterminated_placeholders_are_left_as_is() {
+        assert_eq!(render("a {{ b", Locale::DEFAULT, &[("b", "c")]), "a {{ b");
+        assert_eq!(
+            render("a {{{ b }}", Locale::DEFAULT, &[("b", "c")]),
+            "a {{{ b }}"
+        );
+    }
+
+    #[test]
+    fn built_in_templates_only_use_known_messages() {
+        // Passed by the handlers rather than found in the catalogs.
+        let values = [
+            "lang", "token", "name", "email", "notice", "delivery", "lists",
+        ];
+        for page in Page::ALL {
+            let template = page.built_in();
+            for (start, _) in template.match_indices("{{") {
+                let end = template[start..].find("}}").unwrap() + start;
+                let name = template[start + 2..end].trim_start_matches('{').trim();
+                assert!(
+                    values.contains(&name) || lookup(Locale::DEFAULT, name).is_some(),
+                    "{} uses the unknown message {}.",
+                    page.file_name(),
+                    name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::configuration::TlsSettings;
+use actix_http::header;
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{anyhow, Context};
+use reqwest::Url;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing_actix_web::TracingLogger;
+
+/// What `startup::run` needs to serve HTTPS.
+pub struct TlsConfig {
+    pub server_config: ServerConfig,
+    /// Value of the `Strict-Transport-Security` header, if it is sent.
+    pub hsts: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new(settings: &TlsSettings, resolver: Arc<CertificateResolver>) -> Self {
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.cert_resolver = resolver;
+        let hsts = (settings.hsts_max_age_seconds > 0)
+            .then(|| format!("max-age={}", settings.hsts_max_age_seconds));
+        Self {
+            server_config,
+            hsts,
+        }
+    }
+}
+
+/// Serves the certificate chain and private key read from the configured files.
+///
+/// They are read again by `reload`, so that renewed certificates are picked up without a
+/// restart. Handshakes in progress keep the certificate they started with.
+pub struct CertificateResolver {
+    certificate_path: PathBuf,
+    private_key_path: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl CertificateResolver {
+    pub fn new(settings: &TlsSettings) -> Result<Self, anyhow::Error> {
+        let certificate_path = PathBuf::from(&settings.certificate_path);
+        let private_key_path = PathBuf::from(&settings.private_key_path);
+        let current = load_certified_key(&certificate_path, &private_key_path)?;
+        Ok(Self {
+            certificate_path,
+            private_key_path,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Reads the files again. On failure, the previous certificate is still served.
+    #[tracing::instrument(name = "Reload TLS certificate", skip(self))]
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let new = load_certified_key(&self.certificate_path, &self.private_key_path)?;
+        *self.current.write().unwrap() = new;
+        tracing::info!("Reloaded the TLS certificate.");
+        Ok(())
+    }
+
+    /// The certificate chain served to new connections.
+    pub fn certificate_chain(&self) -> Vec<rustls::Certificate> {
+        self.current.read().unwrap().cert.clone()
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [&self.certificate_path, &self.private_key_path]
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(
+    certificate_path: &Path,
+    private_key_path: &Path,
+) -> Result<CertifiedKey, anyhow::Error> {
+    let chain = certs(&mut open(certificate_path)?)
+        .map_err(|_| anyhow!("{} is not a valid PEM file.", certificate_path.display()))?;
+    if chain.is_empty() {
+        return Err(anyhow!(
+            "There is no certificate in {}.",
+            certificate_path.display()
+        ));
+    }
+    let private_key = read_private_key(private_key_path)?;
+    let signing_key = any_supported_type(&private_key).map_err(|_| {
+        anyhow!(
+            "The private key in {} is not supported.",
+            private_key_path.display()
+        )
+    })?;
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey, anyhow::Error> {
+    let invalid = || anyhow!("{} is not a valid PEM file.", path.display());
+    let mut keys = pkcs8_private_keys(&mut open(path)?).map_err(|_| invalid())?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(path)?).map_err(|_| invalid())?;
+    }
+    keys.into_iter()
+        .next()
+        .with_context(|| format!("There is no private key in {}.", path.display()))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// Reloads the certificate whenever its file or the private key's is modified, checking every
+/// `interval`.
+pub async fn reload_certificate_on_change(resolver: Arc<CertificateResolver>, interval: Duration) {
+    let mut last_seen = resolver.modified();
+    let mut ticks = actix_web::rt::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let current = resolver.modified();
+        if current != last_seen {
+            last_seen = current;
+            if let Err(e) = resolver.reload() {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to reload the TLS certificate. Keeping the current one."
+                );
+            }
+        }
+    }
+}
+
+// Where plain HTTP requests are redirected to, e.g. `https://example.com:8443`.
+struct HttpsOrigin(String);
+
+/// Answers plain HTTP requests with a redirect to the same path over HTTPS, on the host of
+/// `base_url` and on `https_port`.
+///
+/// The request's own `Host` is ignored: anyone can set it, and send the client elsewhere.
+pub fn run_https_redirect(
+    listener: TcpListener,
+    base_url: &str,
+    https_port: u16,
+    shutdown_timeout: Duration,
+) -> Result<Server, anyhow::Error> {
+    let host = Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .with_context(|| format!("{} has no host to redirect to.", base_url))?;
+    let origin = web::Data::new(HttpsOrigin(https_origin(&host, https_port)));
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .default_service(web::route().to(redirect_to_https))
+            .app_data(origin.clone())
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+async fn redirect_to_https(request: HttpRequest, origin: web::Data<HttpsOrigin>) -> HttpResponse {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    // 308 rather than 301, so that clients repeat POSTs instead of turning them into GETs.
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("{}{}", origin.0, path_and_query)))
+        .finish()
+}
+
+fn https_origin(host: &str, port: u16) -> String {
+    if port == 443 {
+        format!("https://{}", host)
+    } else {
+        format!("https://{}:{}", host, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{https_origin, CertificateResolver};
+    use crate::configuration::TlsSettings;
+    use std::path::{Path, PathBuf};
+
+    fn write_self_signed_certificate(directory: &Path) {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        std::fs::write(
+            directory.join("cert.pem"),
+            certificate.serialize_pem().unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("key.pem"),
+            certificate.serialize_private_key_pem(),
+        )
+        .unwrap();
+    }
+
+    fn settings(directory: &Path) -> TlsSettings {
+        TlsSettings {
+            enabled: true,
+            certificate_path: directory.join("cert.pem").display().to_string(),
+            private_key_path: directory.join("key.pem").display().to_string(),
+            watch_interval_seconds: 1,
+            redirect_http: false,
+            redirect_port: 0,
+            hsts_max_age_seconds: 0,
+        }
+    }
+
+    fn temp_directory() -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn reloading_serves_the_new_certificate() {
+        let directory = temp_directory();
+        write_self_signed_certificate(&directory);
+        let resolver = CertificateResolver::new(&settings(&directory)).unwrap();
+        let old = resolver.certificate_chain();
+
+        write_self_signed_certificate(&directory);
+        resolver.reload().unwrap();
+
+        assert_ne!(old, resolver.certificate_chain());
+    }
+
+    #[test]
+    fn a_failed_reload_keeps_the_current_certificate() {
+        let directory = temp_directory();
+        write_self_signed_certificate(&directory);
+        let resolver = CertificateResolver::new(&settings(&directory)).unwrap();
+        let old = resolver.certificate_chain();
+
+        std::fs::write(directory.join("key.pem"), "not a key").unwrap();
+
+        assert!(resolver.reload().is_err());
+        assert_eq!(old, resolver.certificate_chain());
+    }
+
+    #[test]
+    fn the_https_port_is_left_out_when_it_is_the_default() {
+        assert_eq!(https_origin("example.com", 443), "https://example.com");
+        assert_eq!(https_origin("[::1]", 8443), "https://[::1]:8443");
+    }
+}
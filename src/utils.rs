@@ -0,0 +1,174 @@
+use crate::api_error::ApiError;
+use actix_http::StatusCode;
+use actix_web::{web, HttpRequest};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// IP address of the client that sent the request.
+///
+/// This is the address of the peer, unless the peer is one of the `TrustedProxies` registered
+/// as app data. Then it is the right-most address in `X-Forwarded-For` that is not a trusted
+/// proxy itself: each proxy appends the address it received the request from, while anything
+/// further left was sent by the client and could be made up.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let peer = match request.peer_addr() {
+        Some(peer) => peer.ip(),
+        None => return "unknown".into(),
+    };
+    let trusted_proxies = match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) if trusted_proxies.contains(peer) => trusted_proxies,
+        _ => return peer.to_string(),
+    };
+    let forwarded_for: Vec<&str> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    let mut client = peer;
+    for hop in forwarded_for.into_iter().rev() {
+        match parse_hop(hop) {
+            Some(ip) if trusted_proxies.contains(ip) => client = ip,
+            Some(ip) => return ip.to_string(),
+            // Written by a trusted proxy, e.g. `unknown`.
+            None => return hop.to_string(),
+        }
+    }
+    client.to_string()
+}
+
+// Proxies may include the port, e.g. `203.0.113.7:41234` or `[2001:db8::1]:41234`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|address| address.ip()))
+        .or_else(|_| hop.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// Proxies, such as a load balancer, allowed to tell the address of the client, see `client_ip`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNetwork>);
+
+impl TrustedProxies {
+    /// Parses IP addresses and CIDR ranges, e.g. `10.0.0.1` or `10.0.0.0/8`.
+    pub fn parse(proxies: &[String]) -> Result<Self, String> {
+        proxies
+            .iter()
+            .map(|proxy| proxy.parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u32,
+}
+
+impl IpNetwork {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, width) = match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        self.prefix_length == 0
+            || network >> (width - self.prefix_length) == ip >> (width - self.prefix_length)
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not an IP address or CIDR range.", value);
+        let (address, prefix_length) = match value.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let width = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse()
+                .ok()
+                .filter(|prefix_length| *prefix_length <= width)
+                .ok_or_else(invalid)?,
+            None => width,
+        };
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+/// `429 Too Many Requests` telling the client when it may try again.
+pub fn too_many_requests(retry_after: Duration) -> ApiError {
+    ApiError::new(StatusCode::TOO_MANY_REQUESTS, "too_many_requests").with_retry_after(retry_after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_ip, TrustedProxies};
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> TestRequest {
+        let request = TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .app_data(web::Data::new(
+                TrustedProxies::parse(&["10.0.0.0/8".into(), "2001:db8::1".into()]).unwrap(),
+            ));
+        match forwarded_for {
+            Some(forwarded_for) => request.insert_header(("X-Forwarded-For", forwarded_for)),
+            None => request,
+        }
+    }
+
+    #[test]
+    fn forwarding_headers_from_untrusted_peers_are_ignored() {
+        let request = request("203.0.113.7:41234", Some("198.51.100.1")).to_http_request();
+
+        assert_eq!(client_ip(&request), "203.0.113.7");
+    }
+
+    #[test]
+    fn the_right_most_untrusted_hop_is_the_client() {
+        let request = request(
+            "10.1.2.3:41234",
+            Some("1.1.1.1, 198.51.100.1:5000, 10.0.0.7"),
+        )
+        .to_http_request();
+
+        assert_eq!(client_ip(&request), "198.51.100.1");
+    }
+
+    #[test]
+    fn trusted_proxies_without_a_forwarding_header_are_the_client() {
+        let request = request("[2001:db8::1]:41234", None).to_http_request();
+
+        assert_eq!(client_ip(&request), "2001:db8::1");
+    }
+
+    #[test]
+    fn invalid_proxies_are_rejected() {
+        for proxy in &["10.0.0.0/33", "example.com", "::1/129", "10.0.0.0/"] {
+            assert!(
+                TrustedProxies::parse(&[proxy.to_string()]).is_err(),
+                "{} was accepted",
+                proxy
+            );
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp, TestUser};
+use reqwest::Method;
+use zero2prod::authentication::Role;
+
+#[actix_rt::test]
+async fn every_role_can_view_reports() {
+    let app = spawn_app().await;
+
+    for role in &[Role::Admin, Role::Editor, Role::Viewer] {
+        let user = app.create_user(*role).await;
+        let response = app
+            .admin_request(Method::GET, "/reports/subscriptions", &user)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "A user with the {} role could not view reports.",
+            role
+        );
+        let report: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(report["confirmed"], 0);
+        assert_eq!(report["pending_confirmation"], 0);
+    }
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_publish_newsletters() {
+    let app = spawn_app().await;
+    let viewer = app.create_user(Role::Viewer).await;
+
+    let response = app
+        .post_newsletters_as(
+            &viewer.username,
+            &viewer.password,
+            newsletter_request_body(),
+        )
+        .await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn editors_can_publish_newsletters() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = app
+        .post_newsletters_as(
+            &editor.username,
+            &editor.password,
+            newsletter_request_body(),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn create_draft(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(&format!("{}/newsletters/drafts", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_draft_newsletters() {
+    let app = spawn_app().await;
+    let viewer = app.create_user(Role::Viewer).await;
+
+    let response = create_draft(&app, &viewer).await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn drafts_are_sent_once_published() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+    app.confirmed_subscriber().await;
+    let sent = app.email_server.received_requests().await.unwrap().len();
+
+    let response = create_draft(&app, &editor).await;
+    assert_eq!(201, response.status().as_u16());
+    let draft: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        sent
+    );
+    let publish = serde_json::json!({ "draft_id": draft["draft_id"] });
+    let response = app.post_newsletters(publish.clone()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), sent + 1);
+    let newsletter: serde_json::Value = serde_json::from_slice(&email_requests[sent].body).unwrap();
+    assert_eq!(newsletter["Subject"], "Newsletter title");
+    // Published drafts are gone, so they cannot be sent twice.
+    let response = app.post_newsletters(publish).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn only_admins_can_manage_users() {
+    let app = spawn_app().await;
+
+    for role in &[Role::Editor, Role::Viewer] {
+        let user = app.create_user(*role).await;
+        let response = app
+            .admin_request(Method::GET, "/users", &user)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(
+            403,
+            response.status().as_u16(),
+            "A user with the {} role was allowed to manage users.",
+            role
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn admin_endpoints_require_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/users", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn admins_can_create_users_who_can_then_log_in() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::POST, "/users", &app.test_user)
+        .json(&serde_json::json!({
+            "username": "ursula",
+            "password": "a-long-enough-password",
+            "role": "editor"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+
+    let response = app
+        .post_newsletters_as(
+            "ursula",
+            "a-long-enough-password",
+            newsletter_request_body(),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn creating_a_user_with_a_taken_username_returns_a_409() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::POST, "/users", &app.test_user)
+        .json(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": "a-long-enough-password",
+            "role": "viewer"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn admins_can_change_roles() {
+    let app = spawn_app().await;
+    let viewer = app.create_user(Role::Viewer).await;
+
+    let response = app
+        .admin_request(
+            Method::PUT,
+            &format!("/users/{}/role", viewer.user_id),
+            &app.test_user,
+        )
+        .json(&serde_json::json!({ "role": "editor" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT role FROM users WHERE user_id = $1", viewer.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch user.");
+    assert_eq!(saved.role, "editor");
+}
+
+#[actix_rt::test]
+async fn the_last_admin_cannot_be_demoted_or_deleted() {
+    let app = spawn_app().await;
+    let admin = &app.test_user;
+
+    let response = app
+        .admin_request(
+            Method::PUT,
+            &format!("/users/{}/role", admin.user_id),
+            admin,
+        )
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/users/{}", admin.user_id), admin)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn two_admins_demoting_each_other_at_once_leave_one_admin() {
+    let app = spawn_app().await;
+    let other_admin = app.create_user(Role::Admin).await;
+    let demote = |admin: &TestUser, target: &TestUser| {
+        app.admin_request(
+            Method::PUT,
+            &format!("/users/{}/role", target.user_id),
+            admin,
+        )
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+    };
+
+    let (first, second) = futures_util::join!(
+        demote(&app.test_user, &other_admin),
+        demote(&other_admin, &app.test_user)
+    );
+
+    let mut statuses = vec![
+        first.unwrap().status().as_u16(),
+        second.unwrap().status().as_u16(),
+    ];
+    statuses.sort_unstable();
+    // The second request runs after the first demoted its sender.
+    assert!(
+        statuses == [200, 403] || statuses == [200, 409],
+        "{:?}",
+        statuses
+    );
+    let admins = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM users WHERE role = 'admin'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(admins, 1);
+}
+
+#[actix_rt::test]
+async fn admins_can_delete_users() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = app
+        .admin_request(
+            Method::DELETE,
+            &format!("/users/{}", editor.user_id),
+            &app.test_user,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+
+    let saved = sqlx::query!(
+        "SELECT user_id FROM users WHERE user_id = $1",
+        editor.user_id
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .expect("Failed to fetch user.");
+    assert!(saved.is_none());
+}
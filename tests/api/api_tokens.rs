@@ -0,0 +1,143 @@
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp, TestUser};
+use reqwest::Method;
+use zero2prod::authentication::Role;
+
+#[actix_rt::test]
+async fn newsletters_can_be_published_with_an_api_token() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["publish_newsletters"]).await;
+
+    let response = post_newsletters_with_token(&app, &token["token"]).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn api_tokens_cannot_be_used_outside_their_scopes() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["view_reports"]).await;
+
+    let response = post_newsletters_with_token(&app, &token["token"]).await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn revoked_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["publish_newsletters"]).await;
+
+    let response = app
+        .admin_request(
+            Method::DELETE,
+            &format!("/tokens/{}", token["token_id"].as_str().unwrap()),
+            &app.test_user,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+
+    let response = post_newsletters_with_token(&app, &token["token"]).await;
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn expired_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["publish_newsletters"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = post_newsletters_with_token(&app, &token["token"]).await;
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn listing_api_tokens_shows_usage_but_not_the_token() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["publish_newsletters"]).await;
+    post_newsletters_with_token(&app, &token["token"]).await;
+
+    let tokens: serde_json::Value = app
+        .admin_request(Method::GET, "/tokens", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(tokens.as_array().unwrap().len(), 1);
+    assert_eq!(tokens[0]["token_id"], token["token_id"]);
+    assert!(tokens[0].get("token").is_none());
+    assert!(!tokens[0]["last_used_at"].is_null());
+}
+
+#[actix_rt::test]
+async fn api_tokens_cannot_exceed_the_role_of_their_owner() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = app
+        .admin_request(Method::POST, "/tokens", &editor)
+        .json(&serde_json::json!({
+            "name": "ci",
+            "scopes": ["manage_users"],
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn api_tokens_cannot_create_other_api_tokens() {
+    let app = spawn_app().await;
+    let token = create_api_token(&app, &app.test_user, &["manage_users"]).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/tokens", &app.address))
+        .bearer_auth(token["token"].as_str().unwrap())
+        .json(&serde_json::json!({
+            "name": "escalation",
+            "scopes": ["manage_users"],
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+async fn create_api_token(app: &TestApp, user: &TestUser, scopes: &[&str]) -> serde_json::Value {
+    let response = app
+        .admin_request(Method::POST, "/tokens", user)
+        .json(&serde_json::json!({
+            "name": "release notes",
+            "scopes": scopes,
+            "expires_in_days": 30,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+async fn post_newsletters_with_token(
+    app: &TestApp,
+    token: &serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .bearer_auth(token.as_str().unwrap())
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
@@ -0,0 +1,188 @@
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp};
+use reqwest::Method;
+use zero2prod::authentication::Role;
+
+async fn get_audit_events(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = app
+        .admin_request(
+            Method::GET,
+            &format!("/audit-events?{}", query),
+            &app.test_user,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+async fn fail_to_log_in(app: &TestApp) {
+    let response = app
+        .post_newsletters_as(
+            &app.test_user.username,
+            "wrong-password",
+            newsletter_request_body(),
+        )
+        .await;
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn publishing_a_newsletter_is_audited() {
+    let app = spawn_app().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let page = get_audit_events(&app, "action=publish_newsletter").await;
+    assert_eq!(page["total"], 1);
+    let event = &page["events"][0];
+    assert_eq!(event["actor"], app.test_user.username.as_str());
+    assert_eq!(event["actor_user_id"], app.test_user.user_id.to_string());
+    assert_eq!(event["target"], "Newsletter title");
+    assert!(!event["request_id"].is_null());
+    assert!(!event["occurred_at"].is_null());
+}
+
+#[actix_rt::test]
+async fn failed_logins_are_audited_with_the_attempted_username() {
+    let app = spawn_app().await;
+
+    fail_to_log_in(&app).await;
+
+    let page = get_audit_events(&app, "action=login_failed").await;
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["events"][0]["actor"], app.test_user.username.as_str());
+    assert!(page["events"][0]["actor_user_id"].is_null());
+}
+
+#[actix_rt::test]
+async fn successful_logins_are_audited_with_the_api_token_used() {
+    let app = spawn_app().await;
+    let response = app
+        .admin_request(Method::POST, "/tokens", &app.test_user)
+        .json(&serde_json::json!({
+            "name": "audit",
+            "scopes": ["view_audit_log"],
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    let token: serde_json::Value = response.json().await.unwrap();
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit-events?action=login", &app.address))
+        .bearer_auth(token["token"].as_str().unwrap())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let page: serde_json::Value = response.json().await.unwrap();
+    // The token was created after a login with a password.
+    assert_eq!(page["total"], 2);
+    let event = &page["events"][0];
+    assert_eq!(event["actor"], app.test_user.username.as_str());
+    assert_eq!(event["actor_user_id"], app.test_user.user_id.to_string());
+    assert_eq!(event["target"], token["token_id"]);
+    assert_ne!(page["events"][1]["target"], token["token_id"]);
+}
+
+#[actix_rt::test]
+async fn user_changes_are_audited() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    app.admin_request(
+        Method::PUT,
+        &format!("/users/{}/role", editor.user_id),
+        &app.test_user,
+    )
+    .json(&serde_json::json!({ "role": "viewer" }))
+    .send()
+    .await
+    .expect("Failed to execute request.");
+    app.admin_request(
+        Method::DELETE,
+        &format!("/users/{}", editor.user_id),
+        &app.test_user,
+    )
+    .send()
+    .await
+    .expect("Failed to execute request.");
+
+    let page = get_audit_events(&app, &format!("target={}", editor.user_id)).await;
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["events"][0]["action"], "delete_user");
+    assert_eq!(page["events"][1]["action"], "change_user_role");
+}
+
+#[actix_rt::test]
+async fn audit_events_are_paginated() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.admin_request(Method::POST, "/users", &app.test_user)
+            .json(&serde_json::json!({
+                "username": uuid::Uuid::new_v4().to_string(),
+                "password": "a-long-enough-password",
+                "role": "viewer"
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    let first_page = get_audit_events(&app, "action=create_user&per_page=2").await;
+    let second_page = get_audit_events(&app, "action=create_user&per_page=2&page=2").await;
+
+    assert_eq!(first_page["total"], 3);
+    assert_eq!(first_page["events"].as_array().unwrap().len(), 2);
+    assert_eq!(second_page["events"].as_array().unwrap().len(), 1);
+    assert_ne!(
+        first_page["events"][0]["event_id"],
+        second_page["events"][0]["event_id"]
+    );
+}
+
+#[actix_rt::test]
+async fn audit_events_can_be_filtered_by_time() {
+    let app = spawn_app().await;
+    fail_to_log_in(&app).await;
+
+    let before = get_audit_events(&app, "action=login_failed&until=2000-01-01T00:00:00Z").await;
+    let after = get_audit_events(&app, "action=login_failed&since=2000-01-01T00:00:00Z").await;
+
+    assert_eq!(before["total"], 0);
+    assert_eq!(after["total"], 1);
+}
+
+#[actix_rt::test]
+async fn only_admins_can_read_the_audit_log() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = app
+        .admin_request(Method::GET, "/audit-events", &editor)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn audit_events_cannot_be_altered() {
+    let app = spawn_app().await;
+    fail_to_log_in(&app).await;
+
+    let update = sqlx::query!("UPDATE audit_events SET actor = 'someone else'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_events")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
@@ -0,0 +1,145 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Method;
+use zero2prod::authentication::Role;
+
+/// Subscribes from a campaign page and confirms, with a recognisable user agent.
+async fn subscribe_and_confirm(app: &TestApp) {
+    let client = reqwest::Client::builder()
+        .user_agent("consent-test")
+        .build()
+        .unwrap();
+    let links = app
+        .subscribe_with(
+            &client,
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&source=autumn-campaign",
+        )
+        .await;
+    client
+        .get(links.confirmation)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_rt::test]
+async fn signup_and_confirmation_are_recorded_as_proof_of_consent() {
+    let app = spawn_app().await;
+
+    subscribe_and_confirm(&app).await;
+
+    let events = sqlx::query!(
+        r#"
+        SELECT kind, ip, user_agent, source, consent_text_version
+        FROM consent_events
+        ORDER BY occurred_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].kind, "signup");
+    assert_eq!(events[0].ip, "127.0.0.1");
+    assert_eq!(events[0].user_agent.as_deref(), Some("consent-test"));
+    assert_eq!(events[0].source.as_deref(), Some("autumn-campaign"));
+    assert_eq!(
+        events[0].consent_text_version.as_deref(),
+        Some("2021-11-01")
+    );
+    assert_eq!(events[1].kind, "confirmation");
+    assert_eq!(events[1].ip, "127.0.0.1");
+    assert_eq!(events[1].source, None);
+}
+
+#[actix_rt::test]
+async fn confirming_twice_at_once_records_a_single_confirmation() {
+    let app = spawn_app().await;
+    let confirmation = app.subscribe().await.confirmation;
+
+    let (first, second) = futures_util::join!(
+        reqwest::get(confirmation.clone()),
+        reqwest::get(confirmation)
+    );
+
+    assert_eq!(200, first.unwrap().status().as_u16());
+    assert_eq!(200, second.unwrap().status().as_u16());
+    let confirmations = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM consent_events WHERE kind = 'confirmation'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(confirmations, 1);
+    assert!(app
+        .get_metrics()
+        .await
+        .contains("subscriptions_confirmed_total 1\n"));
+}
+
+#[actix_rt::test]
+async fn consent_history_cannot_be_changed() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app).await;
+
+    let updated = sqlx::query!("UPDATE consent_events SET ip = '10.0.0.1'")
+        .execute(&app.db_pool)
+        .await;
+    let deleted = sqlx::query!("DELETE FROM consent_events WHERE kind = 'confirmation'")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(updated.is_err());
+    assert!(deleted.is_err());
+}
+
+#[actix_rt::test]
+async fn erasing_a_subscriber_erases_their_consent_history() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(
+            Method::DELETE,
+            "/subscribers/ursula_le_guin@gmail.com",
+            &admin,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(204, response.status().as_u16());
+    let events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM consent_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(events, 0);
+}
+
+#[actix_rt::test]
+async fn exports_include_the_consent_history() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(
+            Method::GET,
+            "/subscribers/ursula_le_guin@gmail.com/data",
+            &admin,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let data: serde_json::Value = response.json().await.unwrap();
+    let consent = data["consent"].as_array().unwrap();
+    assert_eq!(consent.len(), 2);
+    assert_eq!(consent[0]["kind"], "signup");
+    assert_eq!(consent[0]["source"], "autumn-campaign");
+    assert_eq!(consent[1]["kind"], "confirmation");
+}
@@ -0,0 +1,70 @@
+use crate::helpers::spawn_app;
+
+#[actix_rt::test]
+async fn errors_are_reported_as_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(body["instance"], "/subscriptions");
+    assert!(body["detail"].is_string());
+    assert!(body["request_id"].is_string());
+}
+
+#[actix_rt::test]
+async fn malformed_query_strings_are_invalid_requests() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_request");
+}
+
+#[actix_rt::test]
+async fn unknown_routes_are_not_found() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/nowhere", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "not_found");
+}
+
+#[actix_rt::test]
+async fn browsers_get_an_html_error_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/nowhere", app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("There is nothing at this address."));
+    assert!(body.contains("Request id:"));
+}
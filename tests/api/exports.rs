@@ -0,0 +1,215 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use reqwest::Method;
+use std::collections::HashSet;
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+
+/// Three subscribers signed up a day apart: only the second is confirmed and only the third
+/// joined the `legacy` list, besides `newsletter`.
+async fn create_subscribers(app: &TestApp) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, status, locale, subscribed_at)
+        VALUES
+            ($1, 'ursula_le_guin@gmail.com', 'le guin', 'pending_confirmation', 'en', '2021-11-01T10:00:00Z'),
+            ($2, 'octavia@example.com', 'Butler, Octavia', 'confirmed', 'de', '2021-11-02T10:00:00Z'),
+            ($3, 'ted@example.com', 'Ted', 'pending_confirmation', 'en', '2021-11-03T10:00:00Z')
+        "#,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'legacy', 'Legacy')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id)
+        SELECT subscriptions.id, lists.list_id
+        FROM subscriptions, lists
+        WHERE lists.slug = 'newsletter' OR subscriptions.email = 'ted@example.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn export(app: &TestApp, user: &TestUser, query: &str) -> reqwest::Response {
+    app.admin_request(Method::GET, &format!("/subscribers{}", query), user)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn export_ndjson(app: &TestApp, user: &TestUser, query: &str) -> Vec<serde_json::Value> {
+    let response = export(app, user, query).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "application/x-ndjson"
+    );
+    response
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = export(&app, &admin, "").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0],
+        "id,email,name,status,locale,subscribed_at,paused_until,lists"
+    );
+    assert!(lines[1].contains(",ursula_le_guin@gmail.com,le guin,pending_confirmation,en,"));
+    assert!(lines[2].contains(",octavia@example.com,\"Butler, Octavia\",confirmed,de,"));
+    assert!(lines[3].ends_with(",legacy newsletter"));
+}
+
+#[actix_rt::test]
+async fn exports_can_be_filtered_by_status_list_and_signup_date() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+    let test_cases = vec![
+        ("?status=confirmed", vec!["octavia@example.com"]),
+        ("?list=legacy", vec!["ted@example.com"]),
+        (
+            "?status=pending_confirmation&list=newsletter",
+            vec!["ursula_le_guin@gmail.com", "ted@example.com"],
+        ),
+        (
+            "?since=2021-11-02T00:00:00Z&until=2021-11-03T10:00:00Z",
+            vec!["octavia@example.com"],
+        ),
+    ];
+
+    for (query, expected) in test_cases {
+        let subscribers = export_ndjson(&app, &admin, &format!("{}&format=ndjson", query)).await;
+
+        let emails: Vec<_> = subscribers
+            .iter()
+            .map(|subscriber| subscriber["email"].as_str().unwrap())
+            .collect();
+        assert_eq!(emails, expected, "Unexpected export for {}.", query);
+    }
+}
+
+#[actix_rt::test]
+async fn ndjson_exports_hold_one_subscriber_per_line_without_tokens() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let subscribers = export_ndjson(&app, &admin, "?format=ndjson").await;
+
+    assert_eq!(subscribers.len(), 3);
+    assert_eq!(subscribers[2]["name"], "Ted");
+    assert_eq!(
+        subscribers[2]["lists"],
+        serde_json::json!(["legacy", "newsletter"])
+    );
+    assert!(subscribers[2]["paused_until"].is_null());
+    assert!(subscribers[2].get("preferences_token").is_none());
+}
+
+#[actix_rt::test]
+async fn exports_larger_than_a_page_hold_every_subscriber_once() {
+    let app = spawn_app().await;
+    // Signed up in the same instant, so that pages are told apart by id alone.
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, status, subscribed_at)
+        SELECT md5(i::TEXT)::UUID, 'subscriber' || i || '@example.com', 'Subscriber', 'confirmed', now()
+        FROM generate_series(1, 2500) AS i
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let admin = app.create_user(Role::Admin).await;
+
+    let subscribers = export_ndjson(&app, &admin, "?format=ndjson").await;
+
+    let emails: HashSet<_> = subscribers
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 2500);
+    assert_eq!(emails.len(), 2500);
+}
+
+#[actix_rt::test]
+async fn csv_exports_do_not_carry_formulas() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, status, subscribed_at)
+        VALUES ($1, 'mallory@example.com', '=HYPERLINK("https://example.com")', 'confirmed', now())
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let admin = app.create_user(Role::Admin).await;
+
+    let body = export(&app, &admin, "").await.text().await.unwrap();
+
+    assert!(body.contains(",mallory@example.com,\"'=HYPERLINK(\"\"https://example.com\"\")\","));
+}
+
+#[actix_rt::test]
+async fn exports_of_an_unknown_list_or_format_are_rejected() {
+    let app = spawn_app().await;
+    let admin = app.create_user(Role::Admin).await;
+
+    for query in vec!["?list=nope", "?format=xml", "?status=unsubscribed"] {
+        let response = export(&app, &admin, query).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for {}.",
+            query
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn editors_cannot_export_subscribers() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = export(&app, &editor, "").await;
+
+    assert_eq!(403, response.status().as_u16());
+}
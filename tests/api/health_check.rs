@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
 
 #[actix_rt::test]
 async fn health_check_works() {
@@ -13,3 +13,80 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+async fn get_readiness(app: &TestApp) -> (u16, serde_json::Value) {
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[actix_rt::test]
+async fn liveness_does_not_depend_on_anything() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn the_application_is_ready_when_its_dependencies_are() {
+    let app = spawn_app().await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(200, status);
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["status"], "ok");
+    assert!(body["checks"]["email_provider"].is_null());
+}
+
+#[actix_rt::test]
+async fn the_application_is_not_ready_with_pending_migrations() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["status"], "unavailable");
+    // The reason is logged, not served.
+    assert!(body["checks"]["migrations"]["error"].is_null());
+}
+
+#[actix_rt::test]
+async fn the_email_provider_can_be_required_for_readiness() {
+    let app = spawn_app_with(|config| config.health.check_email_provider = true).await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(200, status);
+    assert_eq!(body["checks"]["email_provider"]["status"], "ok");
+}
+
+#[actix_rt::test]
+async fn the_application_is_not_ready_if_the_email_provider_is_unreachable() {
+    let app = spawn_app_with(|config| {
+        config.health.check_email_provider = true;
+        // Nothing listens on port 1.
+        config.email_client.base_url = "http://127.0.0.1:1".into();
+    })
+    .await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!(body["checks"]["email_provider"]["status"], "unavailable");
+}
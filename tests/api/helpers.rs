@@ -1,41 +1,49 @@
-use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
 use once_cell::sync::Lazy;
 use sqlx::types::Uuid;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::{compute_password_hash, Role};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::reload::{ReloadError, Reloader};
+use zero2prod::shutdown::ShutdownHandle;
 use zero2prod::startup::{get_connection_pool, Application};
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, get_tracer_provider, init_subscriber, init_tracer};
 
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: Role,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role(Role::Admin)
+    }
+
+    pub fn with_role(role: Role) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
-        let salt = SaltString::generate(&mut rand::thread_rng());
-        let password_hash = Argon2::default()
-            .hash_password(self.password.as_bytes(), &salt)
-            .unwrap()
-            .to_string();
+    pub async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(self.password.clone()).unwrap();
         sqlx::query!(
             r#"
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)
             "#,
             self.user_id,
             self.username,
             password_hash,
+            self.role.as_str(),
         )
         .execute(pool)
         .await
@@ -49,9 +57,32 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
+    /// Set when `/metrics` is served on the admin port rather than next to the API.
+    pub metrics_address: Option<String>,
+    /// Set when plain HTTP is redirected to HTTPS.
+    pub https_redirect_address: Option<String>,
+    pub shutdown: ShutdownHandle,
+    reloader: Arc<Reloader>,
+    // What the application reads when it reloads its configuration.
+    configuration: Arc<Mutex<Settings>>,
 }
 
 impl TestApp {
+    /// Edits the configuration the application reads on reload, then triggers a reload.
+    pub fn reload_with(&self, change: impl FnOnce(&mut Settings)) -> Result<(), ReloadError> {
+        change(&mut self.configuration.lock().unwrap());
+        self.reloader.reload()
+    }
+
+    /// Mounts an email server that accepts every email after `delay`.
+    pub async fn mount_email_server(&self, delay: Duration) {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(delay))
+            .mount(&self.email_server)
+            .await;
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
             .post(format!("{}/subscriptions", self.address))
@@ -62,16 +93,101 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Subscribes `ursula_le_guin@gmail.com` from the signup form, then returns the links of the
+    /// confirmation email.
+    pub async fn subscribe(&self) -> SubscriptionLinks {
+        self.subscribe_with(
+            &reqwest::Client::new(),
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        )
+        .await
+    }
+
+    /// Like `subscribe`, but lets the test pick the client and the form it sends.
+    pub async fn subscribe_with(&self, client: &reqwest::Client, body: &str) -> SubscriptionLinks {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.email_server)
+            .await;
+        client
+            .post(format!("{}/subscriptions", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap();
+        let email_requests = self.email_server.received_requests().await.unwrap();
+        let email_request = email_requests.last().unwrap();
+        SubscriptionLinks {
+            confirmation: self.get_confirmation_links(email_request).html,
+            preferences: self.get_preferences_link(email_request),
+        }
+    }
+
+    /// Subscribes as `subscribe` does, then confirms.
+    pub async fn confirmed_subscriber(&self) -> SubscriptionLinks {
+        let links = self.subscribe().await;
+        reqwest::get(links.confirmation.clone())
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap();
+        links
+    }
+
+    /// Scrapes `/metrics` from wherever it is served.
+    pub async fn get_metrics(&self) -> String {
+        let address = self.metrics_address.as_ref().unwrap_or(&self.address);
+        let response = reqwest::Client::new()
+            .get(format!("{}/metrics", address))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(200, response.status().as_u16());
+        response.text().await.unwrap()
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.post_newsletters_as(&self.test_user.username, &self.test_user.password, body)
+            .await
+    }
+
+    pub async fn post_newsletters_as(
+        &self,
+        username: &str,
+        password: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
         reqwest::Client::new()
             .post(&format!("{}/newsletters", &self.address))
-            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .basic_auth(username, Some(password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// Stores an extra user with the given role, next to the admin `test_user`.
+    pub async fn create_user(&self, role: Role) -> TestUser {
+        let user = TestUser::with_role(role);
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    pub fn admin_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        user: &TestUser,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin{}", &self.address, path))
+            .basic_auth(&user.username, Some(&user.password))
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body)
             .expect("Failed to deserialize request body.");
@@ -85,6 +201,16 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    /// The link to the preference center, in the footer of the text body.
+    pub fn get_preferences_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body)
+            .expect("Failed to deserialize request body.");
+        let text = body["TextBody"].as_str().unwrap();
+        let (_, footer) = text.rsplit_once("\n--\n").expect("Footer not found.");
+        self.find_url(footer)
+            .expect("Link not found in the footer.")
+    }
+
     fn find_url(&self, s: &str) -> Option<reqwest::Url> {
         let links: Vec<_> = linkify::LinkFinder::new()
             .links(s)
@@ -119,20 +245,45 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+/// The links of the email confirming a subscription.
+pub struct SubscriptionLinks {
+    pub confirmation: reqwest::Url,
+    pub preferences: reqwest::Url,
+}
+
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let otlp = get_configuration()
+        .expect("Failed to read configuration.")
+        .telemetry
+        .otlp;
+    let tracer = init_tracer(
+        get_tracer_provider(subscriber_name.clone(), &otlp)
+            .expect("Failed to build the tracer provider."),
+    );
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+        );
+        init_subscriber(subscriber, log_filter);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
+        let (subscriber, log_filter) =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink, tracer);
+        init_subscriber(subscriber, log_filter);
     }
 });
 
 // Decouple our app from the rest of the test.
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, but lets the test tweak the configuration before the application is built.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     // Execute the code in TRACING at most once. This prevents failures caused by initializing tracing multiple times.
     Lazy::force(&TRACING);
 
@@ -146,15 +297,28 @@ pub async fn spawn_app() -> TestApp {
         config.application.port = 0;
         // Use mock server for email API
         config.email_client.base_url = email_server.uri();
+        customise(&mut config);
         config
     };
 
     // Create and migrate the database
     configure_database(&configuration.database).await;
 
-    let application = Application::build(configuration.clone())
-        .await
-        .expect("Failed to build application.");
+    let reloaded_configuration = Arc::new(Mutex::new(configuration.clone()));
+    let application = Application::build_with_loader(configuration.clone(), {
+        let reloaded_configuration = reloaded_configuration.clone();
+        Box::new(move || Ok(reloaded_configuration.lock().unwrap().clone()))
+    })
+    .await
+    .expect("Failed to build application.");
+    let reloader = application.reloader();
+    let shutdown = application.shutdown_handle();
+    let https_redirect_address = application
+        .https_redirect_port()
+        .map(|port| format!("http://127.0.0.1:{}", port));
+    let metrics_address = application
+        .metrics_port()
+        .map(|port| format!("http://127.0.0.1:{}", port));
 
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
@@ -168,6 +332,11 @@ pub async fn spawn_app() -> TestApp {
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
+        metrics_address,
+        https_redirect_address,
+        shutdown,
+        reloader,
+        configuration: reloaded_configuration,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -175,6 +344,17 @@ pub async fn spawn_app() -> TestApp {
     test_app
 }
 
+/// A valid body for `POST /newsletters`.
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
     // The database doesn't exist yet. Hence create connection without DB name.
     let mut connection = PgConnection::connect_with(&config.without_db())
@@ -0,0 +1,304 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp, TestUser};
+use reqwest::Method;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::authentication::Role;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::EmailClient;
+use zero2prod::import::resume_import_jobs;
+
+const UPLOAD: &str = "name,email,phone\r
+le guin,ursula_le_guin@gmail.com,555\r
+\"Butler, Octavia\",octavia@example.com,\r
+no address,not-an-email,\r
+Ursula again,URSULA_LE_GUIN@gmail.com,\r
+";
+
+async fn start_import(
+    app: &TestApp,
+    user: &TestUser,
+    query: &str,
+    body: &str,
+) -> reqwest::Response {
+    app.admin_request(Method::POST, &format!("/imports{}", query), user)
+        .header("Content-Type", "text/csv")
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Polls the import until its rows are all processed.
+async fn wait_for_import(app: &TestApp, user: &TestUser, job_id: &str) -> serde_json::Value {
+    for _ in 0..100 {
+        let job: serde_json::Value = app
+            .admin_request(Method::GET, &format!("/imports/{}", job_id), user)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap();
+        if job["status"] == "completed" {
+            return job;
+        }
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The import did not complete.");
+}
+
+async fn import(app: &TestApp, user: &TestUser, query: &str, body: &str) -> serde_json::Value {
+    let response = start_import(app, user, query, body).await;
+    assert_eq!(202, response.status().as_u16());
+    let job: serde_json::Value = response.json().await.unwrap();
+    wait_for_import(app, user, job["job_id"].as_str().unwrap()).await
+}
+
+#[actix_rt::test]
+async fn imported_subscribers_are_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let job = import(&app, &admin, "", UPLOAD).await;
+
+    assert_eq!(
+        job["rows"],
+        serde_json::json!({
+            "total": 4,
+            "pending": 0,
+            "imported": 2,
+            "duplicate": 1,
+            "invalid": 1,
+            "failed": 0
+        })
+    );
+    let subscribers = sqlx::query!("SELECT name, status FROM subscriptions ORDER BY name")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 2);
+    assert_eq!(subscribers[0].name, "Butler, Octavia");
+    assert!(subscribers
+        .iter()
+        .all(|subscriber| subscriber.status == "pending_confirmation"));
+    let consent = sqlx::query!("SELECT source FROM consent_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(consent.len(), 2);
+    assert!(consent
+        .iter()
+        .all(|event| event.source.as_deref() == Some("import")));
+}
+
+#[actix_rt::test]
+async fn confirmed_imports_skip_double_opt_in_and_can_join_a_list() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'legacy', 'Legacy')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let admin = app.create_user(Role::Admin).await;
+
+    let job = import(&app, &admin, "?confirmed=true&list=legacy", UPLOAD).await;
+
+    assert_eq!(job["rows"]["imported"], 2);
+    assert_eq!(job["list"], "legacy");
+    let memberships = sqlx::query!(
+        r#"
+        SELECT lists.slug
+        FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE subscriptions.email = 'octavia@example.com' AND subscriptions.status = 'confirmed'
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let slugs: Vec<_> = memberships.into_iter().map(|row| row.slug).collect();
+    assert_eq!(slugs, vec!["legacy", "newsletter"]);
+}
+
+#[actix_rt::test]
+async fn rows_that_were_not_imported_can_be_downloaded() {
+    let app = spawn_app().await;
+    let admin = app.create_user(Role::Admin).await;
+    let job = import(&app, &admin, "?confirmed=true", UPLOAD).await;
+
+    let response = app
+        .admin_request(
+            Method::GET,
+            &format!("/imports/{}/errors", job["job_id"].as_str().unwrap()),
+            &admin,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let report = response.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "line,email,name,outcome,error");
+    assert!(lines[1].starts_with("4,not-an-email,no address,invalid,"));
+    assert_eq!(
+        lines[2],
+        "5,URSULA_LE_GUIN@gmail.com,Ursula again,duplicate,The address is already subscribed."
+    );
+}
+
+#[actix_rt::test]
+async fn addresses_that_are_already_subscribed_are_skipped() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let job = import(&app, &admin, "?confirmed=true", UPLOAD).await;
+
+    assert_eq!(job["rows"]["imported"], 1);
+    assert_eq!(job["rows"]["duplicate"], 2);
+    let status =
+        sqlx::query!("SELECT status FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .status;
+    assert_eq!(status, "pending_confirmation");
+}
+
+#[actix_rt::test]
+async fn uploads_without_the_required_columns_or_with_an_unknown_list_are_rejected() {
+    let app = spawn_app().await;
+    let admin = app.create_user(Role::Admin).await;
+    let test_cases = vec![
+        (
+            "",
+            "email,phone\na@example.com,555\n",
+            "missing name column",
+        ),
+        ("", "", "empty upload"),
+        ("?list=nope", UPLOAD, "unknown list"),
+    ];
+
+    for (query, body, description) in test_cases {
+        let response = start_import(&app, &admin, query, body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+    let jobs = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM import_jobs"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(jobs, 0);
+}
+
+#[actix_rt::test]
+async fn uploads_over_the_limit_are_rejected() {
+    let app = spawn_app_with(|config| config.limits.imports.max_body_bytes = 64).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = start_import(&app, &admin, "", UPLOAD).await;
+
+    assert_eq!(413, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn editors_cannot_import_subscribers() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let response = start_import(&app, &editor, "", UPLOAD).await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn interrupted_imports_resume_where_they_stopped() {
+    let app = spawn_app().await;
+    let job_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO import_jobs (job_id, status, confirmed, created_by, created_from, created_at)
+        VALUES ($1, 'running', true, 'admin', '127.0.0.1', now())
+        "#,
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO import_rows (job_id, line, email, name, locale, outcome)
+        VALUES
+            ($1, 2, 'done@example.com', 'Done', 'en', 'imported'),
+            ($1, 3, 'pending@example.com', 'Pending', 'en', NULL)
+        "#,
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let email_client = EmailClient::new(
+        app.email_server.uri(),
+        SubscriberEmail::parse("test@example.com".into()).unwrap(),
+        "token".to_string().into(),
+        Duration::from_secs(1),
+    );
+
+    resume_import_jobs(
+        app.db_pool.clone(),
+        Arc::new(email_client),
+        app.address.clone(),
+    )
+    .await
+    .unwrap();
+
+    let emails: Vec<_> = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.email)
+        .collect();
+    assert_eq!(emails, vec!["pending@example.com"]);
+    let status = sqlx::query!("SELECT status FROM import_jobs")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "completed");
+}
@@ -0,0 +1,84 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use std::time::Duration;
+
+#[actix_rt::test]
+async fn oversized_subscription_forms_are_rejected_with_413() {
+    let app = spawn_app().await;
+    let name = "a".repeat(8 * 1024);
+
+    let response = app
+        .post_subscriptions(format!("name={}&email=ursula_le_guin%40gmail.com", name))
+        .await;
+
+    assert_eq!(413, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "payload_too_large");
+}
+
+#[actix_rt::test]
+async fn newsletters_may_be_larger_than_other_bodies() {
+    let app = spawn_app().await;
+    let html = format!("<p>{}</p>", "a".repeat(64 * 1024));
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body as plain text", "html": html },
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn requests_running_past_their_timeout_are_cancelled() {
+    let app = spawn_app_with(|config| config.limits.subscriptions.timeout_milliseconds = 200).await;
+    app.mount_email_server(Duration::from_secs(2)).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(504, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "timed_out");
+}
+
+#[actix_rt::test]
+async fn requests_beyond_the_concurrency_limit_are_shed() {
+    let app = spawn_app_with(|config| config.limits.max_concurrent_requests = 1).await;
+    app.mount_email_server(Duration::from_millis(500)).await;
+
+    let slow_request =
+        app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into());
+    let shed_request = async {
+        actix_rt::time::sleep(Duration::from_millis(100)).await;
+        app.post_subscriptions("name=le%20guin&email=le_guin%40gmail.com".into())
+            .await
+    };
+    let (slow_response, shed_response) = futures_util::join!(slow_request, shed_request);
+
+    assert_eq!(200, slow_response.status().as_u16());
+    assert_eq!(503, shed_response.status().as_u16());
+    assert_eq!(shed_response.headers()["Retry-After"], "5");
+    let body: serde_json::Value = shed_response.json().await.unwrap();
+    assert_eq!(body["code"], "overloaded");
+}
+
+#[actix_rt::test]
+async fn health_checks_are_not_shed() {
+    let app = spawn_app_with(|config| config.limits.max_concurrent_requests = 1).await;
+    app.mount_email_server(Duration::from_millis(500)).await;
+
+    let slow_request =
+        app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into());
+    let health_check = async {
+        actix_rt::time::sleep(Duration::from_millis(100)).await;
+        reqwest::get(format!("{}/health/live", app.address))
+            .await
+            .expect("Failed to execute request.")
+    };
+    let (_, health_response) = futures_util::join!(slow_request, health_check);
+
+    assert_eq!(200, health_response.status().as_u16());
+}
@@ -0,0 +1,124 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscribe(app: &TestApp, body: &str, accept_language: &str) -> reqwest::Response {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html")
+        .header("Accept-Language", accept_language)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn sent_email(app: &TestApp) -> serde_json::Value {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[actix_rt::test]
+async fn the_locale_is_negotiated_from_accept_language() {
+    let app = spawn_app().await;
+
+    let response = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "fr-FR, fr;q=0.9, en;q=0.5",
+    )
+    .await;
+
+    assert_eq!(response.headers()["Content-Language"], "fr");
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Consultez votre boîte de réception"));
+    let email = sent_email(&app).await;
+    assert_eq!(email["Subject"], "Bienvenue !");
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("pour confirmer votre inscription"));
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "fr");
+}
+
+#[actix_rt::test]
+async fn the_locale_from_the_form_wins_over_accept_language() {
+    let app = spawn_app().await;
+
+    subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=de",
+        "fr",
+    )
+    .await;
+
+    assert_eq!(sent_email(&app).await["Subject"], "Willkommen!");
+}
+
+#[actix_rt::test]
+async fn unknown_locales_fall_back_to_the_default() {
+    let app = spawn_app().await;
+
+    subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=tlh",
+        "it",
+    )
+    .await;
+
+    assert_eq!(sent_email(&app).await["Subject"], "Welcome!");
+}
+
+#[actix_rt::test]
+async fn confirmation_pages_use_the_subscribers_locale() {
+    let app = spawn_app().await;
+    subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr",
+        "en",
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+
+    // The link may well be opened in a browser set to another language.
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Bienvenue, le guin !"));
+}
+
+#[actix_rt::test]
+async fn error_pages_are_localised() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/nowhere", app.address))
+        .header("Accept", "text/html")
+        .header("Accept-Language", "de")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(404, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<h1>Seite nicht gefunden</h1>"));
+    assert!(body.contains("Anfrage-ID:"));
+}
@@ -1,5 +1,25 @@
+mod admin;
+mod api_tokens;
+mod audit_events;
+mod consent;
+mod errors;
+mod exports;
 mod health_check;
 mod helpers;
+mod imports;
+mod limits;
+mod localisation;
+mod metrics;
 mod newsletters;
+mod pages;
+mod personal_data;
+mod preferences;
+mod reload;
+mod security;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod telemetry;
+mod tls;
+mod trace_propagation;
+mod two_factor;
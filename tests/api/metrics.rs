@@ -0,0 +1,111 @@
+use crate::helpers::{newsletter_request_body, spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[actix_rt::test]
+async fn requests_are_counted_per_route() {
+    let app = spawn_app().await;
+    for _ in 0..2 {
+        reqwest::get(format!("{}/health_check", app.address))
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    let metrics = app.get_metrics().await;
+
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/health_check",status="200"} 2"#));
+    assert!(metrics
+        .contains(r#"http_request_duration_seconds_count{method="GET",route="/health_check"} 2"#));
+    assert!(metrics.contains(r#"db_pool_connections{state="idle"}"#));
+}
+
+#[actix_rt::test]
+async fn subscriptions_emails_and_deliveries_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let metrics = app.get_metrics().await;
+
+    assert!(metrics.contains("subscriptions_created_total 1"));
+    assert!(metrics.contains("subscriptions_confirmed_total 1"));
+    assert!(metrics.contains("email_send_attempts_total 2"));
+    assert!(metrics.contains("email_send_failures_total 0"));
+    assert!(metrics.contains(r#"newsletter_deliveries_total{outcome="sent"} 1"#));
+    assert!(metrics.contains("newsletter_deliveries_pending 0"));
+}
+
+#[actix_rt::test]
+async fn asking_for_a_new_confirmation_link_is_not_counted_as_a_new_subscription() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains("subscriptions_created_total 1\n"));
+    assert!(metrics.contains("email_send_attempts_total 2"));
+}
+
+#[actix_rt::test]
+async fn failed_emails_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains("email_send_attempts_total 1"));
+    assert!(metrics.contains("email_send_failures_total 1"));
+}
+
+#[actix_rt::test]
+async fn metrics_can_be_moved_to_the_admin_port() {
+    let app = spawn_app_with(|config| {
+        config.metrics.serve_on_admin_port = true;
+        config.metrics.admin_port = 0;
+    })
+    .await;
+
+    let public = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, public.status().as_u16());
+    assert!(app.metrics_address.is_some());
+    assert!(app.get_metrics().await.contains("http_requests_total"));
+}
@@ -1,4 +1,6 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    newsletter_request_body, spawn_app, spawn_app_with, ConfirmationLinks, TestApp,
+};
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -202,3 +204,103 @@ async fn create_confirmed_subscriber(app: &TestApp) {
         .error_for_status()
         .unwrap();
 }
+
+#[actix_rt::test]
+async fn users_are_locked_out_after_too_many_failed_attempts() {
+    let app = spawn_app_with(|config| {
+        config.login_protection.max_failed_attempts_per_user = 3;
+    })
+    .await;
+
+    for _ in 0..3 {
+        let response = app
+            .post_newsletters_as(
+                &app.test_user.username,
+                &Uuid::new_v4().to_string(),
+                newsletter_request_body(),
+            )
+            .await;
+        assert_eq!(401, response.status().as_u16());
+    }
+
+    // Even the correct password is refused while the lockout lasts.
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[actix_rt::test]
+async fn unknown_usernames_are_locked_out_like_existing_ones() {
+    let app = spawn_app_with(|config| {
+        config.login_protection.max_failed_attempts_per_user = 2;
+    })
+    .await;
+    let username = Uuid::new_v4().to_string();
+
+    for _ in 0..2 {
+        app.post_newsletters_as(
+            &username,
+            &Uuid::new_v4().to_string(),
+            newsletter_request_body(),
+        )
+        .await;
+    }
+    let response = app
+        .post_newsletters_as(
+            &username,
+            &Uuid::new_v4().to_string(),
+            newsletter_request_body(),
+        )
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn clients_are_locked_out_after_too_many_failed_attempts_across_usernames() {
+    let app = spawn_app_with(|config| {
+        config.login_protection.max_failed_attempts_per_ip = 3;
+    })
+    .await;
+
+    for _ in 0..3 {
+        app.post_newsletters_as(
+            &Uuid::new_v4().to_string(),
+            &Uuid::new_v4().to_string(),
+            newsletter_request_body(),
+        )
+        .await;
+    }
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn a_successful_login_resets_the_failed_attempts_of_a_user() {
+    let app = spawn_app_with(|config| {
+        config.login_protection.max_failed_attempts_per_user = 2;
+    })
+    .await;
+
+    app.post_newsletters_as(
+        &app.test_user.username,
+        &Uuid::new_v4().to_string(),
+        newsletter_request_body(),
+    )
+    .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = app
+        .post_newsletters_as(
+            &app.test_user.username,
+            &Uuid::new_v4().to_string(),
+            newsletter_request_body(),
+        )
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+}
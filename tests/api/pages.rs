@@ -0,0 +1,185 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get(url: impl reqwest::IntoUrl) -> reqwest::Response {
+    reqwest::get(url).await.expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn the_home_page_is_a_signup_form() {
+    let app = spawn_app().await;
+
+    let response = get(&app.address).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"<form action="/subscriptions" method="post">"#));
+    assert!(body.contains(r#"href="/static/style.css""#));
+}
+
+#[actix_rt::test]
+async fn the_stylesheet_is_served() {
+    let app = spawn_app().await;
+
+    let response = get(format!("{}/static/style.css", app.address)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/css; charset=utf-8"
+    );
+}
+
+#[actix_rt::test]
+async fn subscribing_shows_a_check_your_inbox_page() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Check your inbox"));
+    assert!(body.contains("ursula_le_guin@gmail.com"));
+}
+
+#[actix_rt::test]
+async fn api_clients_get_json_rather_than_the_check_your_inbox_page() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "email": "ursula_le_guin@gmail.com" })
+    );
+}
+
+#[actix_rt::test]
+async fn confirming_shows_a_confirmation_page() {
+    let app = spawn_app().await;
+    let confirmation_link = app.subscribe().await.confirmation;
+
+    let response = get(confirmation_link).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("You're in, le guin!"));
+}
+
+#[actix_rt::test]
+async fn confirming_twice_shows_an_already_confirmed_page() {
+    let app = spawn_app().await;
+    let confirmation_link = app.subscribe().await.confirmation;
+    get(confirmation_link.clone()).await;
+
+    let response = get(confirmation_link).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Already confirmed"));
+}
+
+#[actix_rt::test]
+async fn expired_links_do_not_confirm() {
+    let app = spawn_app().await;
+    let confirmation_link = app.subscribe().await.confirmation;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '4 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = get(confirmation_link).await;
+
+    assert_eq!(410, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("This link has expired"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[actix_rt::test]
+async fn subscribing_again_after_a_link_expired_sends_a_new_one() {
+    let app = spawn_app().await;
+    let expired_link = app.subscribe().await.confirmation;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '4 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(410, get(expired_link).await.status().as_u16());
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), 2);
+    let response = get(app.get_confirmation_links(&email_requests[1]).html).await;
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn unknown_links_show_an_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let response = get(format!(
+        "{}/subscriptions/confirm?subscription_token=tokenNotInDatabase",
+        app.address
+    ))
+    .await;
+
+    assert_eq!(401, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("We don't recognise this link"));
+}
+
+#[actix_rt::test]
+async fn templates_can_be_overridden_one_by_one() {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    std::fs::write(directory.join("signup.html"), "<h1>Our own form</h1>").unwrap();
+    let app =
+        spawn_app_with(|config| config.pages.template_directory = directory.display().to_string())
+            .await;
+
+    let signup = get(&app.address).await.text().await.unwrap();
+    let stylesheet = get(format!("{}/static/style.css", app.address)).await;
+
+    assert_eq!(signup, "<h1>Our own form</h1>");
+    assert_eq!(200, stylesheet.status().as_u16());
+    assert!(!stylesheet.text().await.unwrap().is_empty());
+}
@@ -0,0 +1,230 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Method;
+use uuid::Uuid;
+use zero2prod::authentication::Role;
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// Stages an import row holding the address, as uploads do.
+async fn import_row(app: &TestApp) {
+    let job_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO import_jobs (job_id, status, confirmed, created_by, created_from, created_at)
+        VALUES ($1, 'completed', false, 'admin', '127.0.0.1', now())
+        "#,
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO import_rows (job_id, line, email, name, locale, outcome, error)
+        VALUES ($1, 2, 'URSULA_LE_GUIN@gmail.com', 'Ursula', 'en', 'duplicate', 'The address is already subscribed.')
+        "#,
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query!(
+        r#"SELECT (SELECT COUNT(*) FROM subscriptions) + (SELECT COUNT(*) FROM subscription_tokens) AS "count!""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+#[actix_rt::test]
+async fn admins_can_export_everything_held_about_an_address() {
+    let app = spawn_app().await;
+    app.subscribe().await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(Method::GET, &format!("/subscribers/{}/data", EMAIL), &admin)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], EMAIL);
+    assert_eq!(data["subscription"]["status"], "pending_confirmation");
+    assert_eq!(data["lists"], serde_json::json!(["newsletter"]));
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn exports_and_erasures_cover_import_rows() {
+    let app = spawn_app().await;
+    app.subscribe().await;
+    import_row(&app).await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let data: serde_json::Value = app
+        .admin_request(Method::GET, &format!("/subscribers/{}/data", EMAIL), &admin)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(data["import_rows"][0]["name"], "Ursula");
+    assert_eq!(data["import_rows"][0]["outcome"], "duplicate");
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", EMAIL), &admin)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(204, response.status().as_u16());
+    let rows = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM import_rows"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(rows, 0);
+}
+
+#[actix_rt::test]
+async fn addresses_are_matched_regardless_of_case() {
+    let app = spawn_app().await;
+    app.subscribe().await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(
+            Method::GET,
+            "/subscribers/Ursula_Le_Guin@Gmail.com/data",
+            &admin,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], EMAIL);
+    // A second subscriber the lookup could have missed cannot exist.
+    let duplicate = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, status, subscribed_at)
+        VALUES ($1, 'URSULA_LE_GUIN@gmail.com', 'Ursula', 'confirmed', now())
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await;
+    assert!(duplicate.is_err());
+}
+
+#[actix_rt::test]
+async fn exporting_an_unknown_address_is_not_found() {
+    let app = spawn_app().await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(Method::GET, "/subscribers/nobody@example.com/data", &admin)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn editors_cannot_export_or_erase_subscribers() {
+    let app = spawn_app().await;
+    app.subscribe().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    for (method, path) in &[
+        (Method::GET, format!("/subscribers/{}/data", EMAIL)),
+        (Method::DELETE, format!("/subscribers/{}", EMAIL)),
+    ] {
+        let response = app
+            .admin_request(method.clone(), path, &editor)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(403, response.status().as_u16());
+    }
+}
+
+#[actix_rt::test]
+async fn admins_can_erase_an_address_and_its_tokens() {
+    let app = spawn_app().await;
+    app.subscribe().await;
+    let admin = app.create_user(Role::Admin).await;
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", EMAIL), &admin)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+    let event = sqlx::query!("SELECT actor FROM audit_events WHERE action = 'delete_subscriber'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.actor, admin.username);
+}
+
+#[actix_rt::test]
+async fn subscribers_can_download_their_data() {
+    let app = spawn_app().await;
+    let preferences_link = app.subscribe().await.preferences;
+    let data_link = preferences_link
+        .as_str()
+        .replace("/preferences?", "/preferences/data?");
+
+    let response = reqwest::get(data_link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], EMAIL);
+}
+
+#[actix_rt::test]
+async fn subscribers_can_erase_their_data() {
+    let app = spawn_app().await;
+    let preferences_link = app.subscribe().await.preferences;
+    let token = preferences_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/preferences/erase", app.address))
+        .form(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your data is deleted"));
+    assert_eq!(subscriber_count(&app).await, 0);
+    // The link is now as good as any unknown one.
+    let response = reqwest::get(preferences_link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
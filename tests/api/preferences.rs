@@ -0,0 +1,241 @@
+use crate::helpers::{newsletter_request_body, spawn_app, TestApp};
+use std::time::Duration;
+use zero2prod::digest::send_due_digests;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::EmailClient;
+
+fn token(preferences_link: &reqwest::Url) -> String {
+    preferences_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned())
+        .expect("No token in the preferences link.")
+}
+
+async fn post_preferences(app: &TestApp, form: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/preferences/{}", app.address, form))
+        .form(fields)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn emails_sent(app: &TestApp) -> usize {
+    app.email_server.received_requests().await.unwrap().len()
+}
+
+async fn publish(app: &TestApp) {
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_link_to_the_preference_center() {
+    let app = spawn_app().await;
+    let preferences_link = app.confirmed_subscriber().await.preferences;
+
+    let response = reqwest::get(preferences_link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"value="le guin""#));
+    assert!(body.contains("ursula_le_guin@gmail.com"));
+    assert!(body.contains(r#"name="list" value="newsletter" checked"#));
+}
+
+#[actix_rt::test]
+async fn unknown_tokens_show_an_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/preferences?token=tokenNotInDatabase",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("We don't recognise this link"));
+}
+
+#[actix_rt::test]
+async fn subscribers_can_change_their_name() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+
+    let response = post_preferences(&app, "name", &[("token", &token), ("name", "Ursula")]).await;
+
+    // Redirected back to the preferences page.
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your changes are saved."));
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula");
+}
+
+#[actix_rt::test]
+async fn invalid_names_are_rejected() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+
+    let response = post_preferences(&app, "name", &[("token", &token), ("name", " ")]).await;
+
+    assert_eq!(400, response.status().as_u16());
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+}
+
+#[actix_rt::test]
+async fn newsletters_are_not_delivered_to_subscribers_who_left_the_list() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+    let sent = emails_sent(&app).await;
+
+    post_preferences(&app, "lists", &[("token", &token)]).await;
+    publish(&app).await;
+
+    assert_eq!(emails_sent(&app).await, sent);
+}
+
+#[actix_rt::test]
+async fn newsletters_are_not_delivered_while_paused() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+    let sent = emails_sent(&app).await;
+
+    let response = post_preferences(&app, "pause", &[("token", &token), ("weeks", "4")]).await;
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Delivery is paused until"));
+    publish(&app).await;
+    assert_eq!(emails_sent(&app).await, sent);
+
+    post_preferences(&app, "pause", &[("token", &token), ("weeks", "0")]).await;
+    publish(&app).await;
+    assert_eq!(emails_sent(&app).await, sent + 1);
+}
+
+#[actix_rt::test]
+async fn weekly_subscribers_get_the_issues_of_the_week_in_a_digest() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+    let sent = emails_sent(&app).await;
+
+    let response = post_preferences(
+        &app,
+        "frequency",
+        &[("token", &token), ("frequency", "weekly")],
+    )
+    .await;
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<option value="weekly" selected>"#));
+    publish(&app).await;
+    publish(&app).await;
+    assert_eq!(emails_sent(&app).await, sent);
+
+    let email_client = EmailClient::new(
+        app.email_server.uri(),
+        SubscriberEmail::parse("test@example.com".into()).unwrap(),
+        "token".to_string().into(),
+        Duration::from_secs(1),
+    );
+    // Not due before a week has passed.
+    let digests = send_due_digests(&app.db_pool, &email_client, &app.address)
+        .await
+        .unwrap();
+    assert_eq!(digests, 0);
+    sqlx::query!("UPDATE digest_entries SET queued_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let digests = send_due_digests(&app.db_pool, &email_client, &app.address)
+        .await
+        .unwrap();
+
+    assert_eq!(digests, 1);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), sent + 1);
+    let digest: serde_json::Value = serde_json::from_slice(&email_requests[sent].body).unwrap();
+    assert_eq!(digest["Subject"], "Your weekly digest");
+    let text = digest["TextBody"].as_str().unwrap();
+    assert_eq!(text.matches("Newsletter body as plain text").count(), 2);
+    // Sent issues are not sent again.
+    let digests = send_due_digests(&app.db_pool, &email_client, &app.address)
+        .await
+        .unwrap();
+    assert_eq!(digests, 0);
+}
+
+#[actix_rt::test]
+async fn newsletters_link_to_the_preference_center() {
+    let app = spawn_app().await;
+    let preferences_link = app.confirmed_subscriber().await.preferences;
+
+    publish(&app).await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let newsletter = email_requests.last().unwrap();
+    assert_eq!(app.get_preferences_link(newsletter), preferences_link);
+}
+
+#[actix_rt::test]
+async fn email_changes_take_effect_once_the_new_address_is_confirmed() {
+    let app = spawn_app().await;
+    let token = token(&app.confirmed_subscriber().await.preferences);
+
+    let response = post_preferences(
+        &app,
+        "email",
+        &[("token", &token), ("email", "ursula@example.com")],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let change_email = email_requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&change_email.body).unwrap();
+    assert_eq!(body["To"], "ursula@example.com");
+    // The new address is not verified yet, so it must not be able to manage the subscription.
+    for part in &["TextBody", "HtmlBody"] {
+        assert!(!body[part].as_str().unwrap().contains(&token));
+    }
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+
+    let confirmation_link = app.get_confirmation_links(change_email).html;
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("ursula@example.com"));
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@example.com");
+    // The link can only be used once.
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
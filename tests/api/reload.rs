@@ -0,0 +1,84 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::reload::ReloadError;
+
+#[actix_rt::test]
+async fn reloading_rate_limits_applies_them_to_new_requests() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.reload_with(|config| config.rate_limit.per_ip.capacity = 1)
+        .expect("Failed to reload.");
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn reloading_the_email_client_sends_emails_to_the_new_server() {
+    let app = spawn_app().await;
+    let new_email_server = MockServer::start().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&new_email_server)
+        .await;
+
+    app.reload_with(|config| config.email_client.base_url = new_email_server.uri())
+        .expect("Failed to reload.");
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn reloading_settings_read_only_at_startup_is_rejected_as_a_whole() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let outcome = app.reload_with(|config| {
+        config.database.database_name = "another".into();
+        config.rate_limit.per_ip.capacity = 1;
+    });
+
+    assert!(
+        matches!(outcome, Err(ReloadError::RestartRequired(ref sections)) if sections == &["database"])
+    );
+    // The rate limit change was not applied either.
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=ursula_le_guin_{}%40gmail.com", i);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+}
+
+#[actix_rt::test]
+async fn reloading_an_invalid_configuration_is_rejected() {
+    let app = spawn_app().await;
+
+    let outcome = app.reload_with(|config| config.email_client.timeout_milliseconds = 0);
+
+    assert!(matches!(outcome, Err(ReloadError::InvalidConfiguration(_))));
+}
@@ -0,0 +1,109 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const WIDGET_ORIGIN: &str = "https://blog.example.com";
+
+async fn spawn_app_allowing_widget() -> TestApp {
+    spawn_app_with(|config| config.security.cors_allowed_origins = vec![WIDGET_ORIGIN.into()]).await
+}
+
+fn preflight(app: &TestApp, origin: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscriptions", app.address),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+}
+
+#[actix_rt::test]
+async fn responses_carry_security_headers() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    let headers = response.headers();
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    assert_eq!(
+        headers["Content-Security-Policy"],
+        "default-src 'self'; frame-ancestors 'none'"
+    );
+    assert_eq!(headers["X-Frame-Options"], "DENY");
+    assert_eq!(
+        headers["Referrer-Policy"],
+        "strict-origin-when-cross-origin"
+    );
+}
+
+#[actix_rt::test]
+async fn empty_security_headers_are_left_out() {
+    let app = spawn_app_with(|config| config.security.frame_options = "".into()).await;
+
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(response.headers().get("X-Frame-Options").is_none());
+}
+
+#[actix_rt::test]
+async fn preflights_from_allowed_origins_are_accepted() {
+    let app = spawn_app_allowing_widget().await;
+
+    let response = preflight(&app, WIDGET_ORIGIN)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(204, response.status().as_u16());
+    let headers = response.headers();
+    assert_eq!(headers["Access-Control-Allow-Origin"], WIDGET_ORIGIN);
+    assert_eq!(headers["Access-Control-Allow-Methods"], "POST");
+    assert_eq!(headers["Access-Control-Allow-Headers"], "Content-Type");
+}
+
+#[actix_rt::test]
+async fn preflights_from_other_origins_are_rejected() {
+    let app = spawn_app_allowing_widget().await;
+
+    let response = preflight(&app, "https://evil.example.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, response.status().as_u16());
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+}
+
+#[actix_rt::test]
+async fn allowed_origins_can_read_the_subscription_response() {
+    let app = spawn_app_allowing_widget().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Origin", WIDGET_ORIGIN)
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        WIDGET_ORIGIN
+    );
+}
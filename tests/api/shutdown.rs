@@ -0,0 +1,50 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use std::time::Duration;
+
+/// Subscribes in the background, so that the test can shut down while the request is running.
+async fn subscribe(address: String) -> reqwest::Result<reqwest::Response> {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+}
+
+#[actix_rt::test]
+async fn in_flight_requests_complete_during_shutdown() {
+    let app = spawn_app().await;
+    app.mount_email_server(Duration::from_millis(500)).await;
+
+    let request = tokio::spawn(subscribe(app.address.clone()));
+    actix_rt::time::sleep(Duration::from_millis(100)).await;
+    app.shutdown.shutdown().await;
+
+    let response = request.await.unwrap().expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn connections_are_refused_after_shutdown() {
+    let app = spawn_app().await;
+
+    app.shutdown.shutdown().await;
+    let outcome = reqwest::Client::new()
+        .get(format!("{}/health/live", app.address))
+        .send()
+        .await;
+
+    assert!(outcome.is_err());
+}
+
+#[actix_rt::test]
+async fn requests_still_running_at_the_deadline_are_cancelled() {
+    let app = spawn_app_with(|config| config.shutdown.timeout_seconds = 1).await;
+    app.mount_email_server(Duration::from_secs(10)).await;
+
+    let request = tokio::spawn(subscribe(app.address.clone()));
+    actix_rt::time::sleep(Duration::from_millis(100)).await;
+    app.shutdown.shutdown().await;
+
+    assert!(request.await.unwrap().is_err());
+}
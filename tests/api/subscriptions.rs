@@ -1,9 +1,10 @@
 use wiremock::{
-    matchers::{method, path},
+    matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::configuration::{ChallengeProvider, RateLimitStoreKind};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[actix_rt::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -121,6 +122,27 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[actix_rt::test]
+async fn subscribing_a_confirmed_address_again_sends_no_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
 #[actix_rt::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Arrange
@@ -138,3 +160,187 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[actix_rt::test]
+async fn subscribe_returns_a_429_when_the_ip_rate_limit_is_exceeded() {
+    let app = spawn_app_with(|config| {
+        config.rate_limit.per_ip.capacity = 2;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=ursula_le_guin_{}%40gmail.com", i);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[actix_rt::test]
+async fn forwarding_headers_from_untrusted_peers_do_not_reset_the_ip_rate_limit() {
+    let app = spawn_app_with(|config| {
+        config.rate_limit.per_ip.capacity = 1;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut statuses = Vec::new();
+    for i in 0..2 {
+        let response = reqwest::Client::new()
+            .post(&format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("203.0.113.{}", i))
+            .body(format!(
+                "name=le%20guin&email=ursula_le_guin_{}%40gmail.com",
+                i
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        statuses.push(response.status().as_u16());
+    }
+
+    assert_eq!(statuses, vec![200, 429]);
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_429_when_the_email_rate_limit_is_exceeded() {
+    let app = spawn_app_with(|config| {
+        config.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    // Addresses are compared case-insensitively.
+    let response = app
+        .post_subscriptions("name=le%20guin&email=URSULA_LE_GUIN%40gmail.com".into())
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn rate_limits_can_be_shared_through_postgres() {
+    let app = spawn_app_with(|config| {
+        config.rate_limit.store = RateLimitStoreKind::Postgres;
+        config.rate_limit.per_ip.capacity = 1;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+    let saved = sqlx::query!("SELECT key FROM rate_limit_buckets WHERE key = 'ip:127.0.0.1'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch rate limit bucket.");
+    assert!(saved.is_some());
+}
+
+#[actix_rt::test]
+async fn subscribe_silently_drops_submissions_with_the_honeypot_filled_in() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam.example.com";
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert!(saved.is_none());
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_403_when_the_challenge_is_failed() {
+    let app = spawn_app_with(|config| {
+        config.challenge.provider = ChallengeProvider::Stub;
+        config.challenge.stub_response = Some("i-am-human".into());
+    })
+    .await;
+    let test_cases = vec![
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+            "missing challenge response",
+        ),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&challenge_response=beep",
+            "incorrect challenge response",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        let response = app.post_subscriptions(body.into()).await;
+
+        assert_eq!(
+            403,
+            response.status().as_u16(),
+            "The API did not fail with 403 Forbidden when the payload had a {}.",
+            error_message
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn subscribe_accepts_a_correct_challenge_response() {
+    let app = spawn_app_with(|config| {
+        config.challenge.provider = ChallengeProvider::Stub;
+        config.challenge.stub_response = Some("i-am-human".into());
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&challenge_response=i-am-human";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
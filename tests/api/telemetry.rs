@@ -0,0 +1,92 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use reqwest::Method;
+use zero2prod::authentication::Role;
+
+async fn put_log_filter(app: &TestApp, user: &TestUser, directives: &str) -> reqwest::Response {
+    app.admin_request(Method::PUT, "/telemetry/log-filter", user)
+        .json(&serde_json::json!({ "directives": directives }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_log_filter(app: &TestApp) -> serde_json::Value {
+    let response = app
+        .admin_request(Method::GET, "/telemetry/log-filter", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+#[actix_rt::test]
+async fn admins_can_change_the_log_filter_at_runtime() {
+    let app = spawn_app().await;
+    // The subscriber is shared by every test, so put the original filter back afterwards.
+    let original = get_log_filter(&app).await["directives"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let response = put_log_filter(&app, &app.test_user, "info,zero2prod::routes=trace").await;
+    assert_eq!(200, response.status().as_u16());
+    let current = get_log_filter(&app).await;
+    put_log_filter(&app, &app.test_user, &original).await;
+
+    assert!(current["directives"]
+        .as_str()
+        .unwrap()
+        .contains("zero2prod::routes=trace"));
+}
+
+#[actix_rt::test]
+async fn changing_the_log_filter_is_audited() {
+    let app = spawn_app().await;
+    let original = get_log_filter(&app).await["directives"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    put_log_filter(&app, &app.test_user, &original).await;
+
+    let saved =
+        sqlx::query!("SELECT actor, target FROM audit_events WHERE action = 'change_log_filter'")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch audit event.");
+    assert_eq!(saved.actor, app.test_user.username);
+    assert_eq!(saved.target.as_deref(), Some(original.as_str()));
+}
+
+#[actix_rt::test]
+async fn invalid_log_filters_are_rejected() {
+    let app = spawn_app().await;
+
+    for directives in &["", "zero2prod=loud"] {
+        let response = put_log_filter(&app, &app.test_user, directives).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject the log filter `{}`.",
+            directives
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn only_admins_can_manage_the_log_filter() {
+    let app = spawn_app().await;
+    let editor = app.create_user(Role::Editor).await;
+
+    let get = app
+        .admin_request(Method::GET, "/telemetry/log-filter", &editor)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let put = put_log_filter(&app, &editor, "trace").await;
+
+    assert_eq!(403, get.status().as_u16());
+    assert_eq!(403, put.status().as_u16());
+}
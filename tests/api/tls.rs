@@ -0,0 +1,104 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use std::path::Path;
+
+/// Writes a self-signed certificate for `localhost` and its key, returning the PEM certificate.
+fn self_signed_certificate(directory: &Path) -> String {
+    let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let pem = certificate.serialize_pem().unwrap();
+    std::fs::write(directory.join("cert.pem"), &pem).unwrap();
+    std::fs::write(
+        directory.join("key.pem"),
+        certificate.serialize_private_key_pem(),
+    )
+    .unwrap();
+    pem
+}
+
+/// Spawns an application serving HTTPS, and a client trusting its certificate.
+async fn spawn_https_app(redirect_http: bool) -> (TestApp, reqwest::Client) {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    let pem = self_signed_certificate(&directory);
+    let app = spawn_app_with(|config| {
+        let tls = &mut config.application.tls;
+        tls.enabled = true;
+        tls.certificate_path = directory.join("cert.pem").display().to_string();
+        tls.private_key_path = directory.join("key.pem").display().to_string();
+        tls.redirect_http = redirect_http;
+        tls.redirect_port = 0;
+    })
+    .await;
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(pem.as_bytes()).unwrap())
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    (app, client)
+}
+
+#[actix_rt::test]
+async fn https_responses_carry_an_hsts_header() {
+    let (app, client) = spawn_https_app(false).await;
+
+    let response = client
+        .get(format!("https://localhost:{}/health/live", app.port))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Strict-Transport-Security"],
+        "max-age=31536000"
+    );
+}
+
+#[actix_rt::test]
+async fn plain_http_is_not_served_on_the_https_port() {
+    let (app, client) = spawn_https_app(false).await;
+
+    let outcome = client
+        .get(format!("http://localhost:{}/health/live", app.port))
+        .send()
+        .await;
+
+    assert!(outcome.map_or(true, |response| !response.status().is_success()));
+    assert!(app.https_redirect_address.is_none());
+}
+
+#[actix_rt::test]
+async fn plain_http_is_redirected_to_https() {
+    let (app, client) = spawn_https_app(true).await;
+    let redirect_address = app.https_redirect_address.as_ref().unwrap();
+
+    let response = client
+        .post(format!("{}/subscriptions?source=widget", redirect_address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(308, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Location"],
+        format!("https://127.0.0.1:{}/subscriptions?source=widget", app.port).as_str()
+    );
+}
+
+#[actix_rt::test]
+async fn redirects_ignore_the_host_header() {
+    let (app, client) = spawn_https_app(true).await;
+    let redirect_address = app.https_redirect_address.as_ref().unwrap();
+
+    let response = client
+        .get(format!("{}/", redirect_address))
+        .header("Host", "evil.example.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(308, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Location"],
+        format!("https://127.0.0.1:{}/", app.port).as_str()
+    );
+}
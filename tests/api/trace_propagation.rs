@@ -0,0 +1,55 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+async fn outgoing_traceparent(incoming: Option<String>) -> String {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    if let Some(traceparent) = incoming {
+        request = request.header("traceparent", traceparent);
+    }
+    let response = request.send().await.expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    email_request
+        .headers
+        .get(&"traceparent".into())
+        .expect("The email request has no traceparent header.")
+        .as_str()
+        .to_owned()
+}
+
+#[actix_rt::test]
+async fn the_incoming_trace_context_is_propagated_to_the_email_provider() {
+    let traceparent =
+        outgoing_traceparent(Some(format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID))).await;
+
+    let parts: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(parts[1], TRACE_ID);
+    // The email request is sent from a span of ours, not from the caller's.
+    assert_ne!(parts[2], PARENT_SPAN_ID);
+}
+
+#[actix_rt::test]
+async fn a_new_trace_is_started_without_an_incoming_trace_context() {
+    let traceparent = outgoing_traceparent(None).await;
+
+    let parts: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(parts.len(), 4);
+    assert_ne!(parts[1], TRACE_ID);
+    assert_ne!(parts[1], "0".repeat(32));
+}
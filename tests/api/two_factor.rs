@@ -0,0 +1,205 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use zero2prod::authentication::TotpSecret;
+
+/// Enrols the admin test user and returns its TOTP secret and recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    let enrolment: serde_json::Value = app
+        .admin_request(Method::POST, "/two-factor", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    let secret = TotpSecret::from_base32(enrolment["secret"].as_str().unwrap()).unwrap();
+
+    let response = app
+        .admin_request(Method::POST, "/two-factor/confirm", &app.test_user)
+        .json(&serde_json::json!({ "code": secret.code_at(Utc::now()) }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+    (secret, recovery_codes)
+}
+
+async fn get_report(app: &TestApp, two_factor_code: Option<&str>) -> reqwest::Response {
+    let mut request = app.admin_request(Method::GET, "/reports/subscriptions", &app.test_user);
+    if let Some(code) = two_factor_code {
+        request = request.header("X-Two-Factor-Code", code);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+/// The confirmation consumed the code of the current time step. The next one is still within
+/// the allowed clock skew and has not been used yet.
+fn next_code(secret: &TotpSecret) -> String {
+    secret.code_at(Utc::now() + Duration::seconds(30))
+}
+
+#[actix_rt::test]
+async fn enrolment_returns_a_provisioning_uri() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::POST, "/two-factor", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(201, response.status().as_u16());
+    let enrolment: serde_json::Value = response.json().await.unwrap();
+    let uri = enrolment["provisioning_uri"].as_str().unwrap();
+    assert!(uri.starts_with(&format!(
+        "otpauth://totp/zero2prod:{}?",
+        app.test_user.username
+    )));
+    assert!(uri.contains(&format!("secret={}", enrolment["secret"].as_str().unwrap())));
+}
+
+#[actix_rt::test]
+async fn the_secret_is_stored_encrypted() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    let stored = sqlx::query!("SELECT encrypted_secret FROM user_two_factor")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(!stored.encrypted_secret.contains(&secret.to_base32()));
+}
+
+#[actix_rt::test]
+async fn a_pending_enrolment_is_not_enforced() {
+    let app = spawn_app().await;
+    app.admin_request(Method::POST, "/two-factor", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let response = get_report(&app, None).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn enrolment_is_not_confirmed_with_an_invalid_code() {
+    let app = spawn_app().await;
+    app.admin_request(Method::POST, "/two-factor", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let response = app
+        .admin_request(Method::POST, "/two-factor/confirm", &app.test_user)
+        .json(&serde_json::json!({ "code": "not-a-code" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(200, get_report(&app, None).await.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn the_second_factor_is_required_once_enabled() {
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+
+    let response = get_report(&app, None).await;
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn an_invalid_second_factor_is_rejected() {
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+
+    let response = get_report(&app, Some("000000x")).await;
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn a_valid_totp_code_is_accepted_only_once() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    let code = next_code(&secret);
+
+    assert_eq!(200, get_report(&app, Some(&code)).await.status().as_u16());
+    assert_eq!(401, get_report(&app, Some(&code)).await.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn recovery_codes_are_single_use() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let code = &recovery_codes[0];
+    assert_eq!(200, get_report(&app, Some(code)).await.status().as_u16());
+    assert_eq!(401, get_report(&app, Some(code)).await.status().as_u16());
+    let other_code = &recovery_codes[1];
+    assert_eq!(
+        200,
+        get_report(&app, Some(other_code)).await.status().as_u16()
+    );
+}
+
+#[actix_rt::test]
+async fn api_tokens_do_not_need_a_second_factor() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    let token: serde_json::Value = app
+        .admin_request(Method::POST, "/tokens", &app.test_user)
+        .header("X-Two-Factor-Code", next_code(&secret))
+        .json(&serde_json::json!({ "name": "reporting", "scopes": ["view_reports"] }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/reports/subscriptions", &app.address))
+        .bearer_auth(token["token"].as_str().unwrap())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn disabling_two_factor_requires_the_second_factor() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    let response = app
+        .admin_request(Method::DELETE, "/two-factor", &app.test_user)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(401, response.status().as_u16());
+
+    let response = app
+        .admin_request(Method::DELETE, "/two-factor", &app.test_user)
+        .header("X-Two-Factor-Code", next_code(&secret))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(200, get_report(&app, None).await.status().as_u16());
+}